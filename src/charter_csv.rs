@@ -1,19 +1,32 @@
 use crate::charter_graphs::{draw_bar_graph, draw_flame_graph, draw_histogram, draw_line_chart, draw_pie_chart, draw_scatter_plot};
 use crate::charter_utilities::{check_for_screenshot, cir_parser, custom_divider, grid2csv, grid_search, render_db_stats, save_window_as_png, CsvGrid, DraggableLabel, GridLayout, SearchResult};
-use crate::cir_adapters::sqlite_cir_adapter;
-use crate::csvqb::{csvqb_to_cir, CIR};
+use crate::csvqb::{QueryOutput, CIR};
+use crate::csvqb::columns::ColumnCache;
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::explain::{explain_pipeline, Plan, PlanNode};
+use crate::csvqb::join::qualified_column;
+use crate::csvqb::named::{check_with_named, pipeline_links, run_order};
+use crate::csvqb::nulls::{parse_tokens, NullTokens};
+use crate::csvqb::order::compare_cells;
+use crate::csvqb::sql::csvqb_to_sql;
+use crate::csvqb::macros::{expand_macros, Macro};
+use crate::csvqb::lexer::{join_keys_pending, pipeline_tokens, quote_word, set_chart_type, tokenize, Span};
 use crate::db_manager::{DatabaseConfig, DatabaseSource, DatabaseType, DbManager, };
-use crate::session::{load_session_files_from_db, load_sessions_from_db, reconstruct_session, retrieve_session_list, save_session_to_database, update_current_session, Session};
+use crate::pipeline_params::{placeholders, prepare_pipeline, sql_params, Param, ParamKind, Params};
+use crate::query_executor::{PipelineKey, QueryExecutor, QueryJob, QueryOutcome, QueryState};
+use crate::session::{delete_macro_from_db, load_macros_from_db, load_session_files_from_db, save_macro_to_db, load_sessions_from_db, reconstruct_session, retrieve_session_list, save_session_to_database, migrate_sessions_table, Session};
 use eframe::App;
 use egui::{Align, Button, CentralPanel, Color32, Context, FontId, Frame, IconData, Id, Image, LayerId, Margin, Order, RichText, ScrollArea, Sense, Shape, Stroke, TextEdit, TextureHandle, Ui, Vec2, Window};
 use image::ImageReader;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::BufRead;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 pub use std::thread;
 use std::time::Instant;
+use std::sync::Arc;
 use rayon::prelude::*;
 use crate::components::optimized_load_csv_button::CsvLoaderButton;
 use egui::epaint::TextShape;
@@ -22,13 +35,42 @@ use egui::epaint::TextShape;
 pub struct CharterCsvApp {
     db_manager: Option<DbManager>,
     db_config: DatabaseConfig,
+    /// Database whose tables were last brought up to date, they are again when
+    /// the settings point at another one.
+    opened_db: Option<PathBuf>,
     texture: Option<TextureHandle>,
     screen: Screen,
-    csv_files: Vec<(String, CsvGrid)>,
+    csv_files: Vec<(String, Arc<CsvGrid>)>,
     grid_layout: Option<GridLayout>,
     csvqb_pipelines: Vec<Vec<(usize, Vec<String>)>>,
     multi_pipeline_tracker: HashMap<usize, Vec<usize>>,
     graph_data: Vec<Vec<CIR>>,
+    pipeline_errors: HashMap<(usize, usize), CsvqbError>,
+    pipeline_notes: HashMap<(usize, usize), String>,
+    /// Show the plan of every pipeline under it in the Data Explorer.
+    explain: bool,
+    /// What is shown under every csvqb pipeline, kept until the pipeline or what it reads changes.
+    pipeline_checks: HashMap<PipelineKey, PipelineCheck>,
+    /// Show the SQLite translation of every pipeline under it in the Data Explorer.
+    show_sql: bool,
+    /// Pipelines whose last run was their SQLite translation, their errors aren't csvqb errors.
+    sqlite_runs: HashSet<PipelineKey>,
+    /// Values of the `$name` placeholders, shared by every pipeline of the session.
+    params: Params,
+    /// Distinct values offered for placeholders picked from a column, with the
+    /// column, files and column cache version they were read for.
+    param_choices: HashMap<String, (String, Vec<String>)>,
+    /// Macros of the database, expanded in csvqb pipelines that call them.
+    macros: Vec<Macro>,
+    /// Name, parameters and body of the macro being written under Macros.
+    macro_form: (String, String, String),
+    macro_error: Option<String>,
+    /// Cells each file uses for a missing value, read as null by csvqb.
+    null_tokens: NullTokens,
+    /// Null tokens of a file as they are being typed, by file path.
+    null_token_text: HashMap<String, String>,
+    column_cache: ColumnCache,
+    query_executor: QueryExecutor,
     file_receiver: Receiver<(String, Vec<Vec<String>>)>,
     file_sender: Sender<(String, Vec<Vec<String>>)>,
     chart_style_prototype: String,
@@ -49,6 +91,18 @@ pub struct CharterCsvApp {
     divider_position: f32
 }
 
+/// A csvqb pipeline as the Data Explorer shows it, worked out for the pipeline,
+/// macros, parameters, files and named results in `signature`. EXPLAIN and SQL
+/// are only worked out once they are shown.
+struct PipelineCheck {
+    signature: String,
+    /// The pipeline with its macros expanded and placeholders filled in.
+    prepared: Vec<String>,
+    error: Option<CsvqbError>,
+    plan: Option<Result<Plan, CsvqbError>>,
+    sql: Option<Result<String, CsvqbError>>,
+}
+
 pub enum Screen {
     Main,
     ViewCsv,
@@ -65,7 +119,18 @@ pub struct PlotPoint {
     pub(crate) value: f64,
     pub(crate) x: f64,
     pub(crate) y: f64,
-    pub(crate) depth: f32
+    pub(crate) depth: f32,
+    /// Series the point belongs to when the data has several, e.g. the columns of a pivot.
+    pub(crate) series: Option<String>,
+    /// Seconds since 1970-01-01 when the point's x label is a date, the line
+    /// chart then spaces the points by time.
+    pub(crate) time: Option<i64>,
+    /// Lower and upper edge of the bin when the point counts the values of a
+    /// `BIN`, the histogram then draws the bins side by side.
+    pub(crate) bin: Option<(f64, f64)>,
+    /// Slope and intercept of the line fitted through the point's series, the
+    /// scatter plot draws it over the points.
+    pub(crate) trend: Option<(f64, f64)>,
 }
 
 impl Default for CharterCsvApp {
@@ -74,6 +139,7 @@ impl Default for CharterCsvApp {
         let app = Self {
             db_manager: None,
             db_config: Default::default(),
+            opened_db: None,
             texture: None,
             screen: Screen::Main,
             csv_files: vec![],
@@ -81,6 +147,21 @@ impl Default for CharterCsvApp {
             csvqb_pipelines: vec![],
             multi_pipeline_tracker: HashMap::new(),
             graph_data: vec![],
+            pipeline_errors: HashMap::new(),
+            pipeline_notes: HashMap::new(),
+            explain: false,
+            pipeline_checks: HashMap::new(),
+            show_sql: false,
+            sqlite_runs: HashSet::new(),
+            params: Params::new(),
+            param_choices: HashMap::new(),
+            macros: Vec::new(),
+            macro_form: Default::default(),
+            macro_error: None,
+            null_tokens: NullTokens::default(),
+            null_token_text: HashMap::new(),
+            column_cache: ColumnCache::default(),
+            query_executor: QueryExecutor::default(),
             file_receiver: rx,
             file_sender: tx,
             chart_style_prototype: "Histogram".to_string(),
@@ -131,24 +212,57 @@ impl App for CharterCsvApp {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         check_for_screenshot(ctx);
 
+        let db_path = self.db_config.database_path.get_path();
+        if self.opened_db.as_ref() != Some(&db_path) {
+            if let Ok(conn) = rusqlite::Connection::open(&db_path) {
+                if let Err(err) = migrate_sessions_table(&conn) {
+                    eprintln!("Error updating the sessions table: {}", err);
+                }
+                // Read again only when a macro is saved or deleted.
+                self.macros = load_macros_from_db(&conn).unwrap_or_default();
+            }
+            self.opened_db = Some(db_path);
+        }
+
         if let Ok((path, grid)) = self.file_receiver.try_recv() {
-            self.csv_files.push((path.clone(), grid.clone()));
+            let file = (path, Arc::new(grid));
+            self.csv_files.push(file.clone());
             if let Ok(mut conn) = rusqlite::Connection::open(self.db_config.database_path.get_path()) {
-                if let Err(err) = DbManager::import_all_csvs(&mut conn, &vec![(path, grid)]) {
+                if let Err(err) = DbManager::import_all_csvs(&mut conn, &[file]) {
                     println!("err {}", err)
                 }
 
-                update_current_session(
-                    &self.csv_files,
-                    &mut self.csvqb_pipelines,
-                    &mut self.sessions,
-                    &mut self.multi_pipeline_tracker,
-                    self.current_session,
-                    &self.query_mode,
-                    conn
-                );
+                self.save_current_session(conn);
+            }
+        }
+        self.column_cache.sync(&self.csv_files, &self.null_tokens);
+        if self.column_cache.is_inferring() {
+            ctx.request_repaint();
+        }
+
+        for (key, outcome) in self.query_executor.poll() {
+            match outcome {
+                QueryOutcome::Csvqb(Ok(output)) => {
+                    self.pipeline_errors.remove(&key);
+                    set_pipeline_note(&mut self.pipeline_notes, key, &output);
+                    if !output.results.is_empty() {
+                        self.graph_data.push(output.results);
+                    }
+                }
+                QueryOutcome::Csvqb(Err(err)) => {
+                    self.pipeline_errors.insert(key, err);
+                }
+                QueryOutcome::Sql(Ok(results)) => {
+                    self.pipeline_errors.remove(&key);
+                    self.graph_data.push(results);
+                }
+                // Shown under the pipeline from its state.
+                QueryOutcome::Sql(Err(_)) => {}
             }
         }
+        if self.query_executor.is_running() {
+            ctx.request_repaint();
+        }
 
         let screen = std::mem::replace(&mut self.screen, Screen::Main);
         match screen {
@@ -208,7 +322,7 @@ impl App for CharterCsvApp {
             if let Ok(mut conn) = rusqlite::Connection::open(self.db_config.database_path.get_path()) {
                 if let Ok(files) = load_session_files_from_db(&mut conn, &*self.sessions[self.current_session].name) {
                     for (file_path, grid) in files {
-                        self.csv_files.push((file_path, grid));
+                        self.csv_files.push((file_path, Arc::new(grid)));
                     }
                 }
 
@@ -223,30 +337,22 @@ impl App for CharterCsvApp {
             if ssi >= 0 && ssi < self.sessions.len() {
                 let query_mode = self.sessions[ssi].query_mode.clone();
                 self.query_mode = query_mode;
+                self.params = self.sessions[ssi].params.clone();
+                self.null_tokens = self.sessions[ssi].null_tokens.clone();
+                self.null_token_text.clear();
             }
             let selected_files: Vec<usize> = self.multi_pipeline_tracker.keys().copied().sorted().collect();
-
-            // we run session queries here to set graph data
-            for (_index, pipelines) in self.csvqb_pipelines.iter().enumerate() {
-                for (i, _) in pipelines.iter().enumerate() {
-
-                    if self.db_config.db_type.is(DatabaseType::SQLite) {
-                        let combined_query = self.csvqb_pipelines[_index][i].1.join(" ");
-                        let conn_path = self.db_config.database_path.get_path();
-                        let _ = sqlite_cir_adapter(combined_query, conn_path, self.graph_data.as_mut());
-
-                    } else if self.db_config.db_type.is(DatabaseType::CsvQB) {
-                        let result = csvqb_to_cir(
-                            &*self.csvqb_pipelines[_index][i].1,
-                            &selected_files,
-                            &self.csv_files,
-                        );
-                        if !result.is_empty() {
-                            self.graph_data.push(result);
-                        }
-                    }
-                }
-            }
+            self.column_cache.sync(&self.csv_files, &self.null_tokens);
+
+            // we run session queries here to set graph data, results arrive through the query executor
+            let keys: Vec<PipelineKey> = self.csvqb_pipelines.iter()
+                .enumerate()
+                .flat_map(|(_index, pipelines)| (0..pipelines.len()).map(move |i| (_index, i)))
+                .collect();
+            let db_type = self.db_config.db_type.clone();
+            // Results named in the session that was open before mean nothing here.
+            self.query_executor.clear_named_results();
+            self.run_pipelines(keys, &db_type, &selected_files);
 
             self.prev_session = self.current_session;
 
@@ -259,7 +365,8 @@ impl CharterCsvApp {
             .fill(Color32::from_rgb(193, 200, 208));
 
         CentralPanel::default().frame(frame).show(ctx, |ui| {
-            let texture: &mut TextureHandle = self.texture.get_or_insert_with(|| {
+            // A handle of its own, so the session buttons below can borrow the app.
+            let texture: TextureHandle = self.texture.get_or_insert_with(|| {
                 match ImageReader::open("src/sailboat.png") {
                     Ok(img) => {
                         match img.decode() {
@@ -298,7 +405,7 @@ impl CharterCsvApp {
                         )
                     }
                 }
-            });
+            }).clone();
 
             let top_margin: f32 = 25.0;
             ui.add_space(top_margin.max(0.0));
@@ -374,7 +481,9 @@ impl CharterCsvApp {
                                                     pipelines: vec![],
                                                     selected_files: vec![],
                                                     query_mode: self.query_mode.clone(),
-
+                                                    names: vec![],
+                                                    params: Params::new(),
+                                                    null_tokens: NullTokens::default(),
                                                 };
                                                 if let Err(err) = save_session_to_database( conn, vec![session]) {
                                                     println!("{}", format!("Error saving session to sql lite db: {}", err));
@@ -413,22 +522,14 @@ impl CharterCsvApp {
                             }
 
                             if ui.button("Save Current").clicked() {
-                                update_current_session(
-                                    &self.csv_files,
-                                    &mut self.csvqb_pipelines,
-                                    &mut self.sessions,
-                                    &mut self.multi_pipeline_tracker,
-                                    self.current_session,
-                                    &self.query_mode,
-                                    conn
-                                );
+                                self.save_current_session(conn);
                             }
                         });
                     } else {
                         ui.set_height(-160.0);
                         ui.add_space(20.0);
                         ui.add(
-                            Image::new(&*texture)
+                            Image::new(&texture)
                                 .max_width(200.0)
                         );
                         ui.add_space(20.0);
@@ -485,6 +586,9 @@ impl CharterCsvApp {
                                                             });
                                                             ui.label(RichText::new(format!("files: {:?}", session.file_count)).color(Color32::BLACK));
                                                             ui.label(RichText::new(format!("pipelines: {:?}", session.pipeline_count)).color(Color32::BLACK));
+                                                            if !session.names.is_empty() {
+                                                                ui.label(RichText::new(format!("named results: {}", session.names.join(", "))).color(Color32::BLACK));
+                                                            }
 
                                                             if self.current_session != index {
                                                                 ui.with_layout(egui::Layout::top_down(Align::Center), |ui| {
@@ -496,13 +600,14 @@ impl CharterCsvApp {
                                                                         self.current_session = index;
                                                                         self.multi_pipeline_tracker.clear();
                                                                         self.csv_files.clear();
+                                                                        self.column_cache.clear();
                                                                         self.csvqb_pipelines.clear();
                                                                         self.graph_data.clear();
 
                                                                         let receiver = reconstruct_session(self.sessions[index].clone());
 
                                                                         while let Ok((file_path, grid)) = receiver.recv() {
-                                                                            self.csv_files.push((file_path, grid));
+                                                                            self.csv_files.push((file_path, Arc::new(grid)));
                                                                         }
                                                                         let mut grouped_pipelines: Vec<Vec<(usize, Vec<String>)>> = Vec::new();
                                                                         let mut temp_map: HashMap<usize, Vec<(usize, Vec<String>)>> = HashMap::new();
@@ -525,7 +630,7 @@ impl CharterCsvApp {
                                                                                         temp_map
                                                                                             .entry(index)
                                                                                             .or_insert_with(Vec::new)
-                                                                                            .push((index, remainder.split(' ').map(|s| s.to_string()).collect()));
+                                                                                            .push((index, pipeline_tokens(remainder)));
                                                                                     }
                                                                                 }
                                                                             }
@@ -622,7 +727,7 @@ impl CharterCsvApp {
                                 ui.label(&file_name);
                                 ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
                                     while self.csv_files.len() <= index {
-                                        self.csv_files.push(("".to_string(), CsvGrid::new()));
+                                        self.csv_files.push(("".to_string(), Arc::new(CsvGrid::new())));
                                     }
                                     if ui.button("unload").clicked() {
                                         files_to_remove = Some(index);
@@ -637,7 +742,8 @@ impl CharterCsvApp {
                                             //thread::spawn(move || {
                                                 if let Ok((path, grid)) = DbManager::load_file_from_db(&mut conn, &*file_name) {
                                                     println!("file path: {}", path);
-                                                    self.csv_files[index] = (path, grid);
+                                                    self.csv_files[index] = (path, Arc::new(grid));
+                                                    self.column_cache.invalidate(index);
                                                 }   
 
                                             //});
@@ -649,7 +755,7 @@ impl CharterCsvApp {
                                         if ui.button("edit").clicked() {
                                             next_screen = Some(Screen::EditCsv {
                                                 index,
-                                                content: self.csv_files[index].clone(),
+                                                content: (self.csv_files[index].0.clone(), self.csv_files[index].1.as_ref().clone()),
                                             });
                                         }
                                     }
@@ -665,7 +771,8 @@ impl CharterCsvApp {
         if let Some(index) = files_to_remove {
             if index < self.csv_files.len() {
                 let file = format!("_{}_", self.csv_files[index].0.clone());
-                self.csv_files[index] = (file, CsvGrid::new());
+                self.csv_files[index] = (file, Arc::new(CsvGrid::new()));
+                self.column_cache.invalidate(index);
             }
         }
         if let Some(screen) = next_screen {
@@ -692,9 +799,14 @@ impl CharterCsvApp {
                         }
                         if ui.button("Save File").clicked() {
                             if let Some(index) = edit_index {
-                                self.csv_files[index] = content.clone();
+                                self.csv_files[index] = (content.0.clone(), Arc::new(content.1.clone()));
+                                self.column_cache.invalidate(index);
+                                self.query_executor.invalidate(&content.0);
+                                if let Some(table) = DbManager::table_name(&content.0) {
+                                    self.query_executor.invalidate(&table);
+                                }
                             } else {
-                                self.csv_files.push(content.clone());
+                                self.csv_files.push((content.0.clone(), Arc::new(content.1.clone())));
                             }
 
                             if let Some(path) = rfd::FileDialog::new().add_filter(&content.0, &["csv"]).save_file() {
@@ -838,34 +950,19 @@ impl CharterCsvApp {
                         if ui.button("Run all").clicked() {
                             self.graph_data.clear();
 
-                            let selected_files = &self.multi_pipeline_tracker.keys().copied().collect::<Vec<usize>>();
-                            for (root, indexes) in self.multi_pipeline_tracker.iter() {
-                                for (i, _) in indexes.iter().enumerate() {
-                                    match self.query_mode {
-                                        DatabaseType::CsvQB => {
-                                            let result = csvqb_to_cir(
-                                                &*self.csvqb_pipelines[*root][i].1,
-                                                selected_files,
-                                                &self.csv_files,
-                                            );
-                                            if !result.is_empty() {
-                                                self.graph_data.push(result);
-                                            }
-                                        }
-                                        DatabaseType::SQLite => {
-                                            let combined_query = self.csvqb_pipelines[*root][i].1.join(" ");
-                                            let conn_path = self.db_config.database_path.get_path();
-                                            let _ = sqlite_cir_adapter(combined_query, conn_path, self.graph_data.as_mut());
-                                        }
-                                        DatabaseType::PostgreSQL => {
-                                            println!("coming soon")
-                                        }
-                                        DatabaseType::MongoDB => {
-                                            println!("coming soon")
-                                        }
-                                    }
-                                }
-                            }
+                            let selected_files = self.multi_pipeline_tracker.keys().copied().collect::<Vec<usize>>();
+                            let keys: Vec<PipelineKey> = self.multi_pipeline_tracker.iter()
+                                .flat_map(|(root, indexes)| (0..indexes.len()).map(move |i| (*root, i)))
+                                .collect();
+                            let query_mode = self.query_mode.clone();
+                            self.run_pipelines(keys, &query_mode, &selected_files);
+                        }
+                        if self.query_executor.is_running() && ui.button("Cancel all").clicked() {
+                            self.query_executor.cancel_all();
+                        }
+                        if self.query_mode.is(DatabaseType::CsvQB) {
+                            ui.checkbox(&mut self.explain, "EXPLAIN");
+                            ui.checkbox(&mut self.show_sql, "SQL");
                         }
 
                         if ui.button("View charts").clicked() {
@@ -900,6 +997,9 @@ impl CharterCsvApp {
                 }
             }
             ui.add_space(35.0);
+            self.show_params(ui);
+            self.show_macros(ui);
+            self.show_null_tokens(ui);
             ui.horizontal(|ui| {
                 ui.style_mut().spacing.indent = 30.0;
                 ui.vertical(|ui| {
@@ -974,7 +1074,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Bar Graph".to_string(), "Bar Graph").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Bar Graph".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Bar Graph");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Bar Graph".to_string()])]);
@@ -983,7 +1083,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Histogram".to_string(), "Histogram").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Histogram".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Histogram");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Histogram".to_string()])]);
@@ -992,7 +1092,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Pie Chart".to_string(), "Pie Chart").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Pie Chart".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Pie Chart");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Pie Chart".to_string()])]);
@@ -1001,7 +1101,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Scatter Plot".to_string(), "Scatter Plot").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Scatter Plot".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Scatter Plot");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Scatter Plot".to_string()])]);
@@ -1010,7 +1110,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Line Chart".to_string(), "Line Chart").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Line Chart".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Line Chart");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Line Chart".to_string()])]);
@@ -1019,7 +1119,7 @@ impl CharterCsvApp {
                                                                 if ui.selectable_value(&mut self.chart_style_prototype, "Flame Graph".to_string(), "Flame Graph(coming soon)").clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() - 1 >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.insert(0, "Flame Graph".to_string());
+                                                                            set_chart_type(&mut pipeline.1, "Flame Graph");
                                                                         }
                                                                     } else {
                                                                         self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["Flame Graph".to_string()])]);
@@ -1051,13 +1151,118 @@ impl CharterCsvApp {
                                                         self.csvqb_pipelines[*pipeline_index].push((index, Vec::new()));
                                                     }
 
-                                                    self.csvqb_pipelines[*pipeline_index][index].1 = pipeline_str
-                                                        .split_whitespace()
-                                                        .map(String::from)
-                                                        .collect();
+                                                    self.csvqb_pipelines[*pipeline_index][index].1 = if self.query_mode.is(DatabaseType::CsvQB) {
+                                                        pipeline_tokens(&pipeline_str)
+                                                    } else {
+                                                        pipeline_str
+                                                            .split_whitespace()
+                                                            .map(String::from)
+                                                            .collect()
+                                                    };
+                                                    self.pipeline_errors.remove(&(*pipeline_index, index));
+                                                }
+
+                                                if self.query_mode.is(DatabaseType::CsvQB) {
+                                                    let key = (*pipeline_index, index);
+                                                    let selected_files = self.multi_pipeline_tracker.keys().copied().sorted().collect::<Vec<usize>>();
+                                                    let pipeline = self.csvqb_pipelines.get(*pipeline_index)
+                                                        .and_then(|pipelines| pipelines.get(index))
+                                                        .map(|pipeline| pipeline.1.as_slice())
+                                                        .unwrap_or_default();
+                                                    let signature = format!(
+                                                        "{:?} {:?} {:?} {:?} {} {} {:?}",
+                                                        pipeline, self.macros, self.params, selected_files,
+                                                        self.column_cache.version(), self.query_executor.named_version(), self.null_tokens,
+                                                    );
+                                                    if self.pipeline_checks.get(&key).is_none_or(|check| check.signature != signature) {
+                                                        // EXPLAIN and SQL show the pipeline with its macros expanded and placeholders filled in.
+                                                        let prepared = prepare_pipeline(pipeline, &self.macros, &self.params);
+                                                        let error = if pipeline.is_empty() {
+                                                            None
+                                                        } else {
+                                                            prepared.clone()
+                                                                .and_then(|pipeline| check_with_named(&pipeline, &selected_files, &self.csv_files, &self.query_executor.named_results()))
+                                                                .err()
+                                                        };
+                                                        let prepared = prepared.unwrap_or_else(|_| pipeline.to_vec());
+                                                        self.pipeline_checks.insert(key, PipelineCheck { signature, prepared, error, plan: None, sql: None });
+                                                    }
+                                                    let Some(check) = self.pipeline_checks.get_mut(&key) else { return };
+
+                                                    let error = check.error.clone().or_else(|| self.pipeline_errors.get(&key).cloned());
+                                                    if let Some(error) = error.filter(|error| error.kind != CsvqbErrorKind::Cancelled) {
+                                                        ui.label(RichText::new(error.to_string()).color(Color32::from_rgb(178, 34, 34)));
+                                                    } else if let Some(note) = self.pipeline_notes.get(&key) {
+                                                        ui.label(RichText::new(note).color(Color32::DARK_GRAY));
+                                                    }
+
+                                                    if self.explain && !check.prepared.is_empty() {
+                                                        let plan = check.plan.get_or_insert_with(|| explain_pipeline(&check.prepared, &selected_files, &self.csv_files, &self.null_tokens));
+                                                        // A pipeline that doesn't check has its error shown above instead.
+                                                        if let Ok(plan) = plan {
+                                                            show_plan(ui, &check.prepared.join(" "), plan);
+                                                        }
+                                                    }
+                                                    let mut run_in_sqlite = None;
+                                                    if self.show_sql && !check.prepared.is_empty() {
+                                                        match check.sql.get_or_insert_with(|| csvqb_to_sql(&check.prepared, &selected_files, &self.csv_files, &self.null_tokens)) {
+                                                            Ok(query) => {
+                                                                ui.add(egui::Label::new(RichText::new(query.as_str()).monospace()).selectable(true));
+                                                                if ui.button("Run in SQLite").clicked() {
+                                                                    run_in_sqlite = Some(query.clone());
+                                                                }
+                                                            }
+                                                            // A pipeline that doesn't check has its error shown above instead.
+                                                            Err(err) if err.kind == CsvqbErrorKind::Unsupported => {
+                                                                ui.label(RichText::new(format!("no SQL: {}", err)).color(Color32::DARK_GRAY));
+                                                            }
+                                                            Err(_) => {}
+                                                        }
+                                                    }
+                                                    if let Some(query) = run_in_sqlite {
+                                                        self.run_in_sqlite(key, query, &selected_files);
+                                                    }
+                                                } else if let Some(error) = self.pipeline_errors.get(&(*pipeline_index, index)) {
+                                                    ui.label(RichText::new(error.message.as_str()).color(Color32::from_rgb(178, 34, 34)));
+                                                }
+
+                                                match self.query_executor.state((*pipeline_index, index)) {
+                                                    Some((QueryState::Pending, progress)) => {
+                                                        let mut cancel = false;
+                                                        ui.horizontal(|ui| {
+                                                            let text = if progress.is_started() { "running" } else { "queued" };
+                                                            ui.add(egui::ProgressBar::new(progress.fraction()).desired_width(160.0).text(text));
+                                                            cancel = ui.button("Cancel").clicked();
+                                                        });
+                                                        if cancel {
+                                                            self.query_executor.cancel((*pipeline_index, index));
+                                                        }
+                                                    }
+                                                    // csvqb failures, and files that couldn't be copied into SQLite, are shown above.
+                                                    Some((QueryState::Failed(error), _)) if (!self.query_mode.is(DatabaseType::CsvQB) || self.sqlite_runs.contains(&(*pipeline_index, index)))
+                                                        && !self.pipeline_errors.contains_key(&(*pipeline_index, index)) => {
+                                                        ui.label(RichText::new(error).color(Color32::from_rgb(178, 34, 34)));
+                                                    }
+                                                    Some((QueryState::Cancelled, _)) => {
+                                                        ui.label(RichText::new("cancelled").color(Color32::DARK_GRAY));
+                                                    }
+                                                    _ => {}
                                                 }
                                             });
 
+                                            // While a JOIN waits for its keys, offer the columns of every selected file as file.column.
+                                            let joining = self.query_mode.is(DatabaseType::CsvQB) && self.csvqb_pipelines.get(*pipeline_index)
+                                                .and_then(|pipelines| pipelines.get(index))
+                                                .is_some_and(|pipeline| join_keys_pending(&pipeline.1));
+                                            let column_buttons: Vec<String> = if joining {
+                                                csv_columns.iter()
+                                                    .flat_map(|(file_index, columns)| columns.iter()
+                                                        .map(|column| qualified_column(&self.csv_files[*file_index].0, column)))
+                                                    .collect()
+                                            } else {
+                                                fields.clone()
+                                            };
+
                                             ui.push_id(index, |ui| {
                                                 ui.label(RichText::new("csv columns".to_string()));
                                                 ui.set_min_size(Vec2::new(ui.available_width() / 3.0, 100.0));
@@ -1065,14 +1270,18 @@ impl CharterCsvApp {
                                                     .max_width(ui.available_width() - 20.0)
                                                     .show(ui, |ui| {
                                                         ui.horizontal_wrapped(|ui| {
-                                                            for field in fields.iter() {
-                                                                if ui.button(field).clicked() {
+                                                            for field in column_buttons.iter() {
+                                                                let mut button = ui.button(field);
+                                                                if let Some(column) = self.column_cache.get(_index).and_then(|table| table.column(field)) {
+                                                                    button = button.on_hover_text(format!("{}, {} null", column.kind.name(), column.nulls));
+                                                                }
+                                                                if button.clicked() {
                                                                     if self.csvqb_pipelines.len() > 0 && self.csvqb_pipelines.len() >= *pipeline_index {
                                                                         if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
-                                                                            pipeline.1.push(field.to_string());
+                                                                            pipeline.1.push(quote_word(field));
                                                                         }
                                                                     } else {
-                                                                        self.csvqb_pipelines.push(vec![(*pipeline_index, vec![field.to_string()])]);
+                                                                        self.csvqb_pipelines.push(vec![(*pipeline_index, vec![quote_word(field)])]);
                                                                     }
                                                                 }
                                                             }
@@ -1178,6 +1387,31 @@ impl CharterCsvApp {
                                                                     self.csvqb_pipelines.push(vec![(*pipeline_index, vec!["<".to_string()])]);
                                                                 }
                                                             }
+                                                            for keyword in ["CCOUNTD", "CMIN", "CMAX", "CMEDIAN", "CSTDDEV", "CVAR", "CPCT", "CRUNSUM", "CMOVAVG", "CLAG", "CLEAD", "CRANK", "ADD", "SUB", "DIV", "MOD", "POW", "AS", "PIVOT", "BIN", "CCORR", "CSPEARMAN", "CCOV", "CFIT", "!=", ">=", "<=", "WHERE", "CONTAINS", "STARTSWITH", "ENDSWITH", "MATCHES", "BETWEEN", "IS", "AND", "OR", "NOT", "JOIN", "LEFTJOIN", "FULLJOIN", "SORT", "ASC", "DESC", "LIMIT", "TOP", "BY", "DATEFMT", "BUCKET", "DISTINCT", "DEDUP", "FILL", "TRIM", "LOWER", "UPPER", "SUBSTR", "SPLIT", "EXTRACT", "REPLACE", "=>"] {
+                                                                if ui.button(keyword).clicked() {
+                                                                    if *pipeline_index < self.csvqb_pipelines.len() {
+                                                                        if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
+                                                                            pipeline.1.push(keyword.to_string());
+                                                                        }
+                                                                    } else {
+                                                                        self.csvqb_pipelines.push(vec![(*pipeline_index, vec![keyword.to_string()])]);
+                                                                    }
+                                                                }
+                                                            }
+                                                            for csvqb_macro in &self.macros {
+                                                                let button = ui.button(format!("@{}", csvqb_macro.name))
+                                                                    .on_hover_text(format!("{}\n{}", csvqb_macro.signature(), csvqb_macro.body));
+                                                                if button.clicked() {
+                                                                    let call = format!("@{}", csvqb_macro.name);
+                                                                    if *pipeline_index < self.csvqb_pipelines.len() {
+                                                                        if let Some(pipeline) = self.csvqb_pipelines[*pipeline_index].get_mut(index) {
+                                                                            pipeline.1.push(call);
+                                                                        }
+                                                                    } else {
+                                                                        self.csvqb_pipelines.push(vec![(*pipeline_index, vec![call])]);
+                                                                    }
+                                                                }
+                                                            }
                                                         });
                                                     });
                                             });
@@ -1235,6 +1469,7 @@ impl CharterCsvApp {
 
         let mut indices_to_remove: Vec<usize> = Vec::new();
         let mut labels_to_remove: Vec<usize> = Vec::new();
+        let mut open_in_editor: Option<CsvGrid> = None;
 
         CentralPanel::default().frame(frame).show(ctx, |ui| {
             Frame::NONE
@@ -1406,6 +1641,16 @@ impl CharterCsvApp {
                                     if ui.button("Save as .png").clicked() {
                                         self.time_to_hide_state = Some(Instant::now());
                                     }
+                                    // Result grids, e.g. a pivot table, can be opened as a new csv.
+                                    let result_grid = graph_query.iter().find_map(|cir| match cir {
+                                        CIR::Pivot(grid) | CIR::QueryResult(grid) | CIR::Histogram(grid) | CIR::Relation { stats: grid, .. } => Some(grid),
+                                        _ => None,
+                                    });
+                                    if let Some(grid) = result_grid {
+                                        if ui.button("Open in editor").clicked() {
+                                            open_in_editor = Some(grid.clone());
+                                        }
+                                    }
                                     ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
                                         if ui.button("❌").clicked() {
                                             indices_to_remove.push(index);
@@ -1454,6 +1699,11 @@ impl CharterCsvApp {
         for &index in labels_to_remove.iter().rev() {
             self.labels.remove(index);
         }
+        if let Some(grid) = open_in_editor {
+            self.screen = Screen::CreateCsv {
+                content: ("/todo/set path".to_string(), grid),
+            };
+        }
     }
 
     fn settings_screen(&mut self, ctx: &Context) {
@@ -1551,6 +1801,9 @@ impl CharterCsvApp {
                                            }
                                        }
 
+                                       ui.add_space(5.0);
+                                       ui.checkbox(&mut self.db_config.cache_results, "Keep query results in the database");
+
                                        if let Ok(conn) = rusqlite::Connection::open(self.db_config.database_path.get_path()) {
 
 
@@ -1582,3 +1835,377 @@ impl CharterCsvApp {
         });
     }
 }
+
+impl CharterCsvApp {
+    /// Saves the files, pipelines, selection, query mode, parameters and null
+    /// tokens of the app as the current session.
+    fn save_current_session(&self, conn: rusqlite::Connection) {
+        let Some(current) = self.sessions.get(self.current_session) else { return };
+        let mut pipelines: Vec<Vec<String>> = vec![];
+        let mut names: Vec<String> = vec![];
+
+        for pipeline in &self.csvqb_pipelines {
+            for (pipeline_number, query_string) in pipeline {
+                if self.query_mode.is(DatabaseType::CsvQB) {
+                    if let (Some(name), _) = pipeline_links(query_string) {
+                        names.push(name.name);
+                    }
+                }
+                pipelines.push(vec![format!("{} {}", pipeline_number, query_string.join(" "))]);
+            }
+        }
+
+        let session = Session {
+            name: current.name.to_string(),
+            files: self.csv_files.iter().map(|(path, _)| path.to_string()).collect(),
+            pipelines,
+            selected_files: self.multi_pipeline_tracker.keys().copied().sorted().collect(),
+            query_mode: self.query_mode.clone(),
+            names,
+            params: self.params.clone(),
+            null_tokens: self.null_tokens.clone(),
+        };
+
+        if let Err(err) = save_session_to_database(conn, vec![session]) {
+            println!("Error saving session to sql lite db: {}", err);
+        }
+    }
+
+    /// Hands the pipelines to the query executor, their results come back in `update`.
+    fn run_pipelines(&mut self, keys: Vec<PipelineKey>, query_mode: &DatabaseType, selected_files: &[usize]) {
+        self.sqlite_runs.clear();
+        // Pipelines run after the ones whose named results they read.
+        let keys = if query_mode.is(DatabaseType::CsvQB) {
+            let pipelines: Vec<(PipelineKey, Vec<String>)> = keys.iter()
+                .filter_map(|&(root, i)| {
+                    let pipeline = &self.csvqb_pipelines.get(root)?.get(i)?.1;
+                    Some(((root, i), prepare_pipeline(pipeline, &self.macros, &self.params).unwrap_or_else(|_| pipeline.clone())))
+                })
+                .collect();
+            let (order, errors) = run_order(&pipelines);
+            self.pipeline_errors.retain(|_, err| err.kind != CsvqbErrorKind::Dependency);
+            self.pipeline_errors.extend(errors);
+            order
+        } else {
+            keys
+        };
+
+        let mut jobs = Vec::new();
+        for (root, i) in keys {
+            let Some(pipeline) = self.csvqb_pipelines.get(root).and_then(|pipelines| pipelines.get(i)) else { continue };
+            match query_mode {
+                DatabaseType::CsvQB => match prepare_pipeline(&pipeline.1, &self.macros, &self.params) {
+                    Ok(pipeline) => jobs.push(((root, i), QueryJob::Csvqb {
+                        pipeline,
+                        file_indexes: selected_files.to_vec(),
+                    })),
+                    Err(err) => {
+                        self.pipeline_errors.insert((root, i), err);
+                    }
+                },
+                DatabaseType::SQLite => {
+                    self.pipeline_errors.remove(&(root, i));
+                    let query = pipeline.1.join(" ");
+                    jobs.push(((root, i), QueryJob::Sql {
+                        params: sql_params(&query, &self.params),
+                        query,
+                        conn_path: self.db_config.database_path.get_path(),
+                        import: Vec::new(),
+                    }))
+                }
+                DatabaseType::PostgreSQL | DatabaseType::MongoDB => {
+                    self.pipeline_errors.insert((root, i), CsvqbError::new(
+                        CsvqbErrorKind::Unsupported,
+                        format!("pipelines can't run on {} yet, switch the query mode to CsvQB or SQLite", query_mode),
+                        Span::default(),
+                    ));
+                }
+            }
+        }
+
+        let persist = self.db_config.enabled && self.db_config.cache_results;
+        self.query_executor.persist_results(persist.then(|| self.db_config.database_path.get_path()));
+        self.column_cache.sync(&self.csv_files, &self.null_tokens);
+        self.query_executor.run(jobs, &self.csv_files, self.column_cache.shared_tables(), self.column_cache.version(), &self.null_tokens);
+    }
+
+    /// An input for every `$name` placeholder of the pipelines: text, a number,
+    /// or one of the values of a column of the selected files.
+    fn show_params(&mut self, ui: &mut Ui) {
+        // Placeholders in the body of a macro are asked for where it is called.
+        let names: Vec<String> = self.csvqb_pipelines.iter()
+            .flatten()
+            .map(|(_, pipeline)| match self.query_mode {
+                DatabaseType::CsvQB => expand_macros(pipeline, &self.macros).unwrap_or_else(|_| pipeline.clone()),
+                _ => pipeline.clone(),
+            })
+            .flat_map(|pipeline| placeholders(&pipeline.join(" ")))
+            .unique()
+            .collect();
+        if names.is_empty() {
+            return;
+        }
+
+        let selected_files = self.multi_pipeline_tracker.keys().copied().sorted().collect::<Vec<usize>>();
+        let headers: Vec<String> = selected_files.iter()
+            .filter_map(|&idx| self.csv_files.get(idx))
+            .filter_map(|(_, grid)| grid.first())
+            .flatten()
+            .unique()
+            .cloned()
+            .collect();
+
+        egui::CollapsingHeader::new("Parameters").default_open(true).show(ui, |ui| {
+            for name in names {
+                let param = self.params.entry(name.clone()).or_insert_with(|| Param::guess(&name, &headers));
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(format!("${}", name)).monospace());
+                    let kind_label = |kind: &ParamKind| match kind {
+                        ParamKind::Text => "text".to_string(),
+                        ParamKind::Number => "number".to_string(),
+                        ParamKind::Values(column) => format!("values of {}", column),
+                    };
+                    egui::ComboBox::from_id_salt(("param kind", &name))
+                        .selected_text(kind_label(&param.kind))
+                        .show_ui(ui, |ui| {
+                            for kind in [ParamKind::Text, ParamKind::Number].into_iter()
+                                .chain(headers.iter().map(|header| ParamKind::Values(header.clone())))
+                            {
+                                let label = kind_label(&kind);
+                                ui.selectable_value(&mut param.kind, kind, label);
+                            }
+                        });
+
+                    match &param.kind {
+                        ParamKind::Text => {
+                            ui.text_edit_singleline(&mut param.value);
+                        }
+                        ParamKind::Number => {
+                            let mut number = param.value.trim().parse::<f64>().unwrap_or_default();
+                            if ui.add(egui::DragValue::new(&mut number).speed(1.0)).changed() || param.value.is_empty() {
+                                param.value = number.to_string();
+                            }
+                        }
+                        ParamKind::Values(column) => {
+                            let signature = format!("{} {:?} {}", column, selected_files, self.column_cache.version());
+                            if self.param_choices.get(&name).is_none_or(|(made_for, _)| *made_for != signature) {
+                                let choices = distinct_values(&self.csv_files, &selected_files, column);
+                                self.param_choices.insert(name.clone(), (signature, choices));
+                            }
+                            let choices = &self.param_choices[&name].1;
+                            egui::ComboBox::from_id_salt(("param value", &name))
+                                .selected_text(&param.value)
+                                .show_ui(ui, |ui| {
+                                    for choice in choices {
+                                        ui.selectable_value(&mut param.value, choice.clone(), choice);
+                                    }
+                                });
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// The macros of the database and a form to write new ones, saving a macro
+    /// with the name of an existing one replaces it.
+    fn show_macros(&mut self, ui: &mut Ui) {
+        egui::CollapsingHeader::new("Macros").default_open(false).show(ui, |ui| {
+            let mut deleted = None;
+            for csvqb_macro in &self.macros {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(csvqb_macro.signature()).monospace())
+                        .on_hover_text(&csvqb_macro.body);
+                    if ui.small_button("Delete").clicked() {
+                        deleted = Some(csvqb_macro.name.clone());
+                    }
+                });
+            }
+
+            let (name, params, body) = &mut self.macro_form;
+            ui.horizontal(|ui| {
+                ui.label("@");
+                ui.add(egui::TextEdit::singleline(name).hint_text("name").desired_width(100.0));
+                ui.add(egui::TextEdit::singleline(params).hint_text("$params").desired_width(120.0));
+            });
+            ui.add(egui::TextEdit::multiline(body).hint_text("stages, e.g. WHERE $col > $min").desired_rows(2).code_editor());
+            let saved = ui.button("Save").clicked();
+
+            let conn_path = self.db_config.database_path.get_path();
+            if saved {
+                let result = Macro::new(name, params, body).and_then(|csvqb_macro| {
+                    let conn = rusqlite::Connection::open(&conn_path).map_err(|err| err.to_string())?;
+                    save_macro_to_db(&conn, &csvqb_macro).map_err(|err| err.to_string())?;
+                    load_macros_from_db(&conn).map_err(|err| err.to_string())
+                });
+                match result {
+                    Ok(macros) => {
+                        self.macros = macros;
+                        self.macro_form = Default::default();
+                        self.macro_error = None;
+                    }
+                    Err(err) => self.macro_error = Some(err),
+                }
+            }
+            if let Some(name) = deleted {
+                if let Ok(conn) = rusqlite::Connection::open(&conn_path) {
+                    match delete_macro_from_db(&conn, &name).and_then(|()| load_macros_from_db(&conn)) {
+                        Ok(macros) => self.macros = macros,
+                        Err(err) => self.macro_error = Some(err.to_string()),
+                    }
+                }
+            }
+            if let Some(err) = &self.macro_error {
+                ui.label(RichText::new(err).color(Color32::from_rgb(178, 34, 34)));
+            }
+        });
+    }
+
+    /// The cells every selected file uses for a missing value, separated by
+    /// commas. Empty cells are always null.
+    fn show_null_tokens(&mut self, ui: &mut Ui) {
+        let selected_files = self.multi_pipeline_tracker.keys().copied().sorted().collect::<Vec<usize>>();
+        if selected_files.is_empty() {
+            return;
+        }
+
+        egui::CollapsingHeader::new("Null values").default_open(false).show(ui, |ui| {
+            for idx in selected_files {
+                let Some((path, _)) = self.csv_files.get(idx) else { continue };
+                let text = self.null_token_text.entry(path.clone())
+                    .or_insert_with(|| self.null_tokens.get(path).join(", "));
+                ui.horizontal(|ui| {
+                    ui.label(path.split("\\").last().unwrap_or(path));
+                    let edit = egui::TextEdit::singleline(text).hint_text("empty cells only").desired_width(200.0);
+                    if ui.add(edit).changed() {
+                        self.null_tokens.set(path, parse_tokens(text));
+                    }
+                });
+            }
+        });
+    }
+
+    /// Runs the SQLite translation of a csvqb pipeline, after copying the
+    /// selected files into their tables.
+    fn run_in_sqlite(&mut self, key: PipelineKey, query: String, selected_files: &[usize]) {
+        let conn_path = self.db_config.database_path.get_path();
+        let import = selected_files.iter().filter_map(|&idx| self.csv_files.get(idx).cloned()).collect();
+        self.sqlite_runs.clear();
+        self.sqlite_runs.insert(key);
+        let persist = self.db_config.enabled && self.db_config.cache_results;
+        self.query_executor.persist_results(persist.then(|| self.db_config.database_path.get_path()));
+        self.column_cache.sync(&self.csv_files, &self.null_tokens);
+        self.query_executor.run(
+            vec![(key, QueryJob::Sql { query, conn_path, params: Vec::new(), import })],
+            &self.csv_files,
+            self.column_cache.shared_tables(),
+            self.column_cache.version(),
+            &self.null_tokens
+        );
+    }
+}
+
+/// Most values a placeholder picked from a column offers.
+const MAX_PARAM_CHOICES: usize = 1000;
+
+/// Values of `column` in the selected files, each once and in the order
+/// csvqb sorts groups. Capped so a dropdown stays usable.
+fn distinct_values(files: &[(String, Arc<CsvGrid>)], file_indexes: &[usize], column: &str) -> Vec<String> {
+    let mut values: Vec<String> = file_indexes.iter()
+        .filter_map(|&idx| files.get(idx))
+        .filter_map(|(_, grid)| {
+            let position = grid.first()?.iter().position(|header| header == column)?;
+            Some(grid.iter().skip(1).filter_map(move |row| row.get(position)))
+        })
+        .flatten()
+        .filter(|value| !value.is_empty())
+        .unique()
+        .take(MAX_PARAM_CHOICES)
+        .cloned()
+        .collect();
+    values.sort_by(|a, b| compare_cells(a, b));
+    values
+}
+
+/// Notes the values a pipeline skipped because they aren't numeric and the
+/// null values its aggregates left out, or clears the note.
+fn set_pipeline_note(notes: &mut HashMap<(usize, usize), String>, key: (usize, usize), output: &QueryOutput) {
+    let mut parts = Vec::new();
+    if !output.skipped.is_empty() {
+        let skipped = output.skipped.iter()
+            .map(|(column, count)| format!("{} in '{}'", count, column))
+            .join(", ");
+        parts.push(format!("skipped non-numeric values: {}", skipped));
+    }
+    if !output.nulls.is_empty() {
+        let nulls = output.nulls.iter()
+            .map(|(label, nulls, rows)| format!("{} of {} rows for '{}'", nulls, rows, label))
+            .join(", ");
+        parts.push(format!("null values left out: {}", nulls));
+    }
+    if parts.is_empty() {
+        notes.remove(&key);
+    } else {
+        notes.insert(key, parts.join("; "));
+    }
+}
+
+/// The plan EXPLAIN shows under a pipeline: its tokens with the ignored ones
+/// struck through, then every stage as a tree.
+fn show_plan(ui: &mut Ui, src: &str, plan: &Plan) {
+    let ignored = plan.ignored();
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 4.0;
+        for token in tokenize(src).unwrap_or_default() {
+            let reason = ignored.iter()
+                .find(|(span, _)| span.start <= token.span.start && token.span.end <= span.end)
+                .map(|(_, reason)| reason);
+            match reason {
+                Some(reason) => {
+                    ui.label(RichText::new(&token.text).monospace().strikethrough().color(Color32::GRAY))
+                        .on_hover_text(format!("ignored: {}", reason));
+                }
+                None => {
+                    ui.label(RichText::new(&token.text).monospace().color(Color32::BLACK));
+                }
+            }
+        }
+    });
+    ui.label(RichText::new(format!("reads {} rows", plan.input_rows)).color(Color32::DARK_GRAY));
+    for node in &plan.nodes {
+        show_plan_node(ui, node);
+    }
+}
+
+fn show_plan_node(ui: &mut Ui, node: &PlanNode) {
+    let mut header = format!("{}  {}", node.kind, node.text);
+    if let Some(rows) = node.rows {
+        header += &format!("  ~{} rows", rows);
+    }
+    if let Some((position, kind)) = node.output {
+        header += &format!("  -> result {}: {}", position, kind);
+    }
+    let header = match &node.ignored {
+        Some(_) => RichText::new(header).monospace().strikethrough().color(Color32::GRAY),
+        None => RichText::new(header).monospace().color(Color32::BLACK),
+    };
+
+    if node.notes.is_empty() && node.children.is_empty() && node.ignored.is_none() {
+        ui.label(header);
+        return;
+    }
+    egui::CollapsingHeader::new(header)
+        .id_salt((node.kind, node.span.start, node.span.end))
+        .default_open(true)
+        .show(ui, |ui| {
+            if let Some(reason) = &node.ignored {
+                ui.label(RichText::new(format!("ignored: {}", reason)).color(Color32::from_rgb(178, 34, 34)));
+            }
+            for note in &node.notes {
+                ui.label(RichText::new(note).color(Color32::DARK_GRAY));
+            }
+            for child in &node.children {
+                show_plan_node(ui, child);
+            }
+        });
+}
@@ -4,6 +4,53 @@ use egui::{RichText, ScrollArea, Sense, Shape};
 use egui::epaint::{PathShape};
 use crate::charter_csv::PlotPoint;
 use crate::charter_utilities::{draw_rotated_text, TextPlacement};
+use crate::csvqb::dates::format_date;
+
+const SERIES_COLORS: [Color32; 6] = [
+    Color32::from_rgb(65, 155, 220),
+    Color32::from_rgb(230, 126, 34),
+    Color32::from_rgb(46, 160, 67),
+    Color32::from_rgb(192, 57, 43),
+    Color32::from_rgb(142, 68, 173),
+    Color32::from_rgb(120, 120, 120),
+];
+
+/// Names of the series in the data, in the order they first appear.
+fn series_names(graph_data: &[PlotPoint]) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for series in graph_data.iter().filter_map(|point| point.series.as_deref()) {
+        if !names.contains(&series) {
+            names.push(series);
+        }
+    }
+    names
+}
+
+fn series_color(names: &[&str], point: &PlotPoint, default: Color32) -> Color32 {
+    point.series.as_deref()
+        .and_then(|series| names.iter().position(|name| *name == series))
+        .map(|idx| SERIES_COLORS[idx % SERIES_COLORS.len()])
+        .unwrap_or(default)
+}
+
+/// Color key in the top right corner, only drawn when there are several series.
+fn draw_series_legend(painter: &egui::Painter, rect: Rect, names: &[&str]) {
+    for (idx, name) in names.iter().enumerate() {
+        let y = rect.min.y + 10.0 + idx as f32 * 16.0;
+        painter.rect_filled(
+            Rect::from_min_size(pos2(rect.max.x - 130.0, y), vec2(10.0, 10.0)),
+            0.0,
+            SERIES_COLORS[idx % SERIES_COLORS.len()],
+        );
+        painter.text(
+            pos2(rect.max.x - 115.0, y + 5.0),
+            Align2::LEFT_CENTER,
+            name,
+            FontId::default(),
+            Color32::BLACK,
+        );
+    }
+}
 
 pub fn draw_bar_graph(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>) -> Option<egui::Response> {
     if let Some(graph_data) = &formatted_data {
@@ -38,6 +85,7 @@ pub fn draw_bar_graph(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                     .unwrap_or(&1.0);
 
                 let label_font = FontId::proportional(16.0);
+                let series = series_names(graph_data);
 
                 painter.text(
                     pos2(rect.min.x + 20.0, rect.center().y),
@@ -87,7 +135,7 @@ pub fn draw_bar_graph(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                         pos2(x, rect.max.y - bottom_padding - height as f32),
                         vec2(bar_width, height as f32),
                     );
-                    painter.rect_filled(bar_rect, 0.0, Color32::from_rgb(65, 155, 220));
+                    painter.rect_filled(bar_rect, 0.0, series_color(&series, data, SERIES_COLORS[0]));
 
                     painter.text(
                         pos2(x + bar_width / 2.0, bar_rect.min.y - 5.0),
@@ -108,6 +156,7 @@ pub fn draw_bar_graph(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                     );
                     ui.painter_at(rect).extend(shapes);
                 }
+                draw_series_legend(&painter, rect, &series);
 
                 Some(response)
             }).inner
@@ -244,6 +293,49 @@ pub fn draw_pie_chart(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
 }
 
 
+/// Bins of a histogram as lower edge, upper edge and count. Points from `BIN`
+/// carry their bins, any other values are counted in 10 bins of equal width.
+fn histogram_bins(graph_data: &[PlotPoint]) -> Vec<(f64, f64, f64)> {
+    let binned: Vec<(f64, f64, f64)> = graph_data.iter()
+        .filter_map(|point| point.bin.map(|(from, to)| (from, to, point.value)))
+        .collect();
+    if !binned.is_empty() {
+        return binned;
+    }
+
+    let values: Vec<f64> = graph_data.iter().map(|data| data.value).collect();
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let bin_count = 10;
+    let min_value = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_value = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let bin_width = (max_value - min_value) / bin_count as f64;
+    let mut counts = vec![0.0; bin_count];
+    for &value in &values {
+        let bin_index = if bin_width > 0.0 { ((value - min_value) / bin_width).floor() as usize } else { 0 };
+        counts[bin_index.min(bin_count - 1)] += 1.0;
+    }
+    counts.into_iter()
+        .enumerate()
+        .map(|(idx, count)| (
+            min_value + idx as f64 * bin_width,
+            min_value + (idx + 1) as f64 * bin_width,
+            count,
+        ))
+        .collect()
+}
+
+/// Edge as it is labelled under the histogram, cut to 4 decimals when it is long.
+fn edge_label(edge: f64) -> String {
+    let label = edge.to_string();
+    if label.len() <= 8 {
+        return label;
+    }
+    let label = format!("{:.4}", edge);
+    label.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
 pub fn draw_histogram(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>) -> Option<egui::Response> {
     ScrollArea::horizontal()
         .auto_shrink([false; 2])
@@ -252,26 +344,13 @@ pub fn draw_histogram(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                 let left_padding = 60.0;
                 let bottom_padding = 60.0;
                 let top_padding = 20.0;
-                let bar_spacing = 2.0;
-                let values: Vec<f64> = graph_data.iter()
-                    .map(|data| data.value)
-                    .collect();
-                let max_value = values.iter()
-                    .max_by(|a, b| a.partial_cmp(b).unwrap())
-                    .unwrap_or(&1.0);
-                let bin_count = 10;
-                let min_value = values.iter().cloned().fold(f64::INFINITY, f64::min);
-                let bin_width = (max_value - min_value) / bin_count as f64;
-                let mut bins = vec![0.0; bin_count];
-                let bar_width = ui.available_width() / bin_count as f32;
-                let total_width = (bar_width + bar_spacing) * bin_count as f32;
+                let bins = histogram_bins(graph_data);
+                let bin_count = bins.len().max(1);
+                // Bins stay readable when there are many of them, the chart scrolls instead.
+                let bar_width = (ui.available_width() / bin_count as f32).max(6.0);
+                let total_width = bar_width * bin_count as f32;
                 let available_height = (ui.available_height() - bottom_padding - top_padding) as f64;
-
-                for &value in &values {
-                    let bin_index = ((value - min_value) / bin_width).floor() as usize;
-                    let bin_index = bin_index.min(bin_count - 1);
-                    bins[bin_index] += 1.0;
-                }
+                let max_count = bins.iter().map(|(_, _, count)| *count).fold(1.0, f64::max);
 
                 let content_size = vec2(
                     total_width.max(ui.available_width()) + left_padding,
@@ -284,6 +363,7 @@ pub fn draw_histogram(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                 );
 
                 let rect = response.rect;
+                let baseline = rect.max.y - bottom_padding;
 
                 painter.text(
                     pos2(rect.min.x + 15.0, rect.center().y),
@@ -297,55 +377,61 @@ pub fn draw_histogram(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
                 painter.line_segment(
                     [
                         pos2(rect.min.x + left_padding, rect.min.y + top_padding),
-                        pos2(rect.min.x + left_padding, rect.max.y - bottom_padding),
+                        pos2(rect.min.x + left_padding, baseline),
                     ],
                     stroke,
                 );
                 painter.line_segment(
                     [
-                        pos2(rect.min.x + left_padding, rect.max.y - bottom_padding),
-                        pos2(rect.max.x, rect.max.y - bottom_padding),
+                        pos2(rect.min.x + left_padding, baseline),
+                        pos2(rect.max.x, baseline),
                     ],
                     stroke,
                 );
 
-                // Draw bins
-                for (i, &frequency) in bins.iter().enumerate() {
-                    let frequency_normalized = frequency / bins.iter().cloned().fold(1.0, f64::max);
-                    let height = frequency_normalized * available_height;
-
+                // Bins touch, the outline tells them apart.
+                let outline = Stroke::new(1.0_f32, Color32::from_rgb(70, 130, 180));
+                for (i, &(_, _, count)) in bins.iter().enumerate() {
+                    let height = count / max_count * available_height;
+                    let x = rect.min.x + left_padding + i as f32 * bar_width;
                     let bar_rect = Rect::from_min_size(
-                        pos2(
-                            rect.min.x + left_padding + (i as f32 * (bar_width + bar_spacing)),
-                            rect.max.y - bottom_padding - (height as f32)
-                        ),
+                        pos2(x, baseline - height as f32),
                         vec2(bar_width, height as f32),
                     );
 
                     painter.rect_filled(bar_rect, 0.0, Color32::from_rgb(135, 206, 250));
+                    painter.rect_stroke(bar_rect, 0.0, outline, egui::StrokeKind::Inside);
+
+                    if bar_width >= 16.0 {
+                        painter.text(
+                            pos2(x + bar_width / 2.0, baseline - height as f32 - 5.0),
+                            Align2::CENTER_BOTTOM,
+                            format!("{:.0}", count),
+                            FontId::default(),
+                            Color32::BLACK,
+                        );
+                    }
+                }
 
+                // Edges are labelled where the bins meet, skipping some when they
+                // would run into each other.
+                let edges: Vec<f64> = bins.iter().map(|(from, _, _)| *from)
+                    .chain(bins.last().map(|(_, to, _)| *to))
+                    .collect();
+                let step = (48.0 / bar_width).ceil().max(1.0) as usize;
+                for (i, edge) in edges.iter().enumerate() {
+                    if i % step != 0 && i + 1 != edges.len() {
+                        continue;
+                    }
+                    let x = rect.min.x + left_padding + i as f32 * bar_width;
+                    painter.line_segment([pos2(x, baseline), pos2(x, baseline + 4.0)], stroke);
                     painter.text(
-                        pos2(
-                            rect.min.x + left_padding + (i as f32 * (bar_width + bar_spacing)) + bar_width / 2.0,
-                            rect.max.y - bottom_padding - (height as f32) - 5.0
-                        ),
-                        Align2::CENTER_BOTTOM,
-                        format!("{:.0}", frequency),
-                        FontId::default(),
+                        pos2(x, baseline + 6.0),
+                        Align2::CENTER_TOP,
+                        edge_label(*edge),
+                        FontId::proportional(12.0),
                         Color32::BLACK,
                     );
-
-                    let shapes = draw_rotated_text(
-                        &painter,
-                        rect,
-                        &graph_data[i].label,
-                        rect.min.x + left_padding + (i as f32 * (bar_width + bar_spacing)) + bar_width / 4.0,
-                        bar_width,
-                        16.0,
-                        TextPlacement::Bottom
-                    );
-                    painter.extend(shapes);
-
                 }
 
                 Some(response)
@@ -356,6 +442,26 @@ pub fn draw_histogram(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>)
 }
 
 
+/// Series, slope and intercept of a trend line, with the smallest and largest
+/// x of its points so the line only spans the points it fits.
+type TrendLine<'a> = (Option<&'a str>, (f64, f64), f64, f64);
+
+fn trend_lines(graph_data: &[PlotPoint]) -> Vec<TrendLine<'_>> {
+    let mut lines: Vec<TrendLine> = Vec::new();
+    for point in graph_data {
+        let Some(trend) = point.trend else { continue };
+        let series = point.series.as_deref();
+        match lines.iter_mut().find(|(line_series, ..)| *line_series == series) {
+            Some((_, _, x_min, x_max)) => {
+                *x_min = x_min.min(point.x);
+                *x_max = x_max.max(point.x);
+            }
+            None => lines.push((series, trend, point.x, point.x)),
+        }
+    }
+    lines
+}
+
 pub fn draw_scatter_plot(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>) -> Option<egui::Response> {
     ScrollArea::horizontal().show(ui, |ui| {
         if let Some(graph_data) = &formatted_data {
@@ -375,17 +481,30 @@ pub fn draw_scatter_plot(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint
             let rect = response.rect;
             let plot_width = rect.width() - left_padding * 2.0;
             let plot_height = available_height as f32;
+            let trends = trend_lines(graph_data);
 
+            // The ends of the trend lines are kept in view along with the points.
             let x_values: Vec<f64> = graph_data.iter().map(|data| data.x).collect();
-            let y_values: Vec<f64> = graph_data.iter().map(|data| data.y).collect();
+            let y_values: Vec<f64> = graph_data.iter().map(|data| data.y)
+                .chain(trends.iter().flat_map(|(_, (slope, intercept), x_min, x_max)| {
+                    [slope * x_min + intercept, slope * x_max + intercept]
+                }))
+                .collect();
 
             let x_min = x_values.iter().copied().fold(f64::INFINITY, f64::min);
             let x_max = x_values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
             let y_min = y_values.iter().copied().fold(f64::INFINITY, f64::min);
             let y_max = y_values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
 
-            let x_scale = plot_width / (x_max - x_min) as f32;
-            let y_scale = plot_height / (y_max - y_min) as f32;
+            // A single x or y value is drawn in the middle instead of dividing by zero.
+            let x_scale = if x_max > x_min { plot_width / (x_max - x_min) as f32 } else { 0.0 };
+            let y_scale = if y_max > y_min { plot_height / (y_max - y_min) as f32 } else { 0.0 };
+            let x_offset = if x_max > x_min { 0.0 } else { plot_width / 2.0 };
+            let y_offset = if y_max > y_min { 0.0 } else { plot_height / 2.0 };
+            let screen = |x: f64, y: f64| pos2(
+                rect.min.x + left_padding + x_offset + (x - x_min) as f32 * x_scale,
+                rect.min.y + left_padding + plot_height - y_offset - (y - y_min) as f32 * y_scale,
+            );
 
             painter.add(Shape::line_segment(
                 [
@@ -402,17 +521,31 @@ pub fn draw_scatter_plot(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint
                 Stroke::new(1.0, Color32::BLACK),
             ));
 
+            let names = series_names(graph_data);
             for point in graph_data {
-                let screen_x = rect.min.x + left_padding + (point.x - x_min) as f32 * x_scale;
-                let screen_y = rect.min.y + left_padding + plot_height - (point.y - y_min) as f32 * y_scale;
-
                 painter.add(Shape::circle_filled(
-                    pos2(screen_x, screen_y),
+                    screen(point.x, point.y),
                     4.0,
-                    Color32::from_rgb(30, 144, 255),
+                    series_color(&names, point, Color32::from_rgb(30, 144, 255)),
                 ));
             }
 
+            for (series, (slope, intercept), line_min, line_max) in &trends {
+                let color = series
+                    .and_then(|series| names.iter().position(|name| *name == series))
+                    .map(|idx| SERIES_COLORS[idx % SERIES_COLORS.len()])
+                    .unwrap_or(Color32::from_rgb(192, 57, 43));
+                painter.line_segment(
+                    [
+                        screen(*line_min, slope * line_min + intercept),
+                        screen(*line_max, slope * line_max + intercept),
+                    ],
+                    Stroke::new(2.0_f32, color),
+                );
+            }
+
+            draw_series_legend(&painter, rect, &names);
+
             Some(response)
         } else {
             None
@@ -440,7 +573,14 @@ pub fn draw_line_chart(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>
             let plot_width = rect.width() - left_padding * 2.0;
             let plot_height = available_height as f32;
 
-            let x_values: Vec<f64> = graph_data.iter().map(|data| data.x).collect();
+            // When every label is a date the points are spaced by time, otherwise by position.
+            let time_axis = !graph_data.is_empty() && graph_data.iter().all(|point| point.time.is_some());
+            let x_of = |point: &PlotPoint| match point.time {
+                Some(time) if time_axis => time as f64,
+                _ => point.x,
+            };
+
+            let x_values: Vec<f64> = graph_data.iter().map(x_of).collect();
             let y_values: Vec<f64> = graph_data.iter().map(|data| data.y).collect();
 
             let x_min = x_values.iter().copied().fold(f64::INFINITY, f64::min);
@@ -466,12 +606,43 @@ pub fn draw_line_chart(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>
                 Stroke::new(1.0, Color32::BLACK),
             ));
 
-            if graph_data.len() > 1 {
-                for pair in graph_data.windows(2) {
+            if time_axis {
+                let ticks = 5;
+                for tick in 0..=ticks {
+                    let fraction = tick as f32 / ticks as f32;
+                    let time = x_min + (x_max - x_min) * fraction as f64;
+                    let x = rect.min.x + left_padding + plot_width * fraction;
+                    let y = rect.min.y + left_padding + plot_height;
+                    painter.add(Shape::line_segment([pos2(x, y), pos2(x, y + 4.0)], Stroke::new(1.0_f32, Color32::BLACK)));
+                    let align = match tick {
+                        0 => Align2::LEFT_TOP,
+                        tick if tick == ticks => Align2::RIGHT_TOP,
+                        _ => Align2::CENTER_TOP,
+                    };
+                    painter.text(pos2(x, y + 5.0), align, format_date(time as i64), FontId::proportional(11.0), Color32::BLACK);
+                }
+            }
+
+            // One line per series, points without a series form a single line.
+            let series = series_names(graph_data);
+            let mut lines: Vec<Vec<&PlotPoint>> = vec![graph_data.iter().filter(|point| point.series.is_none()).collect()];
+            for name in &series {
+                lines.push(graph_data.iter().filter(|point| point.series.as_deref() == Some(*name)).collect());
+            }
+
+            if time_axis {
+                for line in lines.iter_mut() {
+                    line.sort_by(|a, b| x_of(a).total_cmp(&x_of(b)));
+                }
+            }
+
+            for line in lines.iter().filter(|line| line.len() > 1) {
+                let color = series_color(&series, line[0], Color32::from_rgb(30, 144, 255));
+                for pair in line.windows(2) {
                     if let [start_point, end_point] = pair {
-                        let start_screen_x = rect.min.x + left_padding + (start_point.x - x_min) as f32 * x_scale;
+                        let start_screen_x = rect.min.x + left_padding + (x_of(start_point) - x_min) as f32 * x_scale;
                         let start_screen_y = rect.min.y + left_padding + plot_height - (start_point.y - y_min) as f32 * y_scale;
-                        let end_screen_x = rect.min.x + left_padding + (end_point.x - x_min) as f32 * x_scale;
+                        let end_screen_x = rect.min.x + left_padding + (x_of(end_point) - x_min) as f32 * x_scale;
                         let end_screen_y = rect.min.y + left_padding + plot_height - (end_point.y - y_min) as f32 * y_scale;
 
                         painter.add(Shape::line_segment(
@@ -479,7 +650,7 @@ pub fn draw_line_chart(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>
                                 pos2(start_screen_x, start_screen_y),
                                 pos2(end_screen_x, end_screen_y),
                             ],
-                            Stroke::new(2.0, Color32::from_rgb(30, 144, 255)),
+                            Stroke::new(2.0_f32, color),
                         ));
                     }
                 }
@@ -487,15 +658,16 @@ pub fn draw_line_chart(ui: &mut egui::Ui, formatted_data: Option<Vec<PlotPoint>>
 
             // Draw points
             for point in graph_data {
-                let screen_x = rect.min.x + left_padding + (point.x - x_min) as f32 * x_scale;
+                let screen_x = rect.min.x + left_padding + (x_of(point) - x_min) as f32 * x_scale;
                 let screen_y = rect.min.y + left_padding + plot_height - (point.y - y_min) as f32 * y_scale;
 
                 painter.add(Shape::circle_filled(
                     pos2(screen_x, screen_y),
                     4.0,
-                    Color32::from_rgb(30, 144, 255),
+                    series_color(&series, point, Color32::from_rgb(30, 144, 255)),
                 ));
             }
+            draw_series_legend(&painter, rect, &series);
 
             Some(response)
         } else {
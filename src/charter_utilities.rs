@@ -3,6 +3,7 @@ use egui::{UserData, emath, pos2, vec2, Color32, FontId, Id, Painter, Pos2, Rect
 use egui::epaint::TextShape;
 use crate::charter_csv::PlotPoint;
 use crate::csvqb::CIR;
+use crate::csvqb::dates::parse_label;
 use rfd::FileDialog;
 use crate::session::retrieve_session_list;
 
@@ -206,6 +207,10 @@ pub fn cir_parser(graph_data: Vec<CIR>) -> Vec<PlotPoint> {
                             x: i as f64,
                             y: *num,
                             depth: 0.0,
+                            series: None,
+                            time: None,
+                            bin: None,
+                            trend: None,
                         });
                         i += 2;
                     } else {
@@ -237,6 +242,10 @@ pub fn cir_parser(graph_data: Vec<CIR>) -> Vec<PlotPoint> {
                                         x: (idx - 1) as f64,
                                         y: last_value,
                                         depth: 0.0,
+                                        series: None,
+                                        time: row.len().checked_sub(2).and_then(|idx| parse_label(&row[idx])),
+                                        bin: None,
+                                        trend: None,
                                     });
                                 }
                             }
@@ -245,6 +254,81 @@ pub fn cir_parser(graph_data: Vec<CIR>) -> Vec<PlotPoint> {
                 }
                 i += 1;
             }
+            CIR::Pivot(pivot) => {
+                if let Some((headers, rows)) = pivot.split_first() {
+                    for (idx, row) in rows.iter().enumerate() {
+                        for (col, header) in headers.iter().enumerate().skip(1) {
+                            if let Some(value) = row.get(col).and_then(|cell| cell.parse::<f64>().ok()) {
+                                plot_data.push(PlotPoint {
+                                    label: format!("{} {}", row[0], header),
+                                    value,
+                                    x: idx as f64,
+                                    y: value,
+                                    depth: 0.0,
+                                    series: Some(header.clone()),
+                                    time: parse_label(&row[0]),
+                                    bin: None,
+                                    trend: None,
+                                });
+                            }
+                        }
+                    }
+                }
+                i += 1;
+            }
+            CIR::Relation { points, stats } => {
+                // Points and result rows start with the same group columns.
+                let Some((headers, rows)) = points.split_first() else { i += 1; continue };
+                let groups = headers.len().saturating_sub(2);
+                let trends: Vec<(&[String], (f64, f64))> = match stats.first() {
+                    Some(stat_headers) if stat_headers.iter().any(|h| h == "slope") => stats.iter().skip(1)
+                        .filter_map(|row| {
+                            let slope = row.get(groups)?.parse::<f64>().ok()?;
+                            let intercept = row.get(groups + 1)?.parse::<f64>().ok()?;
+                            Some((&row[..groups], (slope, intercept)))
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for row in rows {
+                    let (Some(x), Some(y)) = (
+                        row.get(groups).and_then(|cell| cell.parse::<f64>().ok()),
+                        row.get(groups + 1).and_then(|cell| cell.parse::<f64>().ok()),
+                    ) else { continue };
+                    let key = &row[..groups];
+                    plot_data.push(PlotPoint {
+                        label: format!("{}, {}", row[groups], row[groups + 1]),
+                        value: y,
+                        x,
+                        y,
+                        depth: 0.0,
+                        series: (groups > 0).then(|| key.join("-")),
+                        time: None,
+                        bin: None,
+                        trend: trends.iter().find(|(group, _)| *group == key).map(|(_, trend)| *trend),
+                    });
+                }
+                i += 1;
+            }
+            CIR::Histogram(bins) => {
+                for (idx, row) in bins.iter().skip(1).enumerate() {
+                    let cells: Option<Vec<f64>> = row.iter().map(|cell| cell.parse::<f64>().ok()).collect();
+                    if let Some([from, to, count]) = cells.as_deref() {
+                        plot_data.push(PlotPoint {
+                            label: format!("{} - {}", row[0], row[1]),
+                            value: *count,
+                            x: idx as f64,
+                            y: *count,
+                            depth: 0.0,
+                            series: None,
+                            time: None,
+                            bin: Some((*from, *to)),
+                            trend: None,
+                        });
+                    }
+                }
+                i += 1;
+            }
             _ => {
                 i += 1;
             }
@@ -748,6 +832,9 @@ pub fn render_db_stats(ui: &mut egui::Ui, conn: &rusqlite::Connection) -> Result
                     ui.label(format!("Name: {}", session.name));
                     ui.label(format!("Files: {}", session.file_count));
                     ui.label(format!("Pipelines: {}", session.pipeline_count));
+                    if !session.names.is_empty() {
+                        ui.label(format!("Named results: {}", session.names.join(", ")));
+                    }
                     ui.label(format!("Query Mode: {}", session.query_mode));
                     ui.add_space(5.0);
                 }
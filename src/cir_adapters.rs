@@ -1,77 +1,86 @@
-use std::path::PathBuf;
+use rusqlite::types::Value;
+use rusqlite::{Connection, ToSql};
 use crate::csvqb::CIR;
 
-pub fn sqlite_cir_adapter(combined_query: String, conn_path: PathBuf, graph_data: &mut Vec<Vec<CIR>>) {
-    if let Ok(mut conn) = rusqlite::Connection::open(conn_path) {
-        let (graph_type, query) = if combined_query.starts_with("Bar Graph") {
-            ("Bar Graph", combined_query["Bar Graph".len()..].trim_start())
-        } else if combined_query.starts_with("Histogram") {
-            ("Histogram", combined_query["Histogram".len()..].trim_start())
-        } else if combined_query.starts_with("Pie Chart") {
-            ("Pie Chart", combined_query["Pie Chart".len()..].trim_start())
-        } else if combined_query.starts_with("Scatter Plot") {
-            ("Scatter Plot", combined_query["Scatter Plot".len()..].trim_start())
-        } else if combined_query.starts_with("Line Chart") {
-            ("Line Chart", combined_query["Line Chart".len()..].trim_start())
-        } else if combined_query.starts_with("Flame Graph") {
-            ("Flame Graph", combined_query["Flame Graph".len()..].trim_start())
-        } else {
-            ("", combined_query.as_str())
-        };
-
-        let mut stmt = match conn.prepare(query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                eprintln!("Error preparing SQL statement: {}", e);
-                return;
-            }
-        };
+/// The chart type a SQL query starts with, if any, and the query after it.
+pub fn split_graph_type(combined_query: &str) -> (&str, &str) {
+    if combined_query.starts_with("Bar Graph") {
+        ("Bar Graph", combined_query["Bar Graph".len()..].trim_start())
+    } else if combined_query.starts_with("Histogram") {
+        ("Histogram", combined_query["Histogram".len()..].trim_start())
+    } else if combined_query.starts_with("Pie Chart") {
+        ("Pie Chart", combined_query["Pie Chart".len()..].trim_start())
+    } else if combined_query.starts_with("Scatter Plot") {
+        ("Scatter Plot", combined_query["Scatter Plot".len()..].trim_start())
+    } else if combined_query.starts_with("Line Chart") {
+        ("Line Chart", combined_query["Line Chart".len()..].trim_start())
+    } else if combined_query.starts_with("Flame Graph") {
+        ("Flame Graph", combined_query["Flame Graph".len()..].trim_start())
+    } else {
+        ("", combined_query)
+    }
+}
 
-        let column_names: Vec<String> = stmt.column_names()
-            .iter()
-            .map(|&name| name.to_string())
-            .collect();
+/// Runs a SQL pipeline, an optional chart type followed by a query, and returns
+/// the chart type and the result rows. `params` are bound to the `$name`
+/// parameters of the query by name, every one of them needs a value.
+pub fn sqlite_cir_adapter(conn: &Connection, combined_query: &str, params: &[(String, Value)]) -> Result<Vec<CIR>, String> {
+    let (graph_type, query) = split_graph_type(combined_query);
 
-        let mut result_rows: Vec<Vec<String>> = vec![column_names];
-        let column_count = stmt.column_count();
+    let mut stmt = conn.prepare(query)
+        .map_err(|e| format!("Error preparing SQL statement: {}", e))?;
 
-        let rows = match stmt.query_map([], |row| {
-            let mut row_data = Vec::new();
-            for i in 0..column_count {
-                let value = match row.get_ref(i)? {
-                    rusqlite::types::ValueRef::Null => "NULL".to_string(),
-                    rusqlite::types::ValueRef::Integer(i) => i.to_string(),
-                    rusqlite::types::ValueRef::Real(f) => f.to_string(),
-                    rusqlite::types::ValueRef::Text(t) => String::from_utf8_lossy(t).to_string(),
-                    rusqlite::types::ValueRef::Blob(_) => "[BLOB]".to_string(),
-                };
-                row_data.push(value);
-            }
-            Ok(row_data)
-        }) {
-            Ok(rows) => rows,
-            Err(e) => {
-                eprintln!("Error executing query: {}", e);
-                return;
-            }
-        };
-
-        for row_result in rows {
-            match row_result {
-                Ok(row) => result_rows.push(row),
-                Err(e) => {
-                    eprintln!("Error reading row: {}", e);
-                    continue;
-                }
+    let params: Vec<(String, &Value)> = params.iter()
+        .map(|(name, value)| (format!("${}", name), value))
+        .collect();
+    for index in 1..=stmt.parameter_count() {
+        if let Some(name) = stmt.parameter_name(index).filter(|name| name.starts_with('$')) {
+            if !params.iter().any(|(param, _)| param == name) {
+                return Err(format!("no value for {}, set it under Parameters", name));
             }
         }
+    }
+    let mut bound: Vec<(&str, &dyn ToSql)> = Vec::new();
+    for (name, value) in &params {
+        if stmt.parameter_index(name).map_err(|e| format!("Error binding {}: {}", name, e))?.is_some() {
+            bound.push((name.as_str(), *value as &dyn ToSql));
+        }
+    }
+
+    let column_names: Vec<String> = stmt.column_names()
+        .iter()
+        .map(|&name| name.to_string())
+        .collect();
+
+    let mut result_rows: Vec<Vec<String>> = vec![column_names];
+    let column_count = stmt.column_count();
 
-        if !result_rows.is_empty() {
-            let mut results = Vec::new();
-            results.push(CIR::Field(graph_type.to_string()));
-            results.push(CIR::QueryResult(result_rows));
-            graph_data.push(results);
+    let rows = stmt.query_map(bound.as_slice(), |row| {
+        let mut row_data = Vec::new();
+        for i in 0..column_count {
+            let value = match row.get_ref(i)? {
+                rusqlite::types::ValueRef::Null => "NULL".to_string(),
+                rusqlite::types::ValueRef::Integer(i) => i.to_string(),
+                rusqlite::types::ValueRef::Real(f) => f.to_string(),
+                rusqlite::types::ValueRef::Text(t) => String::from_utf8_lossy(t).to_string(),
+                rusqlite::types::ValueRef::Blob(_) => "[BLOB]".to_string(),
+            };
+            row_data.push(value);
         }
+        Ok(row_data)
+    }).map_err(|e| format!("Error executing query: {}", e))?;
 
+    for row_result in rows {
+        match row_result {
+            Ok(row) => result_rows.push(row),
+            // An interrupted query fails on every row that is left.
+            Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == rusqlite::ErrorCode::OperationInterrupted => {
+                return Err("the query was cancelled".to_string());
+            }
+            // Dropping the row would chart a result that is silently incomplete.
+            Err(e) => return Err(format!("Error reading row {}: {}", result_rows.len(), e)),
+        }
     }
-}
\ No newline at end of file
+
+    Ok(vec![CIR::Field(graph_type.to_string()), CIR::QueryResult(result_rows)])
+}
@@ -0,0 +1,425 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::AggFunc;
+use crate::csvqb::columns::{read_numeric, typed_column, Numeric};
+use crate::csvqb::order::{compare_cells, compare_keys};
+use crate::csvqb::parallel::map_chunks;
+use crate::csvqb::nulls::is_null;
+use crate::csvqb::{EvalContext, Tally};
+
+/// Running state of one aggregate group. Every group keeps the same few
+/// partials however many rows it reads, so merging the states of row ranges is
+/// cheap; only `CMEDIAN` and `CPERCENTILE`, which need the values in order,
+/// keep the values themselves.
+#[derive(Debug, Clone)]
+pub struct AggState {
+    /// Rows that had the column at all, numeric or not.
+    pub rows: usize,
+    /// Rows whose value parsed as a number.
+    pub count: usize,
+    /// Rows with a value that isn't a number.
+    pub skipped: usize,
+    /// Rows whose cell is empty or one of the file's null tokens.
+    pub nulls: usize,
+    pub min: f64,
+    pub max: f64,
+    /// Sum of the numbers and the rounding error it lost so far (Neumaier's
+    /// compensated sum), so large and small values add up exactly.
+    sum: f64,
+    compensation: f64,
+    /// Mean of the numbers and the sum of their squared deviations from it,
+    /// updated with Welford's method and merged with Chan's.
+    mean: f64,
+    m2: f64,
+    keep_values: bool,
+    /// Every numeric value, only collected when `keep_values` is set.
+    values: Vec<f64>,
+    /// Every cell that isn't null, only collected for `CCOUNTD`.
+    distinct: HashSet<String>,
+}
+
+impl Default for AggState {
+    fn default() -> Self {
+        Self {
+            rows: 0,
+            count: 0,
+            skipped: 0,
+            nulls: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            compensation: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            keep_values: false,
+            values: Vec::new(),
+            distinct: HashSet::new(),
+        }
+    }
+}
+
+impl AggState {
+    /// An empty state that keeps what `func` needs to finish.
+    pub fn new(func: AggFunc) -> Self {
+        Self {
+            keep_values: matches!(func, AggFunc::Median | AggFunc::Percentile(_)),
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, value: Numeric) {
+        self.rows += 1;
+        match value {
+            Numeric::Value(value) => self.push_number(value),
+            Numeric::NotNumeric => self.skipped += 1,
+            Numeric::Null => self.nulls += 1,
+        }
+    }
+
+    /// Counts the cell as one of the values of a `CCOUNTD`, whether or not it
+    /// is a number. Null cells aren't values.
+    pub fn push_distinct(&mut self, cell: &str, null_tokens: &[String]) {
+        self.rows += 1;
+        if is_null(cell, null_tokens) {
+            self.nulls += 1;
+        } else {
+            self.distinct.insert(cell.to_string());
+        }
+    }
+
+    pub fn push_number(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.add_to_sum(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        if self.keep_values {
+            self.values.push(value);
+        }
+    }
+
+    /// Adds the rows of `other`, which must come after the rows of `self`.
+    pub fn merge(&mut self, other: AggState) {
+        if self.count == 0 {
+            (self.mean, self.m2) = (other.mean, other.m2);
+        } else if other.count > 0 {
+            let (count, other_count) = (self.count as f64, other.count as f64);
+            let total = count + other_count;
+            let delta = other.mean - self.mean;
+            self.mean += delta * other_count / total;
+            self.m2 += other.m2 + delta * delta * count * other_count / total;
+        }
+        self.add_to_sum(other.sum);
+        self.compensation += other.compensation;
+
+        self.rows += other.rows;
+        self.count += other.count;
+        self.skipped += other.skipped;
+        self.nulls += other.nulls;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.keep_values |= other.keep_values;
+        self.values.extend(other.values);
+        self.distinct.extend(other.distinct);
+    }
+
+    /// Final value of the aggregate, `None` when there is nothing to report
+    /// (e.g. the average of no numbers or the deviation of a single one).
+    pub fn finish(&mut self, func: AggFunc) -> Option<f64> {
+        match func {
+            AggFunc::Count => Some(self.rows as f64),
+            AggFunc::CountDistinct => Some(self.distinct.len() as f64),
+            _ if self.count == 0 => None,
+            AggFunc::Sum => Some(self.sum()),
+            AggFunc::Avg => Some(self.sum() / self.count as f64),
+            AggFunc::Min => Some(self.min),
+            AggFunc::Max => Some(self.max),
+            AggFunc::Var => (self.count > 1).then(|| self.m2 / (self.count - 1) as f64),
+            AggFunc::StdDev => (self.count > 1).then(|| (self.m2 / (self.count - 1) as f64).sqrt()),
+            AggFunc::Median => Some(self.percentile(50.0)),
+            AggFunc::Percentile(p) => Some(self.percentile(p)),
+        }
+    }
+
+    fn add_to_sum(&mut self, value: f64) {
+        let sum = self.sum + value;
+        self.compensation += if self.sum.abs() >= value.abs() {
+            (self.sum - sum) + value
+        } else {
+            (value - sum) + self.sum
+        };
+        self.sum = sum;
+    }
+
+    fn sum(&self) -> f64 {
+        self.sum + self.compensation
+    }
+
+    /// Linearly interpolated percentile, the same definition as Excel's PERCENTILE.INC.
+    fn percentile(&mut self, p: f64) -> f64 {
+        self.values.sort_by(|a, b| a.total_cmp(b));
+        let rank = (p.clamp(0.0, 100.0) / 100.0) * (self.values.len() - 1) as f64;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        self.values[lower] + (self.values[upper] - self.values[lower]) * (rank - lower as f64)
+    }
+}
+
+/// Aggregates `column` over every selected file, grouped by the `GRP` columns.
+/// Groups with the same key in different files are merged into one row.
+///
+/// Without `GRP`, `CCOUNT` counts how often each value of the column occurs
+/// while every other aggregate, `CCOUNTD` included, produces a single row. Also returns how many
+/// rows were read, how many of them were null and how many values were skipped
+/// because they aren't numeric.
+pub fn col_aggregate(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    column: &str,
+    group_by: Option<&[String]>,
+    func: AggFunc
+) -> (Vec<Vec<String>>, Tally) {
+    // Every chunk of rows is aggregated on its own, then the partial groups are
+    // merged in chunk order.
+    let partials = map_chunks(file_indexes, csv_files, context.progress, |chunk| {
+        let mut groups: HashMap<Vec<String>, AggState> = HashMap::new();
+        let Some(col_idx) = chunk.headers.iter().position(|h| h == column) else { return groups };
+        let typed = typed_column(&context.tables, chunk.file_idx, column);
+        let null_tokens = context.null_tokens.get(&csv_files[chunk.file_idx].0);
+        let group_idxs: Option<Vec<Option<usize>>> = group_by.map(|group_cols| {
+            group_cols.iter()
+                .map(|group_col| chunk.headers.iter().position(|h| h == group_col))
+                .collect()
+        });
+
+        for (row_idx, row) in chunk.rows.iter().enumerate() {
+            if row.len() <= col_idx { continue; }
+
+            let key = match &group_idxs {
+                Some(idxs) => idxs.iter()
+                    .map(|idx| idx.and_then(|idx| row.get(idx)).cloned().unwrap_or_default())
+                    .collect(),
+                None if func == AggFunc::Count => vec![row[col_idx].clone()],
+                None => vec![func.default_label()],
+            };
+
+            let state = groups.entry(key).or_insert_with(|| AggState::new(func));
+            match func {
+                AggFunc::CountDistinct => state.push_distinct(&row[col_idx], null_tokens),
+                _ => state.push(read_numeric(typed, chunk.offset + row_idx, &row[col_idx], null_tokens))
+            }
+        }
+        groups
+    });
+
+    let mut groups: HashMap<Vec<String>, AggState> = HashMap::new();
+    for partial in partials {
+        for (key, state) in partial {
+            groups.entry(key).or_default().merge(state);
+        }
+    }
+
+    let mut header_row = Vec::new();
+    if let Some(group_cols) = group_by {
+        header_row.extend(group_cols.iter().cloned());
+    } else {
+        header_row.push(column.to_string());
+    }
+    header_row.push(func.result_name());
+
+    // Groups come out ordered by their key so results and charts are the same on every run.
+    let mut groups: Vec<(Vec<String>, AggState)> = groups.into_iter().collect();
+    groups.sort_by(|(a, _), (b, _)| compare_keys(a, b, a.len()));
+
+    let tally = tally(func, groups.iter().map(|(_, state)| state));
+
+    let mut query_grid = vec![header_row];
+    for (mut key, mut state) in groups {
+        key.push(state.finish(func).map(|value| value.to_string()).unwrap_or_default());
+        query_grid.push(key);
+    }
+
+    (query_grid, tally)
+}
+
+/// Rows, nulls and skipped values over every group of an aggregate. Counting
+/// doesn't read the values as numbers, so nothing is skipped.
+fn tally<'a>(func: AggFunc, states: impl Iterator<Item = &'a AggState>) -> Tally {
+    let mut tally = Tally::default();
+    for state in states {
+        tally.rows += state.rows;
+        tally.nulls += state.nulls;
+        tally.skipped += state.skipped;
+    }
+    if matches!(func, AggFunc::Count | AggFunc::CountDistinct) {
+        tally.skipped = 0;
+    }
+    tally
+}
+
+/// Aggregates `value` for every combination of a `rows` and a `columns` value.
+/// The result has one row per `rows` value and one column per `columns` value,
+/// both in order, with empty cells where a combination never occurs.
+pub fn col_pivot(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    rows: &str,
+    columns: &str,
+    value: &str,
+    func: AggFunc
+) -> (Vec<Vec<String>>, Tally) {
+    let partials = map_chunks(file_indexes, csv_files, context.progress, |chunk| {
+        let mut cells: HashMap<(String, String), AggState> = HashMap::new();
+        let position = |name: &str| chunk.headers.iter().position(|h| h == name);
+        let (Some(row_idx), Some(col_idx), Some(value_idx)) = (position(rows), position(columns), position(value)) else {
+            return cells;
+        };
+        let typed = typed_column(&context.tables, chunk.file_idx, value);
+        let null_tokens = context.null_tokens.get(&csv_files[chunk.file_idx].0);
+
+        for (chunk_row, row) in chunk.rows.iter().enumerate() {
+            let Some(cell) = row.get(value_idx) else { continue };
+            let key = (
+                row.get(row_idx).cloned().unwrap_or_default(),
+                row.get(col_idx).cloned().unwrap_or_default(),
+            );
+            let state = cells.entry(key).or_insert_with(|| AggState::new(func));
+            match func {
+                AggFunc::CountDistinct => state.push_distinct(cell, null_tokens),
+                _ => state.push(read_numeric(typed, chunk.offset + chunk_row, cell, null_tokens))
+            }
+        }
+        cells
+    });
+
+    let mut cells: HashMap<(String, String), AggState> = HashMap::new();
+    for partial in partials {
+        for (key, state) in partial {
+            cells.entry(key).or_default().merge(state);
+        }
+    }
+
+    let mut row_keys: Vec<String> = cells.keys().map(|(row, _)| row.clone()).collect();
+    let mut col_keys: Vec<String> = cells.keys().map(|(_, col)| col.clone()).collect();
+    for keys in [&mut row_keys, &mut col_keys] {
+        keys.sort_by(|a, b| compare_cells(a, b));
+        keys.dedup();
+    }
+
+    let tally = tally(func, cells.values());
+    let mut header_row = vec![rows.to_string()];
+    header_row.extend(col_keys.iter().cloned());
+    let mut query_grid = vec![header_row];
+
+    for row_key in row_keys {
+        let mut out = vec![row_key.clone()];
+        for col_key in &col_keys {
+            let value = cells.get_mut(&(row_key.clone(), col_key.clone()))
+                .and_then(|state| state.finish(func));
+            out.push(value.map(|value| value.to_string()).unwrap_or_default());
+        }
+        query_grid.push(out);
+    }
+
+    (query_grid, tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csvqb::nulls::NullTokens;
+    use crate::csvqb::parallel::{Progress, CHUNK_ROWS};
+
+    /// A column of values that don't add up exactly, spread over several chunks
+    /// and three groups, with some nulls and text in between.
+    fn files() -> Vec<(String, Arc<CsvGrid>)> {
+        let mut grid = vec![vec!["group".to_string(), "amount".to_string()]];
+        for idx in 0..3 * CHUNK_ROWS + 17 {
+            let amount = match idx % 11 {
+                0 => String::new(),
+                5 => "n/a".to_string(),
+                7 => (1e9 + idx as f64 * 0.37).to_string(),
+                _ => (idx as f64 * 0.1 + 1.0 / (idx % 13 + 1) as f64).to_string(),
+            };
+            grid.push(vec![["a", "b", "c"][idx % 3].to_string(), amount]);
+        }
+        vec![("big.csv".to_string(), Arc::new(grid))]
+    }
+
+    /// Every group in a single pass over the rows, in order.
+    fn sequential(files: &[(String, Arc<CsvGrid>)], null_tokens: &NullTokens, grouped: bool, func: AggFunc) -> Vec<Vec<String>> {
+        let (path, grid) = &files[0];
+        let mut groups: Vec<(String, AggState)> = Vec::new();
+        for row in &grid[1..] {
+            let key = if grouped { row[0].clone() } else { func.default_label() };
+            let idx = match groups.iter().position(|(name, _)| *name == key) {
+                Some(idx) => idx,
+                None => {
+                    groups.push((key, AggState::new(func)));
+                    groups.len() - 1
+                }
+            };
+            groups[idx].1.push(read_numeric(None, 0, &row[1], null_tokens.get(path)));
+        }
+        groups.sort_by(|(a, _), (b, _)| compare_cells(a, b));
+        groups.into_iter()
+            .map(|(key, mut state)| vec![key, state.finish(func).map(|value| value.to_string()).unwrap_or_default()])
+            .collect()
+    }
+
+    fn same_number(left: &str, right: &str) -> bool {
+        match (left.parse::<f64>(), right.parse::<f64>()) {
+            (Ok(l), Ok(r)) => (l - r).abs() <= 1e-12 * l.abs().max(1.0),
+            _ => left == right,
+        }
+    }
+
+    #[test]
+    fn chunked_aggregates_match_a_sequential_pass() {
+        let files = files();
+        let null_tokens = NullTokens::default();
+        let progress = Progress::default();
+        let group_cols = vec!["group".to_string()];
+        let funcs = [
+            AggFunc::Sum, AggFunc::Avg, AggFunc::Min, AggFunc::Max, AggFunc::Var, AggFunc::StdDev,
+            AggFunc::Median, AggFunc::Percentile(90.0),
+        ];
+
+        for func in funcs {
+            for grouped in [false, true] {
+                let context = EvalContext {
+                    tables: vec![],
+                    null_tokens: &null_tokens,
+                    progress: &progress,
+                    skipped: vec![],
+                    nulls: vec![],
+                };
+                let group_by = grouped.then_some(group_cols.as_slice());
+                let (chunked, _) = col_aggregate(&[0], &files, &context, "amount", group_by, func);
+                let expected = sequential(&files, &null_tokens, grouped, func);
+                assert_eq!(chunked.len() - 1, expected.len(), "{:?} grouped: {}", func, grouped);
+                for (row, expected) in chunked[1..].iter().zip(&expected) {
+                    assert_eq!(row[0], expected[0]);
+                    assert!(same_number(&row[1], &expected[1]), "{:?} grouped: {} {:?} vs {:?}", func, grouped, row, expected);
+                }
+            }
+
+            // Only the aggregates that need the values in order keep them.
+            let mut state = AggState::new(func);
+            let mut other = AggState::new(func);
+            for idx in 0..1000 {
+                state.push_number(idx as f64);
+                other.push_number(-(idx as f64));
+            }
+            state.merge(other);
+            let kept = if matches!(func, AggFunc::Median | AggFunc::Percentile(_)) { 2000 } else { 0 };
+            assert_eq!(state.values.len(), kept, "{:?}", func);
+            assert!(kept > 0 || state.values.capacity() == 0, "{:?}", func);
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use crate::csvqb::bins::BinMethod;
+use crate::csvqb::dates::{parse_iso, DateFormat, TimeUnit};
+use crate::csvqb::lexer::Span;
+use crate::csvqb::nulls::is_null;
+use crate::csvqb::text::{eval_text, Pattern, TextFunc};
+use crate::csvqb::Operator;
+
+/// A column name or other bare word as written in the pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub chart: Option<Ident>,
+    /// `FROM monthly_rev` reads a result named by another pipeline, or a loaded
+    /// file by name, instead of the selected files. Several are stacked.
+    pub sources: Vec<Ident>,
+    pub stages: Vec<Stage>,
+    /// `=> monthly_rev` at the end names the result so other pipelines can read it.
+    pub name: Option<Ident>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// `GRP a b ..` groups every aggregate that follows it.
+    Group { columns: Vec<Ident>, span: Span },
+    /// `WHERE region = EU`, or a bare `amount > 100`. Narrows the rows every
+    /// later stage sees.
+    Filter { condition: Condition, span: Span },
+    /// `JOIN orders.customer_id customers.id` relates the rows of two files on
+    /// a key column, every later stage sees only the combined grid.
+    Join { kind: JoinKind, left: Ident, right: Ident, span: Span },
+    /// `SORT sum DESC` orders the rows of the output right before it, or the rows
+    /// of the files when nothing has been output yet. `TOP 5 BY sum` is parsed
+    /// as a descending sort followed by a limit.
+    Sort { column: Ident, descending: bool, span: Span },
+    /// `LIMIT 10` keeps the first rows, in the same place a sort would apply.
+    Limit { count: usize, span: Span },
+    /// `price MUL qty AS revenue` adds a column computed row by row, which later
+    /// stages use like any other column.
+    Derive { expr: Expr, name: Ident, span: Span },
+    /// `DATEFMT day eu` reads the column as dates written that way in every later
+    /// stage. Columns without one are read as ISO 8601.
+    Dates { column: Ident, format: DateFormat, span: Span },
+    /// `BUCKET day month` replaces every date in the column with the month it
+    /// falls in, so `GRP day` groups by month.
+    Bucket { column: Ident, unit: TimeUnit, span: Span },
+    /// `DEDUP customer day` keeps the first row of every combination of the
+    /// columns, `DEDUP` on its own the first of every identical row. Rows of all
+    /// the files are compared, by column name.
+    Dedup { columns: Vec<Ident>, span: Span },
+    /// `FILL amount 0` writes the value into every null cell of the column, the
+    /// way SQL's `COALESCE` does. Files without the column get it, filled in.
+    Fill { column: Ident, value: Literal, span: Span },
+    /// A value producing expression, each one becomes part of the pipeline output.
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    /// `JOIN`, only rows whose key is in both files.
+    Inner,
+    /// `LEFTJOIN`, every row of the left file, with empty cells where the right has no match.
+    Left,
+    /// `FULLJOIN`, every row of both files.
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggFunc {
+    Sum,
+    Avg,
+    Count,
+    /// `CCOUNTD customer`, how many different values the column has. Empty
+    /// cells aren't a value.
+    CountDistinct,
+    Min,
+    Max,
+    Median,
+    StdDev,
+    Var,
+    /// `CPCT 90 amount`, the percentile is between 0 and 100.
+    Percentile(f64),
+}
+
+/// Functions over a window of ordered rows, partitioned by `GRP`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunc {
+    /// `CRUNSUM amount BY day`
+    RunningSum,
+    /// `CMOVAVG 7 amount BY day`, average of the row and the ones before it.
+    MovingAvg(usize),
+    /// `CLAG 1 amount BY day`, change since the value n rows before.
+    Lag(usize),
+    /// `CLEAD 1 amount BY day`, change until the value n rows after.
+    Lead(usize),
+    /// `CRANK amount`, 1 for the largest value, ties share a rank.
+    Rank,
+}
+
+/// How two numeric columns relate, over the rows where both are numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelationFunc {
+    /// `CCORR x y`, Pearson's correlation coefficient.
+    Pearson,
+    /// `CSPEARMAN x y`, Pearson's coefficient of the ranks, ties share the average rank.
+    Spearman,
+    /// `CCOV x y`, the sample covariance.
+    Covariance,
+    /// `CFIT x y`, the least squares line `y = slope * x + intercept` and its r².
+    Fit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Equals,
+    NotEquals,
+    GreaterThan,
+    LessThan,
+    GreaterEq,
+    LessEq,
+    Contains,
+    StartsWith,
+    EndsWith,
+    /// `email MATCHES '@example\.com$'`, the pattern is found somewhere in the cell.
+    Matches,
+    And,
+    Or,
+}
+
+/// A value written in the pipeline that is compared against cells, kept as
+/// text so `007` or `10.50` match exactly what is in the csv.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Compare { column: Ident, op: BinaryOp, value: Literal },
+    /// `day BETWEEN 2024-01-01 2024-03-31`, both ends included.
+    Between { column: Ident, from: Literal, to: Literal },
+    /// `LOWER region = eu`, compares what a string function makes of the cell.
+    Text { expr: Expr, op: BinaryOp, value: Literal },
+    /// `MATCHES` on a column or on what a string function makes of it.
+    Matches { expr: Expr, pattern: Pattern, span: Span },
+    /// `amount IS NULL`, or `IS NOT NULL` when `negated`. A cell is null when it
+    /// is empty or one of its file's null tokens, a file without the column has
+    /// only null cells in it.
+    IsNull { column: Ident, negated: bool, span: Span },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>, Span),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number { value: f64, span: Span },
+    Field(Ident),
+    Aggregate { func: AggFunc, column: Ident, span: Span },
+    Window { func: WindowFunc, column: Ident, order: Option<Ident>, span: Span },
+    /// `PIVOT product month CSUM revenue`, one row per product and one column per month.
+    Pivot { rows: Ident, columns: Ident, func: AggFunc, value: Ident, span: Span },
+    /// `DISTINCT region product`, every combination of the columns that occurs, once.
+    Distinct { columns: Vec<Ident>, span: Span },
+    /// `CFIT price sold`, how `y` relates to `x`, per `GRP` group.
+    Relation { func: RelationFunc, x: Ident, y: Ident, span: Span },
+    /// `BIN amount width 10`, how many values of the column fall in each bin.
+    Bin { column: Ident, method: BinMethod, span: Span },
+    /// `LOWER TRIM region`, a string function of a column, with a value per row.
+    Text { func: TextFunc, arg: Box<Expr>, span: Span },
+    Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>, span: Span },
+    Not { expr: Box<Expr>, span: Span },
+}
+
+impl Stage {
+    pub fn span(&self) -> Span {
+        match self {
+            Stage::Group { span, .. }
+            | Stage::Filter { span, .. }
+            | Stage::Join { span, .. }
+            | Stage::Sort { span, .. }
+            | Stage::Limit { span, .. }
+            | Stage::Derive { span, .. }
+            | Stage::Dates { span, .. }
+            | Stage::Bucket { span, .. }
+            | Stage::Dedup { span, .. }
+            | Stage::Fill { span, .. } => *span,
+            Stage::Expr(expr) => expr.span(),
+        }
+    }
+}
+
+impl JoinKind {
+    pub fn from_operator(op: &Operator) -> Option<JoinKind> {
+        match op {
+            Operator::Join => Some(JoinKind::Inner),
+            Operator::LeftJoin => Some(JoinKind::Left),
+            Operator::FullJoin => Some(JoinKind::Full),
+            _ => None,
+        }
+    }
+}
+
+impl AggFunc {
+    pub fn from_operator(op: &Operator) -> Option<AggFunc> {
+        match op {
+            Operator::Sum => Some(AggFunc::Sum),
+            Operator::Avg => Some(AggFunc::Avg),
+            Operator::Count => Some(AggFunc::Count),
+            Operator::CountDistinct => Some(AggFunc::CountDistinct),
+            Operator::Min => Some(AggFunc::Min),
+            Operator::Max => Some(AggFunc::Max),
+            Operator::Median => Some(AggFunc::Median),
+            Operator::StdDev => Some(AggFunc::StdDev),
+            Operator::Var => Some(AggFunc::Var),
+            Operator::Percentile => Some(AggFunc::Percentile(50.0)),
+            _ => None,
+        }
+    }
+
+    /// Name of the value column in the aggregate's result grid.
+    pub fn result_name(&self) -> String {
+        match self {
+            AggFunc::Sum => "sum".to_string(),
+            AggFunc::Avg => "average".to_string(),
+            AggFunc::Count => "count".to_string(),
+            AggFunc::CountDistinct => "distinct count".to_string(),
+            AggFunc::Min => "min".to_string(),
+            AggFunc::Max => "max".to_string(),
+            AggFunc::Median => "median".to_string(),
+            AggFunc::StdDev => "stddev".to_string(),
+            AggFunc::Var => "variance".to_string(),
+            AggFunc::Percentile(p) => format!("p{}", p),
+        }
+    }
+
+    /// Row label used when the aggregate isn't grouped.
+    pub fn default_label(&self) -> String {
+        match self {
+            AggFunc::Avg => "average".to_string(),
+            func => format!("column {}", func.result_name()),
+        }
+    }
+}
+
+impl RelationFunc {
+    pub fn from_operator(op: &Operator) -> Option<RelationFunc> {
+        match op {
+            Operator::Correlation => Some(RelationFunc::Pearson),
+            Operator::Spearman => Some(RelationFunc::Spearman),
+            Operator::Covariance => Some(RelationFunc::Covariance),
+            Operator::Fit => Some(RelationFunc::Fit),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RelationFunc::Pearson => "correlation",
+            RelationFunc::Spearman => "rank correlation",
+            RelationFunc::Covariance => "covariance",
+            RelationFunc::Fit => "fit",
+        }
+    }
+
+    /// Names of the value columns in the relation's result grid.
+    pub fn result_names(&self) -> &'static [&'static str] {
+        match self {
+            RelationFunc::Pearson => &["pearson r"],
+            RelationFunc::Spearman => &["spearman rho"],
+            RelationFunc::Covariance => &["covariance"],
+            RelationFunc::Fit => &["slope", "intercept", "r2"],
+        }
+    }
+}
+
+impl WindowFunc {
+    /// The function for a window keyword, row counts are filled in by the parser.
+    pub fn from_operator(op: &Operator) -> Option<WindowFunc> {
+        match op {
+            Operator::RunningSum => Some(WindowFunc::RunningSum),
+            Operator::MovingAvg => Some(WindowFunc::MovingAvg(1)),
+            Operator::Lag => Some(WindowFunc::Lag(1)),
+            Operator::Lead => Some(WindowFunc::Lead(1)),
+            Operator::Rank => Some(WindowFunc::Rank),
+            _ => None,
+        }
+    }
+
+    pub fn result_name(&self) -> String {
+        match self {
+            WindowFunc::RunningSum => "running sum".to_string(),
+            WindowFunc::MovingAvg(n) => format!("moving average {}", n),
+            WindowFunc::Lag(n) => format!("change since {} before", n),
+            WindowFunc::Lead(n) => format!("change until {} after", n),
+            WindowFunc::Rank => "rank".to_string(),
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn from_operator(op: &Operator) -> Option<BinaryOp> {
+        match op {
+            Operator::Add => Some(BinaryOp::Add),
+            Operator::Sub => Some(BinaryOp::Sub),
+            Operator::Mul => Some(BinaryOp::Mul),
+            Operator::Div => Some(BinaryOp::Div),
+            Operator::Mod => Some(BinaryOp::Mod),
+            Operator::Pow => Some(BinaryOp::Pow),
+            Operator::Equals => Some(BinaryOp::Equals),
+            Operator::NotEquals => Some(BinaryOp::NotEquals),
+            Operator::GreaterThan => Some(BinaryOp::GreaterThan),
+            Operator::LessThan => Some(BinaryOp::LessThan),
+            Operator::GreaterEq => Some(BinaryOp::GreaterEq),
+            Operator::LessEq => Some(BinaryOp::LessEq),
+            Operator::Contains => Some(BinaryOp::Contains),
+            Operator::StartsWith => Some(BinaryOp::StartsWith),
+            Operator::EndsWith => Some(BinaryOp::EndsWith),
+            Operator::Matches => Some(BinaryOp::Matches),
+            Operator::And => Some(BinaryOp::And),
+            Operator::Or => Some(BinaryOp::Or),
+            _ => None,
+        }
+    }
+
+    /// Binding strength when the operator is written infix, higher binds tighter.
+    /// `OR` binds loosest, then `AND`, comparisons and arithmetic, where `POW`
+    /// binds tighter than `MUL` which binds tighter than `ADD`.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+            BinaryOp::Pow => 6,
+            _ => 3,
+        }
+    }
+
+    /// Minimum precedence of the operand on the right, `POW` is right associative
+    /// so `2 POW 3 POW 2` is `2 POW (3 POW 2)`.
+    pub fn rhs_precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Pow => self.precedence(),
+            _ => self.precedence() + 1,
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        self.precedence() == 3
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        self.precedence() >= 4
+    }
+
+    /// `None` when dividing by zero or when the operator isn't arithmetic.
+    pub fn arithmetic(&self, left: f64, right: f64) -> Option<f64> {
+        match self {
+            BinaryOp::Add => Some(left + right),
+            BinaryOp::Sub => Some(left - right),
+            BinaryOp::Mul => Some(left * right),
+            BinaryOp::Div | BinaryOp::Mod if right == 0.0 => None,
+            BinaryOp::Div => Some(left / right),
+            BinaryOp::Mod => Some(left % right),
+            BinaryOp::Pow => Some(left.powf(right)),
+            _ => None,
+        }
+    }
+
+    pub fn is_logical(&self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::Equals => "=",
+            BinaryOp::NotEquals => "!=",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::LessThan => "<",
+            BinaryOp::GreaterEq => ">=",
+            BinaryOp::LessEq => "<=",
+            BinaryOp::Contains => "CONTAINS",
+            BinaryOp::StartsWith => "STARTSWITH",
+            BinaryOp::EndsWith => "ENDSWITH",
+            BinaryOp::Matches => "MATCHES",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+
+    /// Compares a csv cell with a literal. Both sides are compared as numbers
+    /// when they both parse as one, otherwise as text.
+    pub fn compare_cell(&self, cell: &str, value: &str) -> bool {
+        if let (Ok(left), Ok(right)) = (cell.parse::<f64>(), value.parse::<f64>()) {
+            if let Some(result) = self.compare_numbers(left, right) {
+                return result;
+            }
+        }
+
+        match self {
+            BinaryOp::Equals => cell == value,
+            BinaryOp::NotEquals => cell != value,
+            BinaryOp::GreaterThan => cell > value,
+            BinaryOp::LessThan => cell < value,
+            BinaryOp::GreaterEq => cell >= value,
+            BinaryOp::LessEq => cell <= value,
+            BinaryOp::Contains => cell.contains(value),
+            BinaryOp::StartsWith => cell.starts_with(value),
+            BinaryOp::EndsWith => cell.ends_with(value),
+            _ => false,
+        }
+    }
+
+    /// `None` for operators that only make sense on text.
+    pub fn compare_numbers(&self, left: f64, right: f64) -> Option<bool> {
+        match self {
+            BinaryOp::Equals => Some(left == right),
+            BinaryOp::NotEquals => Some(left != right),
+            BinaryOp::GreaterThan => Some(left > right),
+            BinaryOp::LessThan => Some(left < right),
+            BinaryOp::GreaterEq => Some(left >= right),
+            BinaryOp::LessEq => Some(left <= right),
+            _ => None,
+        }
+    }
+}
+
+impl Condition {
+    pub fn span(&self) -> Span {
+        match self {
+            Condition::Compare { column, value, .. } => column.span.to(value.span),
+            Condition::Between { column, to, .. } => column.span.to(to.span),
+            Condition::Text { expr, value, .. } => expr.span().to(value.span),
+            Condition::Matches { span, .. } | Condition::IsNull { span, .. } => *span,
+            Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => lhs.span().to(rhs.span()),
+            Condition::Not(_, span) => *span,
+        }
+    }
+
+    /// Calls `f` on every column the condition reads.
+    pub fn visit_columns<E>(&self, f: &mut impl FnMut(&Ident) -> Result<(), E>) -> Result<(), E> {
+        match self {
+            Condition::Compare { column, .. } | Condition::Between { column, .. } | Condition::IsNull { column, .. } => f(column),
+            Condition::Text { expr, .. } | Condition::Matches { expr, .. } => expr.visit(&mut |expr| match expr {
+                Expr::Field(column) => f(column),
+                _ => Ok(()),
+            }),
+            Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+                lhs.visit_columns(f)?;
+                rhs.visit_columns(f)
+            }
+            Condition::Not(inner, _) => inner.visit_columns(f),
+        }
+    }
+
+    /// `dates` holds the format of every column set with `DATEFMT`, `null_tokens`
+    /// the cells the row's file uses for a missing value.
+    pub fn matches(&self, headers: &[String], row: &[String], dates: &[(String, DateFormat)], null_tokens: &[String]) -> bool {
+        let cell = |column: &Ident| headers.iter()
+            .position(|h| *h == column.name)
+            .and_then(|idx| row.get(idx));
+
+        match self {
+            Condition::Compare { column, op, value } => cell(column)
+                .is_some_and(|cell| compare(*op, &column.name, cell, &value.text, dates)),
+            Condition::Between { column, from, to } => cell(column).is_some_and(|cell| {
+                compare(BinaryOp::GreaterEq, &column.name, cell, &from.text, dates)
+                    && compare(BinaryOp::LessEq, &column.name, cell, &to.text, dates)
+            }),
+            // What a string function makes of a date is compared as ISO 8601.
+            Condition::Text { expr, op, value } => eval_text(expr, headers, row)
+                .is_some_and(|text| compare(*op, "", &text, &value.text, dates)),
+            Condition::Matches { expr, pattern, .. } => eval_text(expr, headers, row)
+                .is_some_and(|text| pattern.regex.is_match(&text)),
+            Condition::IsNull { column, negated, .. } => cell(column).is_none_or(|cell| is_null(cell, null_tokens)) != *negated,
+            Condition::And(lhs, rhs) => lhs.matches(headers, row, dates, null_tokens) && rhs.matches(headers, row, dates, null_tokens),
+            Condition::Or(lhs, rhs) => lhs.matches(headers, row, dates, null_tokens) || rhs.matches(headers, row, dates, null_tokens),
+            Condition::Not(inner, _) => !inner.matches(headers, row, dates, null_tokens),
+        }
+    }
+}
+
+/// Compares a cell of `column` with a literal as points in time when both are
+/// dates, so `2024-01-05 10:00 > 2024-01-05` holds and `DATEFMT` columns order
+/// by date rather than by text. The literal may be written in the column's
+/// format or as ISO 8601.
+fn compare(op: BinaryOp, column: &str, cell: &str, value: &str, dates: &[(String, DateFormat)]) -> bool {
+    let format = dates.iter().find(|(name, _)| name == column).map(|(_, format)| *format).unwrap_or_default();
+    let value_date = format.parse(value).or_else(|| parse_iso(value));
+
+    if let (Some(left), Some(right)) = (format.parse(cell), value_date) {
+        if let Some(result) = op.compare_numbers(left as f64, right as f64) {
+            return result;
+        }
+    }
+    op.compare_cell(cell, value)
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number { span, .. } => *span,
+            Expr::Field(ident) => ident.span,
+            Expr::Aggregate { span, .. } => *span,
+            Expr::Window { span, .. } => *span,
+            Expr::Pivot { span, .. } => *span,
+            Expr::Distinct { span, .. } => *span,
+            Expr::Bin { span, .. } => *span,
+            Expr::Relation { span, .. } => *span,
+            Expr::Text { span, .. } => *span,
+            Expr::Binary { span, .. } => *span,
+            Expr::Not { span, .. } => *span,
+        }
+    }
+
+    /// Calls `f` on this expression and every sub expression, stopping at the first error.
+    pub fn visit<E>(&self, f: &mut impl FnMut(&Expr) -> Result<(), E>) -> Result<(), E> {
+        f(self)?;
+        match self {
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.visit(f)?;
+                rhs.visit(f)
+            }
+            Expr::Not { expr, .. } | Expr::Text { arg: expr, .. } => expr.visit(f),
+            _ => Ok(()),
+        }
+    }
+
+    /// Human readable name used to label the expression in charts.
+    pub fn label(&self) -> String {
+        match self {
+            Expr::Number { value, .. } => value.to_string(),
+            Expr::Field(ident) => ident.name.clone(),
+            Expr::Aggregate { column, .. } => column.name.clone(),
+            Expr::Window { func, column, .. } => format!("{} {}", column.name, func.result_name()),
+            Expr::Pivot { rows, columns, value, .. } => format!("{} by {} and {}", value.name, rows.name, columns.name),
+            Expr::Text { func, arg, .. } => format!("{} {}", func.keyword(), arg.label()),
+            Expr::Distinct { columns, .. } => format!("distinct {}", columns.iter().map(|column| column.name.as_str()).collect::<Vec<_>>().join(", ")),
+            Expr::Bin { column, .. } => format!("{} bins", column.name),
+            Expr::Relation { func: RelationFunc::Fit, x, y, .. } => format!("fit of {} on {}", y.name, x.name),
+            Expr::Relation { func, x, y, .. } => format!("{} of {} and {}", func.name(), x.name, y.name),
+            Expr::Binary { op, lhs, rhs, .. } => {
+                // Operands that bind looser than `op` were written in parentheses.
+                let operand = |expr: &Expr| match expr {
+                    Expr::Binary { op: inner, .. } if inner.precedence() < op.precedence() => format!("({})", expr.label()),
+                    _ => expr.label(),
+                };
+                format!("{} {} {}", operand(lhs), op.symbol(), operand(rhs))
+            }
+            Expr::Not { expr, .. } => format!("NOT {}", expr.label()),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::columns::{read_numeric, typed_column, Numeric};
+use crate::csvqb::compute::format_number;
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::Span;
+use crate::csvqb::parallel::map_chunks;
+use crate::csvqb::{EvalContext, Tally};
+
+/// More bins than this can't be told apart on a chart, it is almost always a
+/// width that is too small for the column.
+pub const MAX_BINS: usize = 1000;
+
+/// How `BIN column [n | width w | auto | sturges | fd]` picks its bins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinMethod {
+    /// `BIN amount 20`, that many bins of equal width from the smallest value to the largest.
+    Count(usize),
+    /// `BIN amount width 5`, bins of that width with edges on its multiples.
+    Width(f64),
+    /// ceil(log2 n) + 1 bins, good for small columns that are roughly normal.
+    Sturges,
+    /// Bins of width 2 IQR / n^(1/3), which holds up against outliers.
+    FreedmanDiaconis,
+    /// Whichever of Sturges and Freedman-Diaconis makes more bins, the rule
+    /// used when no method is given.
+    Auto,
+}
+
+impl BinMethod {
+    pub fn from_word(word: &str) -> Option<BinMethod> {
+        match word.to_ascii_lowercase().as_str() {
+            "auto" => Some(BinMethod::Auto),
+            "sturges" => Some(BinMethod::Sturges),
+            "fd" => Some(BinMethod::FreedmanDiaconis),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            BinMethod::Count(count) => format!("{} bins", count),
+            BinMethod::Width(width) => format!("bins {} wide", width),
+            BinMethod::Sturges => "Sturges' rule".to_string(),
+            BinMethod::FreedmanDiaconis => "the Freedman-Diaconis rule".to_string(),
+            BinMethod::Auto => "Sturges' or the Freedman-Diaconis rule".to_string(),
+        }
+    }
+
+    /// Edges of the bins for `values`, which are sorted and not empty. There is
+    /// one more edge than there are bins.
+    fn edges(&self, values: &[f64]) -> Option<Vec<f64>> {
+        let min = values[0];
+        let max = values[values.len() - 1];
+        let n = values.len() as f64;
+
+        let count = match self {
+            BinMethod::Width(width) => {
+                let start = (min / width).floor() * width;
+                // Checked before the cast, a tiny width would overflow `usize`.
+                let bins = ((max - start) / width).floor() + 1.0;
+                if bins > MAX_BINS as f64 {
+                    return None;
+                }
+                let count = (bins as usize).max(1);
+                return Some((0..=count).map(|idx| round_edge(start + idx as f64 * width)).collect());
+            }
+            _ if min == max => 1,
+            BinMethod::Count(count) => *count,
+            BinMethod::Sturges => sturges(n),
+            BinMethod::FreedmanDiaconis => freedman_diaconis(values, max - min).unwrap_or_else(|| sturges(n)),
+            BinMethod::Auto => freedman_diaconis(values, max - min).unwrap_or(0).max(sturges(n)),
+        };
+        if count > MAX_BINS {
+            return None;
+        }
+
+        let width = (max - min) / count as f64;
+        let mut edges: Vec<f64> = (0..count).map(|idx| round_edge(min + idx as f64 * width)).collect();
+        edges.push(max);
+        Some(edges)
+    }
+}
+
+fn sturges(n: f64) -> usize {
+    n.log2().ceil() as usize + 1
+}
+
+/// Bin count of the Freedman-Diaconis rule, `None` when half the values are the
+/// same and the interquartile range is 0.
+fn freedman_diaconis(values: &[f64], range: f64) -> Option<usize> {
+    let iqr = quantile(values, 0.75) - quantile(values, 0.25);
+    let width = 2.0 * iqr / (values.len() as f64).cbrt();
+    (width > 0.0).then(|| ((range / width).ceil() as usize).max(1))
+}
+
+/// Linear interpolation between the closest ranks of sorted `values`.
+fn quantile(values: &[f64], q: f64) -> f64 {
+    let rank = q * (values.len() - 1) as f64;
+    let lower = values[rank.floor() as usize];
+    let upper = values[rank.ceil() as usize];
+    lower + (upper - lower) * rank.fract()
+}
+
+/// Drops the noise of adding up widths, so edges read `0.3` and not `0.30000000000000004`.
+fn round_edge(value: f64) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let scale = 10f64.powi(11 - value.abs().log10().floor() as i32);
+    (value * scale).round() / scale
+}
+
+/// Counts the values of `column` in every selected file per bin. Bins include
+/// their lower edge and leave out the upper one, except the last bin, which
+/// ends at the largest value. The result has a row per bin with its edges and
+/// count, empty bins included. Also returns how many rows were read, how many
+/// of them were null and how many values were skipped because they aren't numeric.
+pub fn bin_column(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    column: &str,
+    method: BinMethod,
+    span: Span
+) -> Result<(Vec<Vec<String>>, Tally), CsvqbError> {
+    let partials = map_chunks(file_indexes, csv_files, context.progress, |chunk| {
+        let mut values = Vec::new();
+        let mut tally = Tally::default();
+        let Some(col_idx) = chunk.headers.iter().position(|h| h == column) else { return (values, tally) };
+        let typed = typed_column(&context.tables, chunk.file_idx, column);
+        let null_tokens = context.null_tokens.get(&csv_files[chunk.file_idx].0);
+
+        for (row_idx, row) in chunk.rows.iter().enumerate() {
+            let Some(cell) = row.get(col_idx) else { continue };
+            tally.rows += 1;
+            match read_numeric(typed, chunk.offset + row_idx, cell, null_tokens) {
+                Numeric::Value(value) if value.is_finite() => values.push(value),
+                Numeric::Value(_) | Numeric::NotNumeric => tally.skipped += 1,
+                Numeric::Null => tally.nulls += 1,
+            }
+        }
+        (values, tally)
+    });
+
+    let mut values = Vec::new();
+    let mut tally = Tally::default();
+    for (partial, partial_tally) in partials {
+        values.extend(partial);
+        tally.rows += partial_tally.rows;
+        tally.nulls += partial_tally.nulls;
+        tally.skipped += partial_tally.skipped;
+    }
+    values.sort_by(f64::total_cmp);
+
+    let mut grid = vec![vec!["from".to_string(), "to".to_string(), "count".to_string()]];
+    if values.is_empty() {
+        return Ok((grid, tally));
+    }
+
+    let Some(edges) = method.edges(&values) else {
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::TypeMismatch,
+            format!(
+                "binning '{}' with {} makes more than {} bins, its values go from {} to {}",
+                column,
+                method.describe(),
+                MAX_BINS,
+                values[0],
+                values[values.len() - 1],
+            ),
+            span,
+        ).with_token("BIN"));
+    };
+
+    // Values are sorted, so every bin takes the values up to its upper edge.
+    let mut counts = vec![0usize; edges.len() - 1];
+    let mut bin = 0;
+    for value in values {
+        while bin + 1 < counts.len() && value >= edges[bin + 1] {
+            bin += 1;
+        }
+        counts[bin] += 1;
+    }
+
+    for (bin, count) in counts.into_iter().enumerate() {
+        grid.push(vec![
+            format_number(Some(edges[bin])),
+            format_number(Some(edges[bin + 1])),
+            count.to_string(),
+        ]);
+    }
+    Ok((grid, tally))
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use rayon::prelude::*;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::dates::parse_iso;
+use crate::csvqb::nulls::{is_null, NullTokens};
+
+/// Text columns with at most this many distinct values are stored as categories.
+const MAX_CATEGORIES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Bool,
+    /// Dates and date times, stored as seconds since 1970-01-01.
+    Date,
+    Categorical,
+    Text,
+    /// Every cell is empty.
+    Empty,
+}
+
+impl ColumnType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+            ColumnType::Categorical => "categorical",
+            ColumnType::Text => "text",
+            ColumnType::Empty => "empty",
+        }
+    }
+}
+
+/// Values of one column, `None` where the cell is null.
+#[derive(Debug, Clone)]
+pub enum ColumnData {
+    Integer(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+    Date(Vec<Option<i64>>),
+    /// Each cell is an index into `labels`, `numbers` holds the labels that are numeric.
+    Categorical { codes: Vec<Option<u32>>, labels: Vec<String>, numbers: Vec<Option<f64>> },
+    /// Text is read from the grid, only the null cells are tracked.
+    Text(Vec<bool>),
+    Empty,
+}
+
+/// A cell read as a number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Numeric {
+    Value(f64),
+    Null,
+    NotNumeric,
+}
+
+impl Numeric {
+    pub fn parse(cell: &str) -> Numeric {
+        let cell = cell.trim();
+        if cell.is_empty() {
+            Numeric::Null
+        } else {
+            cell.parse::<f64>().map_or(Numeric::NotNumeric, Numeric::Value)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TypedColumn {
+    pub name: String,
+    pub kind: ColumnType,
+    pub data: ColumnData,
+    pub nulls: usize,
+}
+
+impl TypedColumn {
+    /// The cell at `row` (0 is the first row under the header) as a number.
+    /// `cell` is the text of the same cell, only read for text columns.
+    pub fn numeric(&self, row: usize, cell: &str) -> Numeric {
+        let from = |value: Option<Option<f64>>| match value {
+            Some(Some(value)) => Numeric::Value(value),
+            _ => Numeric::Null,
+        };
+        match &self.data {
+            ColumnData::Integer(values) => from(values.get(row).map(|value| value.map(|value| value as f64))),
+            ColumnData::Float(values) => from(values.get(row).copied()),
+            ColumnData::Categorical { codes, numbers, .. } => match codes.get(row).copied().flatten() {
+                Some(code) => numbers[code as usize].map_or(Numeric::NotNumeric, Numeric::Value),
+                None => Numeric::Null,
+            },
+            ColumnData::Bool(values) => match values.get(row) {
+                Some(Some(_)) => Numeric::NotNumeric,
+                _ => Numeric::Null,
+            },
+            ColumnData::Date(values) => match values.get(row) {
+                Some(Some(_)) => Numeric::NotNumeric,
+                _ => Numeric::Null,
+            },
+            ColumnData::Text(nulls) => match nulls.get(row) {
+                Some(true) => Numeric::Null,
+                _ => Numeric::parse(cell),
+            },
+            ColumnData::Empty => Numeric::Null,
+        }
+    }
+}
+
+/// Typed, columnar copy of a csv grid. Built once when a file is loaded so csvqb
+/// doesn't parse the same strings on every query.
+#[derive(Debug, Clone)]
+pub struct TypedTable {
+    pub columns: Vec<TypedColumn>,
+    pub rows: usize,
+}
+
+impl TypedTable {
+    /// Infers the type of every column, in parallel. A column gets the narrowest
+    /// type all of its non null cells fit, cells that are empty or one of
+    /// `null_tokens` are null.
+    pub fn infer(grid: &CsvGrid, null_tokens: &[String]) -> TypedTable {
+        let Some((headers, rows)) = grid.split_first() else {
+            return TypedTable { columns: Vec::new(), rows: 0 };
+        };
+
+        let columns = headers.par_iter()
+            .enumerate()
+            .map(|(col_idx, name)| {
+                let cells: Vec<&str> = rows.iter()
+                    .map(|row| row.get(col_idx).map(|cell| cell.trim()).unwrap_or_default())
+                    .collect();
+                infer_column(name, &cells, null_tokens)
+            })
+            .collect();
+
+        TypedTable { columns, rows: rows.len() }
+    }
+
+    pub fn column(&self, name: &str) -> Option<&TypedColumn> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+fn infer_column(name: &str, cells: &[&str], null_tokens: &[String]) -> TypedColumn {
+    let null: Vec<bool> = cells.iter().map(|cell| is_null(cell, null_tokens)).collect();
+    let nulls = null.iter().filter(|&&null| null).count();
+    let values = || cells.iter().zip(&null).filter(|(_, &null)| !null).map(|(cell, _)| *cell);
+
+    let (kind, data) = if nulls == cells.len() {
+        (ColumnType::Empty, ColumnData::Empty)
+    } else if values().all(|cell| cell.parse::<i64>().is_ok()) {
+        (ColumnType::Integer, ColumnData::Integer(parse_cells(cells, &null, |cell| cell.parse().ok())))
+    } else if values().all(is_float) {
+        (ColumnType::Float, ColumnData::Float(parse_cells(cells, &null, |cell| cell.parse().ok())))
+    } else if values().all(|cell| parse_bool(cell).is_some()) {
+        (ColumnType::Bool, ColumnData::Bool(parse_cells(cells, &null, parse_bool)))
+    } else if values().all(|cell| parse_iso(cell).is_some()) {
+        (ColumnType::Date, ColumnData::Date(parse_cells(cells, &null, parse_iso)))
+    } else {
+        categorical(cells, &null, nulls).unwrap_or((ColumnType::Text, ColumnData::Text(null)))
+    };
+
+    TypedColumn { name: name.to_string(), kind, data, nulls }
+}
+
+/// Every cell read with `parse`, `None` for the null ones.
+fn parse_cells<T>(cells: &[&str], null: &[bool], parse: impl Fn(&str) -> Option<T>) -> Vec<Option<T>> {
+    cells.iter().zip(null).map(|(cell, &null)| if null { None } else { parse(cell) }).collect()
+}
+
+/// Text with few distinct values compared to its length, e.g. a region or status column.
+fn categorical(cells: &[&str], null: &[bool], nulls: usize) -> Option<(ColumnType, ColumnData)> {
+    let mut lookup: HashMap<&str, u32> = HashMap::new();
+    let mut labels: Vec<String> = Vec::new();
+    let mut codes = Vec::with_capacity(cells.len());
+
+    for (cell, &null) in cells.iter().zip(null) {
+        if null {
+            codes.push(None);
+            continue;
+        }
+        let code = match lookup.get(cell) {
+            Some(code) => *code,
+            None => {
+                if labels.len() == MAX_CATEGORIES || labels.len() * 2 >= cells.len() - nulls {
+                    return None;
+                }
+                lookup.insert(cell, labels.len() as u32);
+                labels.push(cell.to_string());
+                labels.len() as u32 - 1
+            }
+        };
+        codes.push(Some(code));
+    }
+
+    let numbers = labels.iter().map(|label| label.parse::<f64>().ok()).collect();
+    Some((ColumnType::Categorical, ColumnData::Categorical { codes, labels, numbers }))
+}
+
+/// Plain decimal numbers only, so `inf` and `NaN` stay text.
+fn is_float(cell: &str) -> bool {
+    cell.trim_start_matches(['-', '+']).starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && cell.parse::<f64>().is_ok()
+}
+
+fn parse_bool(cell: &str) -> Option<bool> {
+    match cell.to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Typed tables of the loaded csv files, indexed like `csv_files`. Tables are
+/// inferred on a worker thread and a file has none until its table arrives,
+/// pipelines then read its cells as text. A table is inferred again when its
+/// file changes shape or gets other null tokens; replacing a file or editing it
+/// in place has to call `invalidate`.
+#[derive(Debug)]
+pub struct ColumnCache {
+    slots: Vec<Option<Slot>>,
+    /// Goes up whenever files change or tables arrive, once per `sync` that saw
+    /// any, so copies of the files can tell they are stale.
+    version: u64,
+    /// Stamps every inference, a table for a file that changed since is dropped.
+    next_stamp: u64,
+    sender: Sender<(usize, u64, TypedTable)>,
+    receiver: Receiver<(usize, u64, TypedTable)>,
+}
+
+/// What a table was inferred from, and the table once it arrived.
+#[derive(Debug)]
+struct Slot {
+    path: String,
+    rows: usize,
+    columns: usize,
+    null_tokens: Vec<String>,
+    stamp: u64,
+    table: Option<Arc<TypedTable>>,
+}
+
+impl Default for ColumnCache {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self { slots: Vec::new(), version: 0, next_stamp: 0, sender, receiver }
+    }
+}
+
+impl ColumnCache {
+    /// Picks up the tables that finished inferring, and starts inferring the
+    /// ones of files that are new or changed.
+    pub fn sync(&mut self, files: &[(String, Arc<CsvGrid>)], null_tokens: &NullTokens) {
+        let mut changed = self.slots.len() != files.len();
+        while let Ok((index, stamp, table)) = self.receiver.try_recv() {
+            if let Some(Some(slot)) = self.slots.get_mut(index).filter(|slot| slot.as_ref().is_some_and(|slot| slot.stamp == stamp)) {
+                slot.table = Some(Arc::new(table));
+                changed = true;
+            }
+        }
+
+        self.slots.resize_with(files.len(), || None);
+
+        for (index, (slot, (path, grid))) in self.slots.iter_mut().zip(files).enumerate() {
+            let tokens = null_tokens.get(path);
+            let columns = grid.first().map_or(0, |headers| headers.len());
+            let fresh = slot.as_ref().is_some_and(|slot| {
+                slot.path == *path && slot.rows == grid.len() && slot.columns == columns && slot.null_tokens == tokens
+            });
+            if fresh {
+                continue;
+            }
+
+            self.next_stamp += 1;
+            *slot = Some(Slot {
+                path: path.clone(),
+                rows: grid.len(),
+                columns,
+                null_tokens: tokens.to_vec(),
+                stamp: self.next_stamp,
+                table: None,
+            });
+            changed = true;
+
+            // The worker shares the grid rather than copying it.
+            let (grid, tokens, stamp, sender) = (Arc::clone(grid), tokens.to_vec(), self.next_stamp, self.sender.clone());
+            thread::spawn(move || {
+                let _ = sender.send((index, stamp, TypedTable::infer(&grid, &tokens)));
+            });
+        }
+
+        if changed {
+            self.version += 1;
+        }
+    }
+
+    /// Whether some table is still being inferred.
+    pub fn is_inferring(&self) -> bool {
+        self.slots.iter().flatten().any(|slot| slot.table.is_none())
+    }
+
+    pub fn invalidate(&mut self, index: usize) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            *slot = None;
+        }
+        self.version += 1;
+    }
+
+    /// Forgets every table, for when all the files are replaced at once.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.version += 1;
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get(&self, index: usize) -> Option<&TypedTable> {
+        self.slots.get(index).and_then(|slot| slot.as_ref()?.table.as_deref())
+    }
+
+    /// Tables lined up with `files`, for evaluating a pipeline.
+    pub fn tables(&self) -> Vec<Option<&TypedTable>> {
+        self.slots.iter().map(|slot| slot.as_ref().and_then(|slot| slot.table.as_deref())).collect()
+    }
+
+    /// Same as `tables`, for a pipeline that runs on another thread.
+    pub fn shared_tables(&self) -> Vec<Option<Arc<TypedTable>>> {
+        self.slots.iter().map(|slot| slot.as_ref().and_then(|slot| slot.table.clone())).collect()
+    }
+}
+
+/// `column` of the file at `file_idx`, when the file has a typed table.
+pub fn typed_column<'a>(tables: &[Option<&'a TypedTable>], file_idx: usize, column: &str) -> Option<&'a TypedColumn> {
+    tables.get(file_idx).copied().flatten().and_then(|table| table.column(column))
+}
+
+/// Row `row` of a column as a number, through its typed column when there is one.
+/// A cell that is one of the file's null tokens is null, not text. Typed columns
+/// know their null cells from when they were inferred with the same tokens.
+pub fn read_numeric(typed: Option<&TypedColumn>, row: usize, cell: &str, null_tokens: &[String]) -> Numeric {
+    match typed {
+        Some(typed) => typed.numeric(row, cell),
+        None if is_null(cell, null_tokens) => Numeric::Null,
+        None => Numeric::parse(cell),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(rows: &[&[&str]]) -> CsvGrid {
+        rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn typed_columns_read_like_the_cells() {
+        let grid = grid(&[
+            &["int", "float", "text", "category"],
+            &["1", "1.5", "a", "x"],
+            &["n/a", "", "-", "n/a"],
+            &["3", "NULL", "4.5", "x"],
+            &["", "2", "b", "7"],
+            &["5", "2.25", "n/a", "x"],
+            &["-", "-", "", "x"],
+        ]);
+        let tokens = vec!["N/A".to_string(), "NULL".to_string(), "-".to_string()];
+        let table = TypedTable::infer(&grid, &tokens);
+
+        let kinds: Vec<ColumnType> = table.columns.iter().map(|column| column.kind).collect();
+        assert_eq!(kinds, vec![ColumnType::Integer, ColumnType::Float, ColumnType::Text, ColumnType::Categorical]);
+        let nulls: Vec<usize> = table.columns.iter().map(|column| column.nulls).collect();
+        assert_eq!(nulls, vec![3, 3, 3, 1]);
+
+        for (col_idx, column) in table.columns.iter().enumerate() {
+            for (row, cells) in grid[1..].iter().enumerate() {
+                let cell = &cells[col_idx];
+                assert_eq!(
+                    read_numeric(Some(column), row, cell, &tokens),
+                    read_numeric(None, row, cell, &tokens),
+                    "{} row {}", column.name, row
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn syncing_bumps_the_version_once_per_batch() {
+        let files: Vec<(String, Arc<CsvGrid>)> = ["a.csv", "b.csv", "c.csv"].iter()
+            .map(|path| (path.to_string(), Arc::new(grid(&[&["n"], &["1"], &["2"]]))))
+            .collect();
+        let null_tokens = NullTokens::default();
+        let mut cache = ColumnCache::default();
+
+        cache.sync(&files, &null_tokens);
+        assert_eq!(cache.version(), 1);
+        while cache.is_inferring() {
+            thread::sleep(std::time::Duration::from_millis(5));
+            cache.sync(&files, &null_tokens);
+        }
+        let settled = cache.version();
+        assert!(settled <= 4, "{}", settled);
+        cache.sync(&files, &null_tokens);
+        assert_eq!(cache.version(), settled);
+        assert!(cache.tables().iter().all(Option::is_some));
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::Expr;
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::Span;
+use crate::csvqb::text::eval_text;
+
+/// Whether `expr` reads columns, so it has a value per row instead of a single one.
+pub fn is_row_wise(expr: &Expr) -> bool {
+    let mut found = false;
+    let _ = expr.visit(&mut |expr| {
+        found |= matches!(expr, Expr::Field(_));
+        Ok::<(), ()>(())
+    });
+    found
+}
+
+/// Arithmetic on columns, e.g. `price MUL qty`, which gives a value per row.
+pub fn is_row_arithmetic(expr: &Expr) -> bool {
+    matches!(expr, Expr::Binary { op, .. } if op.is_arithmetic()) && is_row_wise(expr)
+}
+
+/// Row by row only arithmetic makes sense, comparisons on columns are filters.
+pub fn check_row_wise(expr: &Expr) -> Result<(), CsvqbError> {
+    expr.visit(&mut |expr| match expr {
+        Expr::Binary { op, span, .. } if !op.is_arithmetic() => Err(CsvqbError::new(
+            CsvqbErrorKind::TypeMismatch,
+            format!("'{}' can't be computed per row, only ADD, SUB, MUL, DIV, MOD and POW can", op.symbol()),
+            *span,
+        ).with_token(op.symbol())),
+        Expr::Not { span, .. } => Err(CsvqbError::new(
+            CsvqbErrorKind::TypeMismatch,
+            "NOT can't be computed per row, use it in a filter e.g. 'WHERE NOT region = EU'",
+            *span,
+        ).with_token("NOT")),
+        _ => Ok(()),
+    })
+}
+
+/// Value of an arithmetic expression for one row. `scalars` holds the value of
+/// every aggregate in the expression keyed by its span. `None` when a cell isn't
+/// numeric or a division is by zero.
+pub fn eval_row(expr: &Expr, headers: &[String], row: &[String], scalars: &[(Span, f64)]) -> Option<f64> {
+    match expr {
+        Expr::Number { value, .. } => Some(*value),
+        Expr::Field(ident) => headers.iter()
+            .position(|h| *h == ident.name)
+            .and_then(|idx| row.get(idx))
+            .and_then(|cell| cell.trim().parse::<f64>().ok()),
+        Expr::Aggregate { span, .. } => scalars.iter().find(|(s, _)| s == span).map(|(_, value)| *value),
+        Expr::Text { .. } => eval_text(expr, headers, row).and_then(|text| text.trim().parse::<f64>().ok()),
+        Expr::Binary { op, lhs, rhs, .. } if op.is_arithmetic() => {
+            let left = eval_row(lhs, headers, row, scalars)?;
+            let right = eval_row(rhs, headers, row, scalars)?;
+            op.arithmetic(left, right)
+        }
+        _ => None,
+    }
+}
+
+pub fn format_number(value: Option<f64>) -> String {
+    value.filter(|value| value.is_finite()).map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// The cell a row wise expression makes for one row: text for a string
+/// function, a number otherwise.
+fn row_cell(expr: &Expr, headers: &[String], row: &[String], scalars: &[(Span, f64)]) -> String {
+    match expr {
+        Expr::Text { .. } => eval_text(expr, headers, row).unwrap_or_default(),
+        _ => format_number(eval_row(expr, headers, row, scalars)),
+    }
+}
+
+/// Adds `name` to every selected file, computed from `expr` for each row. An
+/// existing column with the same name is overwritten, so `TRIM region AS region`
+/// cleans the column up for the stages after it.
+pub fn derive_column(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    name: &str,
+    expr: &Expr,
+    scalars: &[(Span, f64)]
+) -> Vec<(String, Arc<CsvGrid>)> {
+    let mut result = Vec::new();
+
+    for &file_idx in file_indexes {
+        if let Some((path, grid)) = csv_files.get(file_idx) {
+            let mut grid = grid.as_ref().clone();
+            let Some(headers) = grid.first().cloned() else { continue };
+            let col_idx = match headers.iter().position(|h| h == name) {
+                Some(idx) => idx,
+                None => {
+                    grid[0].push(name.to_string());
+                    headers.len()
+                }
+            };
+
+            for row in grid.iter_mut().skip(1) {
+                let value = row_cell(expr, &headers, row, scalars);
+                if row.len() <= col_idx {
+                    row.resize(col_idx + 1, String::new());
+                }
+                row[col_idx] = value;
+            }
+            result.push((path.clone(), Arc::new(grid)));
+        }
+    }
+
+    result
+}
+
+/// A row wise expression used as output, one value per row labelled by row number.
+pub fn row_values(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    expr: &Expr,
+    scalars: &[(Span, f64)]
+) -> Vec<Vec<String>> {
+    let mut query_grid = vec![vec!["row".to_string(), expr.label()]];
+
+    for &file_idx in file_indexes {
+        if let Some((_, grid)) = csv_files.get(file_idx) {
+            let Some(headers) = grid.first() else { continue };
+            for row in grid.iter().skip(1) {
+                let row_number = query_grid.len().to_string();
+                query_grid.push(vec![row_number, row_cell(expr, headers, row, scalars)]);
+            }
+        }
+    }
+
+    query_grid
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::columns::{typed_column, ColumnData, TypedTable};
+
+/// How the cells of a date column are written, set per column with
+/// `DATEFMT column iso|us|eu|epoch|epochms`. Columns without one are read as ISO 8601.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DateFormat {
+    #[default]
+    Iso,
+    /// `03/31/2024`, month first.
+    Us,
+    /// `31/03/2024` or `31.03.2024`, day first.
+    Eu,
+    /// Seconds since 1970-01-01.
+    EpochSeconds,
+    /// Milliseconds since 1970-01-01.
+    EpochMillis,
+}
+
+impl DateFormat {
+    pub const WORDS: &'static [&'static str] = &["iso", "us", "eu", "epoch", "epochms"];
+
+    pub fn from_word(word: &str) -> Option<DateFormat> {
+        match word.to_ascii_lowercase().as_str() {
+            "iso" => Some(DateFormat::Iso),
+            "us" => Some(DateFormat::Us),
+            "eu" => Some(DateFormat::Eu),
+            "epoch" => Some(DateFormat::EpochSeconds),
+            "epochms" => Some(DateFormat::EpochMillis),
+            _ => None,
+        }
+    }
+
+    /// Seconds since 1970-01-01 UTC, `None` when the text isn't a date in this format.
+    pub fn parse(&self, text: &str) -> Option<i64> {
+        let text = text.trim();
+        match self {
+            DateFormat::Iso => parse_iso(text),
+            DateFormat::Us | DateFormat::Eu => {
+                let (date, time) = match text.find([' ', 'T']) {
+                    Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+                    None => (text, None),
+                };
+                let mut parts = date.split(['/', '.', '-']);
+                let (first, second) = (parts.next()?, parts.next()?);
+                let year = parse_digits(parts.next()?, 4)?;
+                if parts.next().is_some() {
+                    return None;
+                }
+                let (month, day) = match self {
+                    DateFormat::Us => (first, second),
+                    _ => (second, first),
+                };
+                let month = parse_day_or_month(month)?;
+                let day = parse_day_or_month(day)?;
+                let seconds = match time {
+                    Some(time) => parse_time(time)?,
+                    None => 0,
+                };
+                Some(days_from_civil(year, month, day)? * 86_400 + seconds)
+            }
+            DateFormat::EpochSeconds => text.parse::<f64>().ok().filter(|value| value.is_finite()).map(|value| value as i64),
+            DateFormat::EpochMillis => text.parse::<f64>().ok().filter(|value| value.is_finite()).map(|value| (value / 1000.0) as i64),
+        }
+    }
+}
+
+/// Calendar units for `BUCKET column day|week|month|quarter|year`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeUnit {
+    Day,
+    /// Weeks start on Monday, as in ISO 8601.
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl TimeUnit {
+    pub const WORDS: &'static [&'static str] = &["day", "week", "month", "quarter", "year"];
+
+    pub fn from_word(word: &str) -> Option<TimeUnit> {
+        match word.to_ascii_lowercase().as_str() {
+            "day" => Some(TimeUnit::Day),
+            "week" => Some(TimeUnit::Week),
+            "month" => Some(TimeUnit::Month),
+            "quarter" => Some(TimeUnit::Quarter),
+            "year" => Some(TimeUnit::Year),
+            _ => None,
+        }
+    }
+
+    /// Label of the bucket `seconds` falls in. Labels sort in time order and
+    /// `parse_label` reads them back: days and weeks are the date of their first
+    /// day (`2024-03-04`), months `2024-03`, quarters `2024-Q1` and years `2024`.
+    pub fn label(&self, seconds: i64) -> String {
+        let days = seconds.div_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        match self {
+            TimeUnit::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            TimeUnit::Week => {
+                // 1970-01-01 was a Thursday, so Monday is 3 days before it.
+                let (year, month, day) = civil_from_days(days - (days + 3).rem_euclid(7));
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+            TimeUnit::Month => format!("{:04}-{:02}", year, month),
+            TimeUnit::Quarter => format!("{:04}-Q{}", year, (month - 1) / 3 + 1),
+            TimeUnit::Year => format!("{:04}", year),
+        }
+    }
+}
+
+/// Seconds since 1970-01-01 of a chart label that is a date: an ISO date or
+/// date time, or a month (`2024-03`) or quarter (`2024-Q1`) bucket.
+pub fn parse_label(text: &str) -> Option<i64> {
+    if let Some(seconds) = parse_iso(text) {
+        return Some(seconds);
+    }
+    let (year, rest) = text.trim().split_once('-')?;
+    let year = parse_digits(year, 4)?;
+    let month = match rest.strip_prefix('Q') {
+        Some(quarter) => match parse_digits(quarter, 1)? {
+            quarter @ 1..=4 => quarter * 3 - 2,
+            _ => return None,
+        },
+        None => parse_digits(rest, 2)?,
+    };
+    Some(days_from_civil(year, month, 1)? * 86_400)
+}
+
+/// `2024-03-01`, with the time of day when it isn't midnight.
+pub fn format_date(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    if time == 0 {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    } else {
+        format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60)
+    }
+}
+
+/// Seconds since 1970-01-01 UTC for an ISO 8601 date (`2024-03-01`) or date
+/// and time (`2024-03-01T12:30:00`, `2024-03-01 12:30`). Offsets are ignored.
+pub fn parse_iso(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None => (text, None),
+    };
+
+    let mut parts = date.split('-');
+    let year = parse_digits(parts.next()?, 4)?;
+    let month = parse_digits(parts.next()?, 2)?;
+    let day = parse_digits(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let seconds = match time {
+        Some(time) => parse_time(time)?,
+        None => 0,
+    };
+    Some(days_from_civil(year, month, day)? * 86_400 + seconds)
+}
+
+/// Seconds into the day of `HH:MM[:SS[.fff]]`, with an optional `Z` or `+01:00` offset after it.
+fn parse_time(time: &str) -> Option<i64> {
+    let time = time.trim_end_matches('Z');
+    let time = time.split(['+', '-']).next()?;
+    let mut parts = time.split(':');
+    let hours = parse_digits(parts.next()?, 2)?;
+    let minutes = parse_digits(parts.next()?, 2)?;
+    let seconds = match parts.next() {
+        Some(seconds) => parse_digits(seconds.split('.').next()?, 2)?,
+        None => 0,
+    };
+    (hours < 24 && minutes < 60 && seconds < 61).then_some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// `3` or `03`.
+fn parse_day_or_month(text: &str) -> Option<i64> {
+    parse_digits(text, 1).or_else(|| parse_digits(text, 2))
+}
+
+fn parse_digits(text: &str, len: usize) -> Option<i64> {
+    (text.len() == len && text.bytes().all(|b| b.is_ascii_digit())).then(|| text.parse().ok())?
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, `None` for dates that don't exist.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_len = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day < 1 || day > month_len {
+        return None;
+    }
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+/// Year, month and day of a count of days since 1970-01-01, the inverse of `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Replaces every date in `column` with the label of the `unit` it falls in.
+/// Cells that aren't dates in `format` become empty.
+pub fn bucket_column(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    tables: &[Option<&TypedTable>],
+    column: &str,
+    unit: TimeUnit,
+    format: DateFormat
+) -> Vec<(String, Arc<CsvGrid>)> {
+    let mut result = Vec::new();
+
+    for &file_idx in file_indexes {
+        if let Some((path, grid)) = csv_files.get(file_idx) {
+            let Some(col_idx) = grid.first().and_then(|headers| headers.iter().position(|h| h == column)) else {
+                result.push((path.clone(), grid.clone()));
+                continue;
+            };
+            let mut grid = grid.as_ref().clone();
+            // ISO columns were already parsed when the file was loaded.
+            let parsed = match typed_column(tables, file_idx, column).map(|typed| &typed.data) {
+                Some(ColumnData::Date(values)) if format == DateFormat::Iso => Some(values),
+                _ => None,
+            };
+
+            for (row_idx, row) in grid.iter_mut().skip(1).enumerate() {
+                let Some(cell) = row.get_mut(col_idx) else { continue };
+                let seconds = match parsed {
+                    Some(values) => values.get(row_idx).copied().flatten(),
+                    None => format.parse(cell),
+                };
+                *cell = seconds.map(|seconds| unit.label(seconds)).unwrap_or_default();
+            }
+            result.push((path.clone(), Arc::new(grid)));
+        }
+    }
+
+    result
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::order::compare_keys;
+use crate::csvqb::parallel::map_chunks;
+use crate::csvqb::EvalContext;
+
+/// Positions of `columns` in `headers`, `None` for the ones the file doesn't have.
+fn positions(headers: &[String], columns: &[String]) -> Vec<Option<usize>> {
+    columns.iter().map(|column| headers.iter().position(|h| h == column)).collect()
+}
+
+/// The cells of `row` under `positions`, a column the file doesn't have reads
+/// as empty, the way it does once the files are stacked.
+fn key(row: &[String], positions: &[Option<usize>]) -> Vec<String> {
+    positions.iter()
+        .map(|pos| pos.and_then(|idx| row.get(idx)).cloned().unwrap_or_default())
+        .collect()
+}
+
+/// Every combination of `columns` that occurs in the selected files, once, in
+/// the order groups come out of an aggregate.
+pub fn distinct_rows(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    columns: &[String]
+) -> Vec<Vec<String>> {
+    let partials = map_chunks(file_indexes, csv_files, context.progress, |chunk| {
+        let positions = positions(chunk.headers, columns);
+        chunk.rows.iter().map(|row| key(row, &positions)).collect::<HashSet<Vec<String>>>()
+    });
+
+    let mut keys: HashSet<Vec<String>> = HashSet::new();
+    for partial in partials {
+        keys.extend(partial);
+    }
+    let mut keys: Vec<Vec<String>> = keys.into_iter().collect();
+    keys.sort_by(|a, b| compare_keys(a, b, columns.len()));
+
+    let mut grid = vec![columns.to_vec()];
+    grid.extend(keys);
+    grid
+}
+
+/// Drops the rows of the selected files whose `columns` repeat a row before
+/// them, in the same file or an earlier one. Without columns, rows are compared
+/// on every column of the files. Each file keeps its own header.
+pub fn dedup_rows(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    columns: &[String]
+) -> Vec<(String, Arc<CsvGrid>)> {
+    let selected: Vec<&(String, Arc<CsvGrid>)> = file_indexes.iter().filter_map(|&idx| csv_files.get(idx)).collect();
+    let columns: Vec<String> = if columns.is_empty() {
+        let mut every = Vec::new();
+        for header in selected.iter().filter_map(|(_, grid)| grid.first()).flatten() {
+            if !every.contains(header) {
+                every.push(header.clone());
+            }
+        }
+        every
+    } else {
+        columns.to_vec()
+    };
+
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+    let mut result = Vec::new();
+    for (path, grid) in selected {
+        let Some((headers, rows)) = grid.split_first() else { continue };
+        let positions = positions(headers, &columns);
+        let mut kept = vec![headers.clone()];
+        kept.extend(rows.iter().filter(|row| seen.insert(key(row, &positions))).cloned());
+        result.push((path.clone(), Arc::new(kept)));
+    }
+    result
+}
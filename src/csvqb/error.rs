@@ -0,0 +1,97 @@
+use std::fmt::Display;
+use crate::csvqb::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsvqbErrorKind {
+    Syntax,
+    UnknownColumn,
+    TypeMismatch,
+    NoInput,
+    Cancelled,
+    /// The pipeline uses something a translation, e.g. to SQL, has no equivalent for.
+    Unsupported,
+    /// Pipelines that read each other's named results in a circle, or share a name.
+    Dependency,
+    /// An `@name` call of a macro that isn't saved.
+    UnknownMacro,
+}
+
+/// Everything that can go wrong while parsing or running a csvqb pipeline.
+/// `span` points into the pipeline text so the explorer can show where it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvqbError {
+    pub kind: CsvqbErrorKind,
+    pub message: String,
+    pub span: Span,
+    /// The column or operator the error is about.
+    pub token: Option<String>,
+    pub suggestion: Option<String>,
+}
+
+impl CsvqbError {
+    pub fn new(kind: CsvqbErrorKind, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span,
+            token: None,
+            suggestion: None,
+        }
+    }
+
+    pub fn syntax(message: impl Into<String>, span: Span) -> Self {
+        Self::new(CsvqbErrorKind::Syntax, message, span)
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Option<String>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
+}
+
+impl Display for CsvqbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {})", self.message, self.span.start)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean '{}'?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CsvqbError {}
+
+/// Closest candidate to `word` by edit distance, ignoring case. Only returns
+/// candidates that are plausibly a typo of `word`.
+pub fn closest_match<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let word_lower = word.to_lowercase();
+    let max_distance = (word.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (levenshtein(&word_lower, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+
+    prev[b.len()]
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use itertools::Itertools;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::{Condition, Expr, Ident, JoinKind, Stage};
+use crate::csvqb::bins::BinMethod;
+use crate::csvqb::compute::is_row_arithmetic;
+use crate::csvqb::dates::{DateFormat, TimeUnit};
+use crate::csvqb::error::CsvqbError;
+use crate::csvqb::join::resolve_join_key;
+use crate::csvqb::lexer::Span;
+use crate::csvqb::check_pipeline;
+use crate::csvqb::named::input_indexes;
+use crate::csvqb::nulls::NullTokens;
+
+/// Rows read from each file to estimate how selective filters are and how many
+/// distinct values a column has. Rows are taken at an even stride so files
+/// sorted by some column are still sampled across their whole range.
+const SAMPLE_ROWS: usize = 2000;
+
+/// Share of rows a filter is assumed to keep when the sample can't tell, e.g.
+/// when it reads a column that only exists after a join or `AS`.
+const UNKNOWN_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// What `csvqb_to_cir` would do with a pipeline, without running it.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    /// Rows of the selected files before the first stage.
+    pub input_rows: usize,
+    pub nodes: Vec<PlanNode>,
+}
+
+/// A stage of the plan. Expressions list their operands as children.
+#[derive(Debug, Clone)]
+pub struct PlanNode {
+    /// What the stage does, e.g. "filter" or "aggregate".
+    pub kind: &'static str,
+    /// The pipeline text of the stage.
+    pub text: String,
+    pub span: Span,
+    /// Estimated rows after the stage, `None` for single values.
+    pub rows: Option<usize>,
+    /// Extra facts about the stage, like the group keys an aggregate uses.
+    pub notes: Vec<String>,
+    /// Position and kind of the value the stage adds to the results.
+    pub output: Option<(usize, &'static str)>,
+    /// Why the stage has no effect on the chart, if it has none.
+    pub ignored: Option<String>,
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn new(kind: &'static str, span: Span, src: &str) -> Self {
+        Self {
+            kind,
+            text: src.get(span.start..span.end).unwrap_or_default().to_string(),
+            span,
+            rows: None,
+            notes: Vec::new(),
+            output: None,
+            ignored: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl Plan {
+    /// Spans of the stages that are ignored, with the reason.
+    pub fn ignored(&self) -> Vec<(Span, String)> {
+        fn collect(nodes: &[PlanNode], ignored: &mut Vec<(Span, String)>) {
+            for node in nodes {
+                match &node.ignored {
+                    Some(reason) => ignored.push((node.span, reason.clone())),
+                    None => collect(&node.children, ignored),
+                }
+            }
+        }
+        let mut ignored = Vec::new();
+        collect(&self.nodes, &mut ignored);
+        ignored
+    }
+}
+
+/// Checks the pipeline and describes every stage: the rows it leaves, which
+/// value it adds to the results and whether it is ignored. Row counts are
+/// estimates from a sample of the selected files.
+pub fn explain_pipeline(
+    qb_pipeline: &[String],
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    null_tokens: &NullTokens
+) -> Result<Plan, CsvqbError> {
+    let pipeline = check_pipeline(qb_pipeline, file_indexes, files)?;
+    let file_indexes = &input_indexes(&pipeline, file_indexes, files)?;
+    let src = qb_pipeline.join(" ");
+    let selected: Vec<&CsvGrid> = file_indexes.iter()
+        .filter_map(|&idx| files.get(idx).map(|(_, grid)| grid.as_ref()))
+        .collect();
+    let selected_tokens: Vec<&[String]> = file_indexes.iter()
+        .filter_map(|&idx| files.get(idx).map(|(path, _)| null_tokens.get(path)))
+        .collect();
+
+    let input_rows: usize = selected.iter().map(|grid| grid.len().saturating_sub(1)).sum();
+    let mut plan = Plan { input_rows, nodes: Vec::new() };
+    let mut results = 0;
+
+    if let Some(chart) = &pipeline.chart {
+        let mut node = PlanNode::new("chart", chart.span, &src);
+        node.output = Some((results, "CIR::Field"));
+        results += 1;
+        plan.nodes.push(node);
+    }
+
+    let mut rows = input_rows as f64;
+    let mut groups: Vec<String> = Vec::new();
+    let mut dates: Vec<(String, DateFormat)> = Vec::new();
+    let mut buckets: Vec<(String, TimeUnit)> = Vec::new();
+    // Rows of the last output, which SORT and LIMIT apply to once there is one.
+    let mut output_rows: Option<Option<f64>> = None;
+
+    for (stage_idx, stage) in pipeline.stages.iter().enumerate() {
+        let later = &pipeline.stages[stage_idx + 1..];
+        let node = match stage {
+            Stage::Group { columns, span } => {
+                groups.extend(columns.iter().map(|column| column.name.clone()));
+                let mut node = PlanNode::new("group", *span, &src);
+                node.notes.push(format!("group keys: {}", groups.join(", ")));
+                if !later.iter().any(uses_groups) {
+                    node.ignored = Some("no aggregate, window, DISTINCT or AS after it to group".to_string());
+                }
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Filter { condition, span } => {
+                let mut node = PlanNode::new("filter", *span, &src);
+                match selectivity(&selected, &selected_tokens, condition, &dates) {
+                    Some(share) => {
+                        node.notes.push(format!("keeps about {:.0}% of the rows in a sample", share * 100.0));
+                        rows *= share;
+                    }
+                    None => {
+                        node.notes.push("the sample can't tell how many rows match, guessing a third".to_string());
+                        rows *= UNKNOWN_SELECTIVITY;
+                    }
+                }
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Join { kind, left, right, span } => {
+                let mut node = PlanNode::new("join", *span, &src);
+                let side = |key: &Ident| resolve_join_key(key, file_indexes, files).ok()
+                    .map(|(idx, column)| (files[idx].1.len().saturating_sub(1) as f64, distinct_column(&files[idx].1, &column)));
+                if let (Some((left_rows, left_keys)), Some((right_rows, right_keys))) = (side(left), side(right)) {
+                    // The left side is what the earlier stages left, when they narrowed anything.
+                    let left_rows = if rows < input_rows as f64 { rows } else { left_rows };
+                    let matched = left_rows * right_rows / left_keys.max(right_keys).max(1.0);
+                    rows = match kind {
+                        JoinKind::Inner => matched,
+                        JoinKind::Left => matched.max(left_rows),
+                        JoinKind::Full => matched.max(left_rows) + (right_rows - matched).max(0.0),
+                    };
+                    node.notes.push(format!("about {:.0} and {:.0} distinct keys", left_keys, right_keys));
+                }
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Sort { span, .. } => {
+                let mut node = PlanNode::new("sort", *span, &src);
+                node.rows = match output_rows {
+                    Some(output) => output,
+                    None => Some(rows),
+                }.map(|rows| rows.round() as usize);
+                node
+            }
+            Stage::Limit { count, span } => {
+                let mut node = PlanNode::new("limit", *span, &src);
+                match &mut output_rows {
+                    Some(output) => *output = output.map(|rows| rows.min(*count as f64)),
+                    None => rows = rows.min(*count as f64),
+                }
+                node.rows = output_rows.unwrap_or(Some(rows)).map(|rows| rows.round() as usize);
+                node
+            }
+            Stage::Derive { expr, name, span } => {
+                let mut node = PlanNode::new("derive", *span, &src);
+                node.notes.push(format!("adds the column '{}'", name.name));
+                node.children.push(expr_node(expr, &src));
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Dates { column, format, span } => {
+                dates.retain(|(name, _)| *name != column.name);
+                dates.push((column.name.clone(), *format));
+                let mut node = PlanNode::new("date format", *span, &src);
+                let read_later = later.iter().any(|stage| match stage {
+                    Stage::Filter { condition, .. } => reads_column(condition, &column.name),
+                    Stage::Bucket { column: bucketed, .. } => bucketed.name == column.name,
+                    _ => false,
+                });
+                if !read_later {
+                    node.ignored = Some(format!("no filter or BUCKET after it reads '{}' as dates", column.name));
+                }
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Bucket { column, unit, span } => {
+                buckets.retain(|(name, _)| *name != column.name);
+                buckets.push((column.name.clone(), *unit));
+                let mut node = PlanNode::new("bucket", *span, &src);
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Fill { column, value, span } => {
+                let mut node = PlanNode::new("fill", *span, &src);
+                node.notes.push(format!("null cells of '{}' become '{}'", column.name, value.text));
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Dedup { columns, span } => {
+                let mut node = PlanNode::new("dedup", *span, &src);
+                let columns: Vec<String> = match columns.is_empty() {
+                    true => selected.iter().filter_map(|grid| grid.first()).flatten().unique().cloned().collect(),
+                    false => columns.iter().map(|column| column.name.clone()).collect(),
+                };
+                if let Some(keys) = distinct_keys(&selected, &columns, &dates, &buckets) {
+                    node.notes.push(format!("about {:.0} distinct rows", keys));
+                    rows = rows.min(keys);
+                }
+                node.rows = Some(rows.round() as usize);
+                node
+            }
+            Stage::Expr(expr) => {
+                let mut node = expr_node(expr, &src);
+                let grouped = !groups.is_empty() && uses_groups(stage);
+                if grouped {
+                    node.notes.push(format!("grouped by {}", groups.join(", ")));
+                }
+
+                let estimate = match expr {
+                    Expr::Aggregate { .. } | Expr::Relation { .. } if grouped => {
+                        let keys = distinct_keys(&selected, &groups, &dates, &buckets);
+                        Some(keys.map_or(rows, |keys| keys.min(rows)))
+                    }
+                    Expr::Aggregate { .. } | Expr::Relation { .. } => Some(1.0),
+                    Expr::Window { .. } | Expr::Text { .. } => Some(rows),
+                    Expr::Distinct { columns, .. } => {
+                        let columns: Vec<String> = groups.iter().cloned()
+                            .chain(columns.iter().map(|column| column.name.clone()))
+                            .unique()
+                            .collect();
+                        let keys = distinct_keys(&selected, &columns, &dates, &buckets);
+                        Some(keys.map_or(rows, |keys| keys.min(rows)))
+                    }
+                    Expr::Pivot { rows: row_column, .. } => {
+                        let keys = distinct_keys(&selected, std::slice::from_ref(&row_column.name), &dates, &buckets);
+                        Some(keys.map_or(rows, |keys| keys.min(rows)))
+                    }
+                    // The other rules need the values, not just how many there are.
+                    Expr::Bin { method: BinMethod::Count(count), .. } => Some(*count as f64),
+                    Expr::Bin { method: BinMethod::Sturges, .. } => Some(rows.max(1.0).log2().ceil() + 1.0),
+                    expr if is_row_arithmetic(expr) => Some(rows),
+                    _ => None,
+                };
+                node.rows = estimate.map(|rows| rows.round() as usize);
+                output_rows = Some(estimate);
+
+                let next = later.first();
+                let (kind, width) = match expr {
+                    Expr::Number { .. } => ("CIR::Number", 1),
+                    Expr::Field(_) => ("CIR::Field", 1),
+                    Expr::Pivot { .. } => ("CIR::Pivot", 1),
+                    Expr::Bin { .. } => ("CIR::Histogram", 1),
+                    Expr::Relation { .. } => ("CIR::Relation", 1),
+                    Expr::Aggregate { .. } | Expr::Window { .. } | Expr::Distinct { .. } | Expr::Text { .. } => ("CIR::QueryResult", 1),
+                    expr if is_row_arithmetic(expr) => ("CIR::QueryResult", 1),
+                    Expr::Binary { op, .. } if op.is_arithmetic() => ("CIR::Number and CIR::Field", 2),
+                    _ => ("CIR::Bool", 1),
+                };
+                node.output = Some((results, kind));
+                results += width;
+
+                let previous = stage_idx.checked_sub(1).map(|idx| &pipeline.stages[idx]);
+                node.ignored = match expr {
+                    Expr::Number { .. } if !matches!(next, Some(Stage::Expr(Expr::Field(_)))) => {
+                        Some("a number is only charted with a label right after it".to_string())
+                    }
+                    Expr::Field(_) if !matches!(previous, Some(Stage::Expr(Expr::Number { .. }))) => {
+                        Some("a column on its own isn't charted, it only labels a number right before it".to_string())
+                    }
+                    _ if kind == "CIR::Bool" => Some("true or false isn't charted".to_string()),
+                    _ => None,
+                };
+                node
+            }
+        };
+        plan.nodes.push(node);
+    }
+
+    // A pipeline that only filters, joins or orders shows the rows that are left.
+    if output_rows.is_none() && !pipeline.stages.is_empty() {
+        let end = src.len();
+        let mut node = PlanNode::new("rows", Span::new(end, end), &src);
+        node.output = Some((results, "CIR::QueryResult"));
+        node.rows = Some(rows.round() as usize);
+        plan.nodes.push(node);
+    }
+
+    Ok(plan)
+}
+
+fn expr_node(expr: &Expr, src: &str) -> PlanNode {
+    let kind = match expr {
+        Expr::Number { .. } => "number",
+        Expr::Field(_) => "word",
+        Expr::Aggregate { .. } => "aggregate",
+        Expr::Window { .. } => "window",
+        Expr::Pivot { .. } => "pivot",
+        Expr::Distinct { .. } => "distinct",
+        Expr::Bin { .. } => "bin",
+        Expr::Relation { .. } => "relation",
+        Expr::Text { .. } => "text",
+        Expr::Binary { op, .. } if op.is_arithmetic() => "arithmetic",
+        Expr::Binary { op, .. } if op.is_logical() => "logic",
+        Expr::Binary { .. } => "comparison",
+        Expr::Not { .. } => "not",
+    };
+    let mut node = PlanNode::new(kind, expr.span(), src);
+    match expr {
+        Expr::Binary { lhs, rhs, .. } => {
+            node.children.push(expr_node(lhs, src));
+            node.children.push(expr_node(rhs, src));
+        }
+        Expr::Not { expr, .. } | Expr::Text { arg: expr, .. } => node.children.push(expr_node(expr, src)),
+        _ => {}
+    }
+    node
+}
+
+/// Whether `GRP` changes what the stage computes.
+fn uses_groups(stage: &Stage) -> bool {
+    let expr = match stage {
+        Stage::Expr(expr) | Stage::Derive { expr, .. } => expr,
+        _ => return false,
+    };
+    expr.visit(&mut |expr| match expr {
+        Expr::Aggregate { .. } | Expr::Window { .. } | Expr::Distinct { .. } | Expr::Relation { .. } => Err(()),
+        _ => Ok(()),
+    }).is_err()
+}
+
+fn reads_column(condition: &Condition, column: &str) -> bool {
+    condition.visit_columns(&mut |ident| if ident.name == column { Err(()) } else { Ok(()) }).is_err()
+}
+
+/// Every n-th row of a file so at most `SAMPLE_ROWS` are read.
+fn sample(grid: &CsvGrid) -> impl Iterator<Item = &Vec<String>> {
+    let stride = grid.len().saturating_sub(1).div_ceil(SAMPLE_ROWS).max(1);
+    grid.iter().skip(1).step_by(stride)
+}
+
+/// Share of the sampled rows that match, over the files that have every column
+/// the condition reads. `null_tokens` are those of the selected files, in order.
+fn selectivity(
+    selected: &[&CsvGrid],
+    null_tokens: &[&[String]],
+    condition: &Condition,
+    dates: &[(String, DateFormat)]
+) -> Option<f64> {
+    let (mut matched, mut sampled) = (0usize, 0usize);
+    for (grid, tokens) in selected.iter().zip(null_tokens) {
+        let Some(headers) = grid.first() else { continue };
+        let has_columns = condition.visit_columns(&mut |column| {
+            if headers.contains(&column.name) { Ok(()) } else { Err(()) }
+        }).is_ok();
+        if !has_columns {
+            continue;
+        }
+        for row in sample(grid) {
+            sampled += 1;
+            matched += condition.matches(headers, row, dates, tokens) as usize;
+        }
+    }
+    (sampled > 0).then(|| matched as f64 / sampled as f64)
+}
+
+/// Distinct values of one column, see `distinct_keys`.
+fn distinct_column(grid: &CsvGrid, column: &str) -> f64 {
+    distinct_keys(&[grid], &[column.to_string()], &[], &[]).unwrap_or(1.0)
+}
+
+/// Estimated number of distinct combinations of `columns`, `None` when no file
+/// has all of them. Uses the GEE estimator, `sqrt(N/n) * f1 + f2+`, where f1
+/// counts the combinations seen once in the sample and f2+ the others. It is
+/// exact when the sample is the whole file.
+fn distinct_keys(
+    selected: &[&CsvGrid],
+    columns: &[String],
+    dates: &[(String, DateFormat)],
+    buckets: &[(String, TimeUnit)]
+) -> Option<f64> {
+    let mut counts: HashMap<Vec<String>, usize> = HashMap::new();
+    let (mut total, mut sampled) = (0usize, 0usize);
+
+    for grid in selected {
+        let Some(headers) = grid.first() else { continue };
+        let Some(positions) = columns.iter()
+            .map(|column| headers.iter().position(|h| h == column))
+            .collect::<Option<Vec<usize>>>() else { continue };
+
+        total += grid.len().saturating_sub(1);
+        for row in sample(grid) {
+            sampled += 1;
+            let key = positions.iter().zip(columns)
+                .map(|(&idx, column)| {
+                    let cell = row.get(idx).map(String::as_str).unwrap_or_default();
+                    // Bucketed columns group by the period a date falls in.
+                    match buckets.iter().find(|(name, _)| name == column) {
+                        Some((_, unit)) => {
+                            let format = dates.iter().find(|(name, _)| name == column).map(|(_, format)| *format).unwrap_or_default();
+                            format.parse(cell).map(|seconds| unit.label(seconds)).unwrap_or_default()
+                        }
+                        None => cell.to_string(),
+                    }
+                })
+                .collect();
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+
+    if sampled == 0 {
+        return None;
+    }
+    let once = counts.values().filter(|&&count| count == 1).count() as f64;
+    let more = counts.len() as f64 - once;
+    Some((total as f64 / sampled as f64).sqrt() * once + more)
+}
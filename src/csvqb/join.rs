@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::{Ident, JoinKind};
+use crate::csvqb::error::{closest_match, CsvqbError, CsvqbErrorKind};
+
+/// File name without its directory or extension, the name used to qualify join keys.
+pub fn file_stem(path: &str) -> &str {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
+
+/// `file.column` as a join key for `column` of the file at `path`.
+pub fn qualified_column(path: &str, column: &str) -> String {
+    format!("{}.{}", file_stem(path), column)
+}
+
+/// Finds which of `file_indexes` a `file.column` key refers to. The file part is
+/// the file name with or without its extension, the longest matching name wins
+/// so `sales.2024.csv.region` still resolves.
+pub fn resolve_join_key(
+    key: &Ident,
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)]
+) -> Result<(usize, String), CsvqbError> {
+    let mut best: Option<(usize, &str)> = None;
+
+    for &file_idx in file_indexes {
+        let Some((path, _)) = csv_files.get(file_idx) else { continue };
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        for prefix in [name, file_stem(path)] {
+            if let Some(column) = key.name.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('.')) {
+                if best.is_none_or(|(_, best_column)| column.len() < best_column.len()) {
+                    best = Some((file_idx, column));
+                }
+            }
+        }
+    }
+
+    let Some((file_idx, column)) = best else {
+        let (file, column) = key.name.split_once('.').unwrap_or((&key.name, ""));
+        let stems: Vec<&str> = file_indexes.iter()
+            .filter_map(|&idx| csv_files.get(idx))
+            .map(|(path, _)| file_stem(path))
+            .collect();
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::UnknownColumn,
+            format!("'{}' doesn't name a selected file, join keys are written as file.column", key.name),
+            key.span,
+        ).with_token(key.name.clone())
+            .with_suggestion(closest_match(file, stems).map(|stem| format!("{}.{}", stem, column))));
+    };
+
+    let headers = csv_files[file_idx].1.first().map(|row| row.as_slice()).unwrap_or_default();
+    if !headers.iter().any(|h| h == column) {
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::UnknownColumn,
+            format!("unknown column '{}' in {}", column, file_stem(&csv_files[file_idx].0)),
+            key.span,
+        ).with_token(key.name.clone())
+            .with_suggestion(closest_match(column, headers.iter().map(|h| h.as_str()))
+                .map(|h| qualified_column(&csv_files[file_idx].0, &h))));
+    }
+
+    Ok((file_idx, column.to_string()))
+}
+
+/// Header of a joined grid: the left file's columns followed by the right file's,
+/// without the right key. Right columns whose name is already taken are
+/// qualified with their file name.
+pub fn joined_headers(left_headers: &[String], right_path: &str, right_headers: &[String], right_key: usize) -> Vec<String> {
+    let mut headers = left_headers.to_vec();
+    for (idx, header) in right_headers.iter().enumerate() {
+        if idx == right_key { continue; }
+        if left_headers.contains(header) {
+            headers.push(qualified_column(right_path, header));
+        } else {
+            headers.push(header.clone());
+        }
+    }
+    headers
+}
+
+/// Joins two files on their key columns. Rows come out in the left file's order,
+/// rows of the right file that matched nothing are appended by a full join. The
+/// key column is shared, so it is filled from the right file for those rows.
+pub fn join_files(
+    left: &(String, Arc<CsvGrid>),
+    left_column: &str,
+    right: &(String, Arc<CsvGrid>),
+    right_column: &str,
+    kind: JoinKind
+) -> CsvGrid {
+    let (Some(left_headers), Some(right_headers)) = (left.1.first(), right.1.first()) else {
+        return Vec::new();
+    };
+    let (Some(left_key), Some(right_key)) = (
+        left_headers.iter().position(|h| h == left_column),
+        right_headers.iter().position(|h| h == right_column),
+    ) else {
+        return Vec::new();
+    };
+
+    let mut right_rows: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (row_idx, row) in right.1.iter().enumerate().skip(1) {
+        if let Some(key) = row.get(right_key) {
+            right_rows.entry(key.as_str()).or_default().push(row_idx);
+        }
+    }
+
+    let right_cells = |row: Option<&Vec<String>>| -> Vec<String> {
+        (0..right_headers.len())
+            .filter(|&idx| idx != right_key)
+            .map(|idx| row.and_then(|row| row.get(idx)).cloned().unwrap_or_default())
+            .collect()
+    };
+    let left_cells = |row: &Vec<String>| -> Vec<String> {
+        (0..left_headers.len()).map(|idx| row.get(idx).cloned().unwrap_or_default()).collect()
+    };
+
+    let mut grid = vec![joined_headers(left_headers, &right.0, right_headers, right_key)];
+    let mut matched = vec![false; right.1.len()];
+
+    for row in left.1.iter().skip(1) {
+        let matches = row.get(left_key).and_then(|key| right_rows.get(key.as_str()));
+        match matches {
+            Some(matches) => {
+                for &right_idx in matches {
+                    matched[right_idx] = true;
+                    let mut joined = left_cells(row);
+                    joined.extend(right_cells(right.1.get(right_idx)));
+                    grid.push(joined);
+                }
+            }
+            None if kind != JoinKind::Inner => {
+                let mut joined = left_cells(row);
+                joined.extend(right_cells(None));
+                grid.push(joined);
+            }
+            None => {}
+        }
+    }
+
+    if kind == JoinKind::Full {
+        for (row_idx, row) in right.1.iter().enumerate().skip(1) {
+            if matched[row_idx] { continue; }
+            let mut joined = vec![String::new(); left_headers.len()];
+            joined[left_key] = row.get(right_key).cloned().unwrap_or_default();
+            joined.extend(right_cells(Some(row)));
+            grid.push(joined);
+        }
+    }
+
+    grid
+}
@@ -0,0 +1,243 @@
+use crate::csvqb::error::CsvqbError;
+use crate::csvqb::Operator;
+
+/// Byte range of a token inside the pipeline source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Chart(String),
+    Operator(Operator),
+    Number(f64),
+    Word(String),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+    pub text: String,
+}
+
+pub const CHART_TYPES: &[&str] = &[
+    "Bar Graph",
+    "Histogram",
+    "Pie Chart",
+    "Scatter Plot",
+    "Line Chart",
+    "Flame Graph",
+];
+
+/// Splits a csvqb pipeline into tokens. Parentheses are self delimiting, quoted
+/// words (`"order date"`) may contain whitespace and chart names are recognised
+/// as a single token even though most of them are two words.
+pub fn tokenize(src: &str) -> Result<Vec<Token>, CsvqbError> {
+    let mut tokens = Vec::new();
+    let bytes = src.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c == b'(' || c == b')' {
+            let kind = if c == b'(' { TokenKind::LParen } else { TokenKind::RParen };
+            tokens.push(Token { kind, span: Span::new(i, i + 1), text: (c as char).to_string() });
+            i += 1;
+            continue;
+        }
+
+        if c == b'"' || c == b'\'' {
+            let start = i;
+            let close = src[i + 1..].find(c as char).ok_or_else(|| {
+                CsvqbError::syntax("unterminated quote", Span::new(start, src.len()))
+                    .with_token((c as char).to_string())
+            })?;
+            let end = i + 1 + close + 1;
+            tokens.push(Token {
+                kind: TokenKind::Word(src[start + 1..end - 1].to_string()),
+                span: Span::new(start, end),
+                text: src[start..end].to_string(),
+            });
+            i = end;
+            continue;
+        }
+
+        if let Some((chart, end)) = match_chart(src, i) {
+            tokens.push(Token {
+                kind: TokenKind::Chart(chart.to_string()),
+                span: Span::new(i, end),
+                text: src[i..end].to_string(),
+            });
+            i = end;
+            continue;
+        }
+
+        let start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'(' && bytes[i] != b')' {
+            i += 1;
+        }
+        let text = &src[start..i];
+        let kind = if let Some(op) = Operator::from_keyword(text) {
+            TokenKind::Operator(op)
+        } else if let Some(num) = parse_number(text) {
+            TokenKind::Number(num)
+        } else {
+            TokenKind::Word(text.to_string())
+        };
+        tokens.push(Token { kind, span: Span::new(start, i), text: text.to_string() });
+    }
+
+    Ok(tokens)
+}
+
+/// Only plain decimal literals count as numbers, so columns named `inf` or `NaN` stay words.
+fn parse_number(text: &str) -> Option<f64> {
+    let digits = text.trim_start_matches(['-', '+']);
+    if digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        text.parse::<f64>().ok()
+    } else {
+        None
+    }
+}
+
+/// Matches a chart name at `start`, allowing any run of whitespace between its words.
+fn match_chart(src: &str, start: usize) -> Option<(&'static str, usize)> {
+    for chart in CHART_TYPES {
+        let mut pos = start;
+        let mut matched = true;
+        for (n, word) in chart.split(' ').enumerate() {
+            if n > 0 {
+                let skipped = src[pos..].len() - src[pos..].trim_start().len();
+                if skipped == 0 {
+                    matched = false;
+                    break;
+                }
+                pos += skipped;
+            }
+            if !src[pos..].starts_with(word) {
+                matched = false;
+                break;
+            }
+            pos += word.len();
+        }
+        let at_boundary = src[pos..].chars().next().is_none_or(|c| c.is_whitespace() || c == '(' || c == ')');
+        if matched && at_boundary {
+            return Some((chart, pos));
+        }
+    }
+    None
+}
+
+/// Token texts of a pipeline, used to store what the user typed without breaking
+/// quoted words or chart names apart. Falls back to whitespace splitting while the
+/// text can't be tokenized yet (e.g. a quote that is still being typed).
+pub fn pipeline_tokens(src: &str) -> Vec<String> {
+    match tokenize(src) {
+        Ok(tokens) => tokens.into_iter().map(|token| token.text).collect(),
+        Err(_) => src.split_whitespace().map(String::from).collect(),
+    }
+}
+
+/// One spelling of a pipeline for every way of typing it: tokens are separated
+/// by a single space and words are quoted only where they need it. Numbers keep
+/// their spelling, since `007` and `7` match different cells. Pipelines that can't be tokenized are joined as they are.
+pub fn canonical_pipeline(pipeline: &[String]) -> String {
+    let src = pipeline.join(" ");
+    match tokenize(&src) {
+        Ok(tokens) => tokens.into_iter()
+            .map(|token| match token.kind {
+                TokenKind::Chart(chart) => chart,
+                TokenKind::Operator(op) => op.keyword().to_string(),
+                TokenKind::Number(_) => token.text,
+                TokenKind::Word(word) => quote_word(&word),
+                TokenKind::LParen | TokenKind::RParen => token.text,
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(_) => src.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Quotes a column name if it would otherwise not survive tokenization as one word.
+pub fn quote_word(word: &str) -> String {
+    let needs_quotes = word.is_empty()
+        || word.chars().any(|c| c.is_whitespace() || c == '(' || c == ')')
+        || Operator::from_keyword(word).is_some()
+        || word.starts_with(['"', '\''])
+        || parse_number(word).is_some()
+        || match_chart(word, 0).is_some();
+
+    if !needs_quotes {
+        word.to_string()
+    } else if word.contains('"') {
+        format!("'{}'", word)
+    } else {
+        format!("\"{}\"", word)
+    }
+}
+
+/// Puts `chart` at the front of a stored pipeline, replacing the chart type it had.
+pub fn set_chart_type(pipeline: &mut Vec<String>, chart: &str) {
+    if pipeline.first().is_some_and(|first| match_chart(first, 0).is_some_and(|(_, end)| end == first.len())) {
+        pipeline.remove(0);
+    }
+    pipeline.insert(0, chart.to_string());
+}
+
+/// Whether the stored pipeline ends in a join that is still missing one of its
+/// two keys, the explorer then offers `file.column` names of every selected file.
+pub fn join_keys_pending(pipeline: &[String]) -> bool {
+    let is_join = |text: &String| {
+        matches!(Operator::from_keyword(text), Some(Operator::Join | Operator::LeftJoin | Operator::FullJoin))
+    };
+    match pipeline {
+        [.., last] if is_join(last) => true,
+        [.., join, _] => is_join(join),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_cache::cache_key;
+
+    #[test]
+    fn canonical_pipelines_keep_the_spelling_of_numbers() {
+        let cases = [
+            ("WHERE code CONTAINS 007", "WHERE code CONTAINS 7"),
+            ("FILL amount 0.50", "FILL amount 0.5"),
+            ("WHERE amount > 1e3", "WHERE amount > 1000"),
+        ];
+        for (left, right) in cases {
+            let left = canonical_pipeline(&pipeline_tokens(left));
+            let right = canonical_pipeline(&pipeline_tokens(right));
+            assert_ne!(left, right);
+            assert_ne!(cache_key("csvqb", &left, &[]), cache_key("csvqb", &right, &[]), "{}", left);
+        }
+        assert_eq!(
+            canonical_pipeline(&pipeline_tokens("FILL   amount 0.50")),
+            canonical_pipeline(&pipeline_tokens("FILL amount 0.50")),
+        );
+    }
+}
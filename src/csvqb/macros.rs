@@ -0,0 +1,125 @@
+use crate::csvqb::ast::BinaryOp;
+use crate::csvqb::error::{closest_match, CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::{pipeline_tokens, tokenize, Span};
+use crate::csvqb::Operator;
+
+/// A named sub-pipeline, called as `@name` followed by one value per parameter.
+/// `$param` in the body stands for the value passed for it, other placeholders
+/// are left for the session's parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: String,
+}
+
+impl Macro {
+    /// Checks a macro before it is saved, `Err` says what is wrong with it.
+    pub fn new(name: &str, params: &str, body: &str) -> Result<Macro, String> {
+        let is_name = |word: &str| !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || c == '_');
+        let name = name.trim().trim_start_matches('@');
+        if !is_name(name) {
+            return Err("a macro name is letters, digits and '_'".to_string());
+        }
+        let params: Vec<String> = params.split([' ', ',']).filter(|param| !param.is_empty())
+            .map(|param| param.trim_start_matches('$').to_string())
+            .collect();
+        if let Some(param) = params.iter().find(|param| !is_name(param)) {
+            return Err(format!("'{}' can't be a parameter name, use letters, digits and '_'", param));
+        }
+        tokenize(body).map_err(|err| format!("the body doesn't read: {}", err))?;
+        Ok(Macro { name: name.to_string(), params, body: body.trim().to_string() })
+    }
+
+    /// `@name $a $b`, how the macro is called.
+    pub fn signature(&self) -> String {
+        std::iter::once(format!("@{}", self.name))
+            .chain(self.params.iter().map(|param| format!("${}", param)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The pipeline with every macro call replaced by the macro's body, so parsing
+/// reads the stages of a macro as if they were written out. Macros may call
+/// other macros, but not themselves. The values a comparison reads (`email
+/// CONTAINS @home`) are never calls, and neither is a quoted `"@word"`.
+pub fn expand_macros(pipeline: &[String], macros: &[Macro]) -> Result<Vec<String>, CsvqbError> {
+    if !pipeline.iter().any(|token| token.starts_with('@')) {
+        return Ok(pipeline.to_vec());
+    }
+    let mut start = 0;
+    let spans: Vec<Span> = pipeline.iter()
+        .map(|token| {
+            let span = Span::new(start, start + token.len());
+            start = span.end + 1;
+            span
+        })
+        .collect();
+    expand(pipeline, &spans, macros, &mut Vec::new())
+}
+
+/// Expands the calls in `tokens`. Tokens that come from a macro body report
+/// errors at the call they come from, `calling` holds the macros being expanded.
+fn expand<'a>(
+    tokens: &[String],
+    spans: &[Span],
+    macros: &'a [Macro],
+    calling: &mut Vec<&'a str>
+) -> Result<Vec<String>, CsvqbError> {
+    let mut expanded = Vec::with_capacity(tokens.len());
+    let mut pos = 0;
+    while pos < tokens.len() {
+        let (token, span) = (&tokens[pos], spans[pos]);
+        pos += 1;
+        let name = token.strip_prefix('@').filter(|name| !name.is_empty());
+        let Some(name) = name.filter(|_| !is_compared_value(tokens, pos - 1)) else {
+            expanded.push(token.clone());
+            continue;
+        };
+
+        let Some(called) = macros.iter().find(|m| m.name == name) else {
+            let message = format!("unknown macro '{}', quote it (\"{}\") to use it as text", token, token);
+            return Err(CsvqbError::new(CsvqbErrorKind::UnknownMacro, message, span)
+                .with_token(token.clone())
+                .with_suggestion(closest_match(name, macros.iter().map(|m| m.name.as_str())).map(|name| format!("@{}", name))));
+        };
+        if calling.contains(&called.name.as_str()) {
+            let path: Vec<String> = calling.iter().chain([&called.name.as_str()]).map(|name| format!("@{}", name)).collect();
+            return Err(CsvqbError::syntax(format!("macro {} calls itself: {}", token, path.join(" -> ")), span)
+                .with_token(token.clone()));
+        }
+        if tokens.len() - pos < called.params.len() {
+            return Err(CsvqbError::syntax(
+                format!("{} expects a value for each of its parameters, e.g. '{}'", token, called.signature()),
+                span,
+            ).with_token(token.clone()));
+        }
+
+        let args = &tokens[pos..pos + called.params.len()];
+        let call_span = spans.get(pos + called.params.len() - 1).map_or(span, |last| span.to(*last));
+        pos += called.params.len();
+
+        let body: Vec<String> = pipeline_tokens(&called.body).into_iter()
+            .map(|token| {
+                let param = token.strip_prefix('$').and_then(|name| called.params.iter().position(|param| param == name));
+                match param {
+                    Some(idx) => args[idx].clone(),
+                    None => token,
+                }
+            })
+            .collect();
+        calling.push(&called.name);
+        expanded.extend(expand(&body, &vec![call_span; body.len()], macros, calling)?);
+        calling.pop();
+    }
+    Ok(expanded)
+}
+
+/// Whether the token at `pos` is a value a comparison reads, the one after a
+/// comparison keyword or the two after `BETWEEN`.
+fn is_compared_value(tokens: &[String], pos: usize) -> bool {
+    let keyword = |back: usize| pos.checked_sub(back).and_then(|idx| Operator::from_keyword(&tokens[idx]));
+    let compares = keyword(1).and_then(|op| BinaryOp::from_operator(&op)).is_some_and(|op| op.is_comparison());
+    compares || keyword(1) == Some(Operator::Between) || keyword(2) == Some(Operator::Between)
+}
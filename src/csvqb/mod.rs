@@ -0,0 +1,857 @@
+pub mod aggregate;
+pub mod ast;
+pub mod bins;
+pub mod columns;
+pub mod compute;
+pub mod dates;
+pub mod distinct;
+pub mod error;
+pub mod explain;
+pub mod join;
+pub mod lexer;
+pub mod macros;
+pub mod named;
+pub mod nulls;
+pub mod order;
+pub mod parallel;
+pub mod parser;
+pub mod relation;
+pub mod sql;
+pub mod text;
+pub mod window;
+
+use std::sync::Arc;
+use itertools::Itertools;
+use rayon::prelude::*;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::aggregate::{col_aggregate, col_pivot};
+use crate::csvqb::bins::bin_column;
+use crate::csvqb::ast::{BinaryOp, Condition, Expr, Ident, Pipeline, Stage};
+use crate::csvqb::columns::TypedTable;
+use crate::csvqb::compute::{check_row_wise, derive_column, is_row_arithmetic, row_values};
+use crate::csvqb::dates::{bucket_column, DateFormat};
+use crate::csvqb::distinct::{dedup_rows, distinct_rows};
+use crate::csvqb::error::{closest_match, CsvqbError, CsvqbErrorKind};
+use crate::csvqb::join::{join_files, joined_headers, resolve_join_key};
+use crate::csvqb::lexer::Span;
+use crate::csvqb::named::input_indexes;
+use crate::csvqb::nulls::{fill_column, NullTokens};
+use crate::csvqb::order::{limit_grid, sort_grid};
+use crate::csvqb::parallel::Progress;
+use crate::csvqb::parser::parse_pipeline;
+use crate::csvqb::relation::col_relation;
+use crate::csvqb::window::col_window;
+
+/// CIR is the Charting Intermediate Representation
+#[derive(Debug, Clone)]
+pub enum CIR {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    Field(String),
+    QueryResult(Vec<Vec<String>>),
+    /// A wide grid from `PIVOT`, the first column labels the rows and every
+    /// other column is a series.
+    Pivot(Vec<Vec<String>>),
+    /// Bins from `BIN`, a row per bin with its lower and upper edge and the
+    /// number of values in it.
+    Histogram(Vec<Vec<String>>),
+    /// Two columns related by `CCORR`, `CSPEARMAN`, `CCOV` or `CFIT`: the
+    /// pairs that were read, the `GRP` columns then x and y, and the result, a
+    /// row per group. A fit's slope and intercept are drawn as a trend line.
+    Relation { points: Vec<Vec<String>>, stats: Vec<Vec<String>> }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Sum,
+    Avg,
+    Count,
+    CountDistinct,
+    Min,
+    Max,
+    Median,
+    StdDev,
+    Var,
+    Percentile,
+    Correlation,
+    Spearman,
+    Covariance,
+    Fit,
+    RunningSum,
+    MovingAvg,
+    Lag,
+    Lead,
+    Rank,
+    Pivot,
+    GroupBy,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    As,
+    Equals,
+    NotEquals,
+    GreaterThan,
+    LessThan,
+    GreaterEq,
+    LessEq,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Matches,
+    Between,
+    Is,
+    Where,
+    And,
+    Or,
+    Not,
+    Join,
+    LeftJoin,
+    FullJoin,
+    Sort,
+    Asc,
+    Desc,
+    Limit,
+    Top,
+    By,
+    DateFormat,
+    Bucket,
+    Distinct,
+    Dedup,
+    Fill,
+    Bin,
+    Trim,
+    Lower,
+    Upper,
+    Substr,
+    Split,
+    Extract,
+    Replace,
+    From,
+    Name,
+}
+
+impl Operator {
+    pub const ALL: &'static [Operator] = &[
+        Operator::Sum,
+        Operator::Avg,
+        Operator::Count,
+        Operator::CountDistinct,
+        Operator::Min,
+        Operator::Max,
+        Operator::Median,
+        Operator::StdDev,
+        Operator::Var,
+        Operator::Percentile,
+        Operator::Correlation,
+        Operator::Spearman,
+        Operator::Covariance,
+        Operator::Fit,
+        Operator::RunningSum,
+        Operator::MovingAvg,
+        Operator::Lag,
+        Operator::Lead,
+        Operator::Rank,
+        Operator::Pivot,
+        Operator::GroupBy,
+        Operator::Add,
+        Operator::Sub,
+        Operator::Mul,
+        Operator::Div,
+        Operator::Mod,
+        Operator::Pow,
+        Operator::As,
+        Operator::Equals,
+        Operator::NotEquals,
+        Operator::GreaterThan,
+        Operator::LessThan,
+        Operator::GreaterEq,
+        Operator::LessEq,
+        Operator::Contains,
+        Operator::StartsWith,
+        Operator::EndsWith,
+        Operator::Matches,
+        Operator::Between,
+        Operator::Is,
+        Operator::Where,
+        Operator::And,
+        Operator::Or,
+        Operator::Not,
+        Operator::Join,
+        Operator::LeftJoin,
+        Operator::FullJoin,
+        Operator::Sort,
+        Operator::Asc,
+        Operator::Desc,
+        Operator::Limit,
+        Operator::Top,
+        Operator::By,
+        Operator::DateFormat,
+        Operator::Bucket,
+        Operator::Distinct,
+        Operator::Dedup,
+        Operator::Fill,
+        Operator::Bin,
+        Operator::Trim,
+        Operator::Lower,
+        Operator::Upper,
+        Operator::Substr,
+        Operator::Split,
+        Operator::Extract,
+        Operator::Replace,
+        Operator::From,
+        Operator::Name,
+    ];
+
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Operator::Sum => "CSUM",
+            Operator::Avg => "CAVG",
+            Operator::Count => "CCOUNT",
+            Operator::CountDistinct => "CCOUNTD",
+            Operator::Min => "CMIN",
+            Operator::Max => "CMAX",
+            Operator::Median => "CMEDIAN",
+            Operator::StdDev => "CSTDDEV",
+            Operator::Var => "CVAR",
+            Operator::Percentile => "CPCT",
+            Operator::Correlation => "CCORR",
+            Operator::Spearman => "CSPEARMAN",
+            Operator::Covariance => "CCOV",
+            Operator::Fit => "CFIT",
+            Operator::RunningSum => "CRUNSUM",
+            Operator::MovingAvg => "CMOVAVG",
+            Operator::Lag => "CLAG",
+            Operator::Lead => "CLEAD",
+            Operator::Rank => "CRANK",
+            Operator::Pivot => "PIVOT",
+            Operator::GroupBy => "GRP",
+            Operator::Add => "ADD",
+            Operator::Sub => "SUB",
+            Operator::Mul => "MUL",
+            Operator::Div => "DIV",
+            Operator::Mod => "MOD",
+            Operator::Pow => "POW",
+            Operator::As => "AS",
+            Operator::Equals => "=",
+            Operator::NotEquals => "!=",
+            Operator::GreaterThan => ">",
+            Operator::LessThan => "<",
+            Operator::GreaterEq => ">=",
+            Operator::LessEq => "<=",
+            Operator::Contains => "CONTAINS",
+            Operator::StartsWith => "STARTSWITH",
+            Operator::EndsWith => "ENDSWITH",
+            Operator::Matches => "MATCHES",
+            Operator::Between => "BETWEEN",
+            Operator::Is => "IS",
+            Operator::Where => "WHERE",
+            Operator::And => "AND",
+            Operator::Or => "OR",
+            Operator::Not => "NOT",
+            Operator::Join => "JOIN",
+            Operator::LeftJoin => "LEFTJOIN",
+            Operator::FullJoin => "FULLJOIN",
+            Operator::Sort => "SORT",
+            Operator::Asc => "ASC",
+            Operator::Desc => "DESC",
+            Operator::Limit => "LIMIT",
+            Operator::Top => "TOP",
+            Operator::By => "BY",
+            Operator::DateFormat => "DATEFMT",
+            Operator::Bucket => "BUCKET",
+            Operator::Distinct => "DISTINCT",
+            Operator::Dedup => "DEDUP",
+            Operator::Fill => "FILL",
+            Operator::Bin => "BIN",
+            Operator::Trim => "TRIM",
+            Operator::Lower => "LOWER",
+            Operator::Upper => "UPPER",
+            Operator::Substr => "SUBSTR",
+            Operator::Split => "SPLIT",
+            Operator::Extract => "EXTRACT",
+            Operator::Replace => "REPLACE",
+            Operator::From => "FROM",
+            Operator::Name => "=>",
+        }
+    }
+
+    pub fn from_keyword(keyword: &str) -> Option<Operator> {
+        Operator::ALL.iter().find(|op| op.keyword() == keyword).copied()
+    }
+}
+
+/// Keeps the rows of every selected file that match `condition`, testing the
+/// rows in parallel. Files stay separate so later aggregates still look up
+/// columns per file.
+fn filter_rows(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    condition: &Condition,
+    dates: &[(String, DateFormat)],
+    null_tokens: &NullTokens
+) -> Vec<(String, Arc<CsvGrid>)> {
+    let mut result = Vec::new();
+
+    for &file_idx in file_indexes {
+        if let Some((path, grid)) = csv_files.get(file_idx) {
+            if grid.is_empty() { continue; }
+
+            let headers = &grid[0];
+            let tokens = null_tokens.get(path);
+            let mut filtered = vec![headers.clone()];
+            filtered.par_extend(grid[1..].par_iter()
+                .filter(|row| condition.matches(headers, row, dates, tokens))
+                .cloned());
+            result.push((path.clone(), Arc::new(filtered)));
+        }
+    }
+
+    result
+}
+
+/// Stacks the rows of several grids under the header of the first one, lining
+/// columns up by name.
+fn combine_rows(file_indexes: &[usize], csv_files: &[(String, Arc<CsvGrid>)]) -> Vec<Vec<String>> {
+    let mut result: Vec<Vec<String>> = Vec::new();
+
+    for &file_idx in file_indexes {
+        if let Some((_, grid)) = csv_files.get(file_idx) {
+            let Some(headers) = grid.first() else { continue };
+            if result.is_empty() {
+                result.push(headers.clone());
+            }
+            let positions: Vec<Option<usize>> = result[0].iter()
+                .map(|h| headers.iter().position(|col| col == h))
+                .collect();
+
+            for row in grid.iter().skip(1) {
+                result.push(positions.iter()
+                    .map(|pos| pos.and_then(|idx| row.get(idx)).cloned().unwrap_or_default())
+                    .collect());
+            }
+        }
+    }
+
+    result
+}
+
+/// Parses a pipeline and evaluates it against the selected files. Nothing is
+/// evaluated when the pipeline doesn't parse or refers to unknown columns.
+/// What evaluation reads besides the files: the typed tables lined up with them,
+/// the null tokens of every file, the progress to report to, a tally per column
+/// of the values skipped because they aren't numeric and one per aggregate of
+/// the null values it left out. Grids made by an earlier stage (filters,
+/// joins, ...) have no table and are parsed as they are read.
+pub struct EvalContext<'a> {
+    pub tables: Vec<Option<&'a TypedTable>>,
+    pub null_tokens: &'a NullTokens,
+    pub progress: &'a Progress,
+    pub skipped: Vec<(String, usize)>,
+    pub nulls: Vec<(String, usize, usize)>,
+}
+
+impl EvalContext<'_> {
+    /// Adds what the aggregate `label` read of `column` to the tallies.
+    pub fn tally(&mut self, label: &str, column: &str, tally: Tally) {
+        if tally.skipped > 0 {
+            match self.skipped.iter_mut().find(|(name, _)| name == column) {
+                Some((_, total)) => *total += tally.skipped,
+                None => self.skipped.push((column.to_string(), tally.skipped)),
+            }
+        }
+        if tally.nulls > 0 {
+            match self.nulls.iter_mut().find(|(name, ..)| name == label) {
+                Some((_, nulls, rows)) => {
+                    *nulls += tally.nulls;
+                    *rows += tally.rows;
+                }
+                None => self.nulls.push((label.to_string(), tally.nulls, tally.rows)),
+            }
+        }
+    }
+}
+
+/// What an aggregate made of the cells it read: how many rows had the column,
+/// how many of those were null and how many weren't numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tally {
+    pub rows: usize,
+    pub nulls: usize,
+    pub skipped: usize,
+}
+
+/// Results of a pipeline, per column how many values were skipped because
+/// they aren't numeric, and per aggregate how many of the rows it read were
+/// null, next to how many it read.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOutput {
+    pub results: Vec<CIR>,
+    pub skipped: Vec<(String, usize)>,
+    pub nulls: Vec<(String, usize, usize)>,
+}
+
+/// `tables` holds the typed tables of `files` at the same indexes, files
+/// without one are parsed as they are read. Cancelling `progress` stops the
+/// evaluation with a `Cancelled` error.
+pub fn csvqb_to_cir(
+    qb_pipeline: &[String],
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    tables: &[Option<&TypedTable>],
+    null_tokens: &NullTokens,
+    progress: &Progress
+) -> Result<QueryOutput, CsvqbError> {
+    let pipeline = check_pipeline(qb_pipeline, file_indexes, files)?;
+    evaluate(&pipeline, file_indexes, files, tables, null_tokens, progress)
+}
+
+/// Parses a pipeline and resolves every column it mentions against the headers
+/// of the selected files, without running anything.
+pub fn check_pipeline(
+    qb_pipeline: &[String],
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)]
+) -> Result<Pipeline, CsvqbError> {
+    let pipeline = parse_pipeline(&qb_pipeline.join(" "))?;
+    let file_indexes = &input_indexes(&pipeline, file_indexes, files)?;
+
+    let mut headers: Vec<String> = file_indexes.iter()
+        .filter_map(|&idx| files.get(idx))
+        .filter_map(|(_, grid)| grid.first())
+        .flatten()
+        .unique()
+        .cloned()
+        .collect();
+    if headers.is_empty() && !pipeline.stages.is_empty() {
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::NoInput,
+            "none of the selected files are loaded",
+            Span::default(),
+        ));
+    }
+
+    // Columns of the aggregate results, which SORT can order by as well.
+    let mut result_columns: Vec<String> = Vec::new();
+    let mut pivoted = false;
+
+    for stage in &pipeline.stages {
+        match stage {
+            Stage::Group { columns, .. } => {
+                for column in columns {
+                    resolve_column(&headers, column, false)?;
+                }
+            }
+            Stage::Filter { condition, .. } => condition.visit_columns(&mut |column| resolve_column(&headers, column, false))?,
+            Stage::Join { left, right, .. } => {
+                let (left_idx, _) = resolve_join_key(left, file_indexes, files)?;
+                let (right_idx, right_column) = resolve_join_key(right, file_indexes, files)?;
+                if left_idx == right_idx {
+                    return Err(CsvqbError::new(
+                        CsvqbErrorKind::TypeMismatch,
+                        "JOIN needs keys from two different files",
+                        left.span.to(right.span),
+                    ).with_token(right.name.clone()));
+                }
+
+                let (right_path, right_grid) = &files[right_idx];
+                let right_headers = &right_grid[0];
+                let right_key = right_headers.iter().position(|h| *h == right_column).unwrap_or_default();
+                for header in joined_headers(&files[left_idx].1[0], right_path, right_headers, right_key) {
+                    if !headers.contains(&header) {
+                        headers.push(header);
+                    }
+                }
+            }
+            // The columns of a pivot are only known once the data is read.
+            Stage::Sort { .. } if pivoted => {}
+            Stage::Sort { column, .. } => {
+                let candidates: Vec<String> = headers.iter().chain(&result_columns).cloned().collect();
+                resolve_column(&candidates, column, false)?;
+            }
+            Stage::Limit { .. } => {}
+            Stage::Dates { column, .. } | Stage::Bucket { column, .. } | Stage::Fill { column, .. } => resolve_column(&headers, column, false)?,
+            Stage::Dedup { columns, .. } => {
+                for column in columns {
+                    resolve_column(&headers, column, false)?;
+                }
+            }
+            Stage::Derive { expr, name, .. } => {
+                check_columns(expr, &headers, &mut result_columns)?;
+                check_row_wise(expr)?;
+                if !headers.contains(&name.name) {
+                    headers.push(name.name.clone());
+                }
+            }
+            Stage::Expr(expr) => {
+                check_columns(expr, &headers, &mut result_columns)?;
+                pivoted |= matches!(expr, Expr::Pivot { .. });
+                if is_row_arithmetic(expr) {
+                    check_row_wise(expr)?;
+                }
+            }
+        }
+    }
+
+    Ok(pipeline)
+}
+
+/// Resolves the columns an expression reads and records the result columns of its aggregates.
+fn check_columns(expr: &Expr, headers: &[String], result_columns: &mut Vec<String>) -> Result<(), CsvqbError> {
+    expr.visit(&mut |expr| match expr {
+        Expr::Aggregate { func, column, .. } => {
+            result_columns.push(func.result_name());
+            resolve_column(headers, column, false)
+        }
+        Expr::Pivot { rows, columns, value, .. } => {
+            resolve_column(headers, rows, false)?;
+            resolve_column(headers, columns, false)?;
+            resolve_column(headers, value, false)
+        }
+        Expr::Distinct { columns, .. } => columns.iter().try_for_each(|column| resolve_column(headers, column, false)),
+        Expr::Relation { func, x, y, .. } => {
+            result_columns.extend(func.result_names().iter().map(|name| name.to_string()));
+            resolve_column(headers, x, false)?;
+            resolve_column(headers, y, false)
+        }
+        Expr::Bin { column, .. } => {
+            result_columns.extend(["from", "to", "count"].map(String::from));
+            resolve_column(headers, column, false)
+        }
+        // A string function reads a column, never an operator.
+        Expr::Text { arg, .. } => match arg.as_ref() {
+            Expr::Field(column) => resolve_column(headers, column, false),
+            _ => Ok(()),
+        },
+        Expr::Window { func, column, order, .. } => {
+            result_columns.push(func.result_name());
+            resolve_column(headers, column, false)?;
+            order.as_ref().map_or(Ok(()), |order| resolve_column(headers, order, false))
+        }
+        Expr::Field(ident) => resolve_column(headers, ident, true),
+        _ => Ok(()),
+    })
+}
+
+fn resolve_column(headers: &[String], ident: &Ident, allow_operator: bool) -> Result<(), CsvqbError> {
+    if headers.contains(&ident.name) {
+        return Ok(());
+    }
+    let mut candidates: Vec<&str> = headers.iter().map(|h| h.as_str()).collect();
+    let message = if allow_operator {
+        candidates.extend(Operator::ALL.iter().map(|op| op.keyword()));
+        format!("unknown column or operator '{}'", ident.name)
+    } else {
+        format!("unknown column '{}'", ident.name)
+    };
+    Err(CsvqbError::new(CsvqbErrorKind::UnknownColumn, message, ident.span)
+        .with_token(ident.name.clone())
+        .with_suggestion(closest_match(&ident.name, candidates)))
+}
+
+pub fn evaluate(
+    pipeline: &Pipeline,
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    tables: &[Option<&TypedTable>],
+    null_tokens: &NullTokens,
+    progress: &Progress
+) -> Result<QueryOutput, CsvqbError> {
+    let file_indexes = &input_indexes(pipeline, file_indexes, files)?;
+    let mut results: Vec<CIR> = Vec::new();
+    let mut context = EvalContext { tables: tables.to_vec(), null_tokens, progress, skipped: Vec::new(), nulls: Vec::new() };
+    let mut capture_group: Vec<String> = Vec::new();
+    let mut last_output: Option<&Expr> = None;
+    let (selected_indexes, selected_files) = (file_indexes, files);
+    // Files as narrowed by filters and joins, `None` until the first one runs.
+    let mut working: Option<Vec<(String, Arc<CsvGrid>)>> = None;
+    let mut date_formats: Vec<(String, DateFormat)> = Vec::new();
+
+    if let Some(chart) = &pipeline.chart {
+        results.push(CIR::Field(chart.name.clone()));
+    }
+
+    progress.start(pipeline.stages.len());
+    for stage in &pipeline.stages {
+        let working_indexes: Vec<usize>;
+        let (file_indexes, files): (&[usize], &[(String, Arc<CsvGrid>)]) = match &working {
+            Some(working) => {
+                // Grids made by earlier stages have no typed table.
+                context.tables.clear();
+                working_indexes = (0..working.len()).collect();
+                (&working_indexes, working)
+            }
+            None => (file_indexes, files),
+        };
+
+        let group_by = if !capture_group.is_empty() {
+            Some(capture_group.as_slice())
+        } else {
+            None
+        };
+
+        match stage {
+            Stage::Group { columns, .. } => {
+                capture_group.extend(columns.iter().map(|column| column.name.clone()));
+            }
+            Stage::Filter { condition, .. } => {
+                working = Some(filter_rows(file_indexes, files, condition, &date_formats, context.null_tokens));
+            }
+            Stage::Dates { column, format, .. } => {
+                date_formats.retain(|(name, _)| *name != column.name);
+                date_formats.push((column.name.clone(), *format));
+            }
+            Stage::Bucket { column, unit, .. } => {
+                let format = date_formats.iter()
+                    .find(|(name, _)| *name == column.name)
+                    .map(|(_, format)| *format)
+                    .unwrap_or_default();
+                working = Some(bucket_column(file_indexes, files, &context.tables, &column.name, *unit, format));
+            }
+            Stage::Dedup { columns, .. } => {
+                let columns: Vec<String> = columns.iter().map(|column| column.name.clone()).collect();
+                working = Some(dedup_rows(file_indexes, files, &columns));
+            }
+            Stage::Fill { column, value, .. } => {
+                working = Some(fill_column(file_indexes, files, context.null_tokens, &column.name, &value.text));
+            }
+            Stage::Join { kind, left, right, .. } => {
+                // Keys name a file of the working set, or one of the selected files
+                // that hasn't been joined in yet.
+                let lookup = |key: &Ident| resolve_join_key(key, file_indexes, files)
+                    .map(|(idx, column)| (&files[idx], column))
+                    .or_else(|err| match working {
+                        Some(_) => resolve_join_key(key, selected_indexes, selected_files)
+                            .map(|(idx, column)| (&selected_files[idx], column)),
+                        None => Err(err),
+                    });
+                let (left_file, left_column) = lookup(left)?;
+                let (right_file, right_column) = lookup(right)?;
+                let joined = join_files(left_file, &left_column, right_file, &right_column, *kind);
+                working = Some(vec![(left_file.0.clone(), Arc::new(joined))]);
+            }
+            Stage::Derive { expr, name, .. } => {
+                let mut scalars = Vec::new();
+                row_scalars(expr, "AS", file_indexes, files, group_by, &mut context, &mut scalars)?;
+                working = Some(derive_column(file_indexes, files, &name.name, expr, &scalars));
+            }
+            Stage::Expr(expr) => {
+                match eval_expr(expr, file_indexes, files, group_by, &mut context)? {
+                    CIR::Number(value) if matches!(expr, Expr::Binary { .. }) => {
+                        results.push(CIR::Number(value));
+                        results.push(CIR::Field(expr.label()));
+                    }
+                    value => results.push(value),
+                }
+                last_output = Some(expr);
+            }
+            Stage::Sort { .. } | Stage::Limit { .. } => {
+                let order = |grid: &mut Vec<Vec<String>>| match stage {
+                    Stage::Sort { column, descending, .. } => sort_grid(grid, column, *descending),
+                    Stage::Limit { count, .. } => {
+                        limit_grid(grid, *count);
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                };
+
+                match (last_output, results.last_mut()) {
+                    (Some(_), Some(CIR::QueryResult(grid) | CIR::Pivot(grid) | CIR::Histogram(grid) | CIR::Relation { stats: grid, .. })) => order(grid)?,
+                    (Some(expr), _) => return Err(CsvqbError::new(
+                        CsvqbErrorKind::TypeMismatch,
+                        format!("'{}' is a single value, there are no rows to order", expr.label()),
+                        stage.span(),
+                    )),
+                    (None, _) => {
+                        // Rows of several files are ordered as one list.
+                        let name = file_indexes.first()
+                            .and_then(|&idx| files.get(idx))
+                            .map(|(path, _)| path.clone())
+                            .unwrap_or_default();
+                        let mut grid = combine_rows(file_indexes, files);
+                        order(&mut grid)?;
+                        working = Some(vec![(name, Arc::new(grid))]);
+                    }
+                }
+            }
+        }
+
+        // Stages that were cut short leave partial results behind.
+        if progress.is_cancelled() {
+            return Err(CsvqbError::new(CsvqbErrorKind::Cancelled, "the pipeline was cancelled", stage.span()));
+        }
+        progress.finish_stage();
+    }
+
+    // A pipeline that only filters, joins or orders shows the rows that are left.
+    let has_output = pipeline.stages.iter().any(|stage| matches!(stage, Stage::Expr(_)));
+    if let (Some(working), false) = (&working, has_output) {
+        let indexes: Vec<usize> = (0..working.len()).collect();
+        results.push(CIR::QueryResult(combine_rows(&indexes, working)));
+    }
+
+    Ok(QueryOutput { results, skipped: context.skipped, nulls: context.nulls })
+}
+
+fn eval_expr(
+    expr: &Expr,
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    group_by: Option<&[String]>,
+    context: &mut EvalContext
+) -> Result<CIR, CsvqbError> {
+    match expr {
+        Expr::Number { value, .. } => Ok(CIR::Number(*value)),
+        Expr::Field(ident) => Ok(CIR::Field(ident.name.clone())),
+        Expr::Aggregate { func, column, .. } => {
+            let (grid, tally) = col_aggregate(file_indexes, files, context, &column.name, group_by, *func);
+            context.tally(&format!("{} of {}", func.result_name(), column.name), &column.name, tally);
+            Ok(CIR::QueryResult(grid))
+        }
+        Expr::Pivot { rows, columns, func, value, .. } => {
+            let (grid, tally) = col_pivot(
+                file_indexes,
+                files,
+                context,
+                &rows.name,
+                &columns.name,
+                &value.name,
+                *func,
+            );
+            context.tally(&format!("{} of {}", func.result_name(), expr.label()), &value.name, tally);
+            Ok(CIR::Pivot(grid))
+        }
+        Expr::Distinct { columns, .. } => {
+            // Group columns come first, like the keys of a grouped aggregate.
+            let columns: Vec<String> = group_by.unwrap_or_default().iter()
+                .chain(columns.iter().map(|column| &column.name))
+                .unique()
+                .cloned()
+                .collect();
+            Ok(CIR::QueryResult(distinct_rows(file_indexes, files, context, &columns)))
+        }
+        Expr::Relation { func, x, y, .. } => {
+            let (points, stats, tally, y_skipped) = col_relation(file_indexes, files, context, &x.name, &y.name, group_by, *func);
+            context.tally(&expr.label(), &x.name, tally);
+            context.tally(&expr.label(), &y.name, Tally { skipped: y_skipped, ..Tally::default() });
+            Ok(CIR::Relation { points, stats })
+        }
+        Expr::Bin { column, method, span } => {
+            let (grid, tally) = bin_column(file_indexes, files, context, &column.name, *method, *span)?;
+            context.tally(&expr.label(), &column.name, tally);
+            Ok(CIR::Histogram(grid))
+        }
+        Expr::Window { func, column, order, .. } => {
+            let (grid, tally) = col_window(
+                file_indexes,
+                files,
+                context,
+                &column.name,
+                order.as_ref().map(|order| order.name.as_str()),
+                group_by,
+                *func,
+            );
+            context.tally(&expr.label(), &column.name, tally);
+            Ok(CIR::QueryResult(grid))
+        }
+        Expr::Not { expr: inner, .. } => match eval_expr(inner, file_indexes, files, group_by, context)? {
+            CIR::Bool(value) => Ok(CIR::Bool(!value)),
+            _ => Err(CsvqbError::new(
+                CsvqbErrorKind::TypeMismatch,
+                format!("NOT needs a comparison but '{}' isn't one", inner.label()),
+                inner.span(),
+            ).with_token("NOT")),
+        },
+        Expr::Binary { op, lhs, rhs, .. } if op.is_logical() => {
+            let left = eval_expr(lhs, file_indexes, files, group_by, context)?;
+            let right = eval_expr(rhs, file_indexes, files, group_by, context)?;
+            match (left, right) {
+                (CIR::Bool(left), CIR::Bool(right)) => Ok(CIR::Bool(match op {
+                    BinaryOp::And => left && right,
+                    _ => left || right,
+                })),
+                _ => Err(CsvqbError::new(
+                    CsvqbErrorKind::TypeMismatch,
+                    format!("{} combines comparisons, e.g. 'CSUM a > 10 {} CSUM b < 5'", op.symbol(), op.symbol()),
+                    expr.span(),
+                ).with_token(op.symbol())),
+            }
+        }
+        Expr::Text { .. } => Ok(CIR::QueryResult(row_values(file_indexes, files, expr, &[]))),
+        Expr::Binary { .. } if is_row_arithmetic(expr) => {
+            let mut scalars = Vec::new();
+            row_scalars(expr, "AS", file_indexes, files, group_by, context, &mut scalars)?;
+            Ok(CIR::QueryResult(row_values(file_indexes, files, expr, &scalars)))
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            let left = scalar(op.symbol(), lhs, eval_expr(lhs, file_indexes, files, group_by, context)?)?;
+            let right = scalar(op.symbol(), rhs, eval_expr(rhs, file_indexes, files, group_by, context)?)?;
+
+            match op {
+                op if op.is_arithmetic() => op.arithmetic(left, right).map(CIR::Number).ok_or_else(|| {
+                    CsvqbError::new(
+                        CsvqbErrorKind::TypeMismatch,
+                        format!("'{}' divides by zero", expr.label()),
+                        expr.span(),
+                    ).with_token(op.symbol())
+                }),
+                op => op.compare_numbers(left, right).map(CIR::Bool).ok_or_else(|| {
+                    CsvqbError::new(
+                        CsvqbErrorKind::TypeMismatch,
+                        format!("{} compares text, use it on a column e.g. 'WHERE name {} value'", op.symbol(), op.symbol()),
+                        expr.span(),
+                    ).with_token(op.symbol())
+                }),
+            }
+        }
+    }
+}
+
+/// Values of the aggregates inside a row wise expression, keyed by their span.
+fn row_scalars(
+    expr: &Expr,
+    keyword: &str,
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    group_by: Option<&[String]>,
+    context: &mut EvalContext,
+    scalars: &mut Vec<(Span, f64)>
+) -> Result<(), CsvqbError> {
+    match expr {
+        Expr::Aggregate { span, .. } => {
+            let value = scalar(keyword, expr, eval_expr(expr, file_indexes, files, group_by, context)?)?;
+            scalars.push((*span, value));
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            row_scalars(lhs, op.symbol(), file_indexes, files, group_by, context, scalars)?;
+            row_scalars(rhs, op.symbol(), file_indexes, files, group_by, context, scalars)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Numbers, or the value of an aggregate that produced a single row.
+fn scalar(keyword: &str, operand: &Expr, value: CIR) -> Result<f64, CsvqbError> {
+    let mismatch = |message: String| {
+        CsvqbError::new(CsvqbErrorKind::TypeMismatch, message, operand.span())
+            .with_token(keyword)
+    };
+
+    match value {
+        CIR::Number(num) => Ok(num),
+        CIR::QueryResult(grid) | CIR::Relation { stats: grid, .. } if grid.len() == 2 => grid[1].last()
+            .and_then(|cell| cell.parse::<f64>().ok())
+            .ok_or_else(|| mismatch(format!("'{}' needs a number but '{}' isn't numeric", keyword, operand.label()))),
+        CIR::QueryResult(grid) | CIR::Relation { stats: grid, .. } => Err(mismatch(format!(
+            "'{}' needs a single value but '{}' returned {} rows, remove GRP or aggregate it first",
+            keyword,
+            operand.label(),
+            grid.len().saturating_sub(1)
+        ))),
+        _ => Err(mismatch(format!("'{}' needs a number, got '{}'", keyword, operand.label()))),
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::{Ident, Pipeline};
+use crate::csvqb::error::{closest_match, CsvqbError, CsvqbErrorKind};
+use crate::csvqb::join::file_stem;
+use crate::csvqb::parser::parse_pipeline;
+use crate::csvqb::{check_pipeline, CIR};
+
+/// Results of the pipelines that end in `=> name`, by that name.
+pub type NamedResults = HashMap<String, Arc<CsvGrid>>;
+
+/// Which of `files` a pipeline reads: the ones its `FROM` names, or the
+/// selected ones when it has none.
+pub fn input_indexes(
+    pipeline: &Pipeline,
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)]
+) -> Result<Vec<usize>, CsvqbError> {
+    if pipeline.sources.is_empty() {
+        return Ok(file_indexes.to_vec());
+    }
+    pipeline.sources.iter()
+        .map(|source| {
+            files.iter()
+                .position(|(path, _)| *path == source.name || file_stem(path) == source.name)
+                .ok_or_else(|| unknown_source(source, files.iter().map(|(path, _)| file_stem(path))))
+        })
+        .collect()
+}
+
+/// The tables `FROM` reads, named results first and loaded files by name after.
+/// With `headers_only` the tables keep just their header, enough to check a
+/// pipeline against.
+pub fn source_tables(
+    sources: &[Ident],
+    files: &[(String, Arc<CsvGrid>)],
+    named: &NamedResults,
+    headers_only: bool
+) -> Result<Vec<(String, Arc<CsvGrid>)>, CsvqbError> {
+    sources.iter()
+        .map(|source| {
+            let grid = named.get(&source.name)
+                .or_else(|| files.iter()
+                    .find(|(path, _)| *path == source.name || file_stem(path) == source.name)
+                    .map(|(_, grid)| grid))
+                .ok_or_else(|| unknown_source(
+                    source,
+                    named.keys().map(String::as_str).chain(files.iter().map(|(path, _)| file_stem(path))),
+                ))?;
+            let grid = if headers_only { Arc::new(grid.iter().take(1).cloned().collect()) } else { grid.clone() };
+            Ok((source.name.clone(), grid))
+        })
+        .collect()
+}
+
+/// `check_pipeline` for a pipeline of the explorer, which may read named results
+/// that only exist once the pipeline naming them ran.
+pub fn check_with_named(
+    qb_pipeline: &[String],
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    named: &NamedResults
+) -> Result<Pipeline, CsvqbError> {
+    let pipeline = parse_pipeline(&qb_pipeline.join(" "))?;
+    if pipeline.sources.is_empty() {
+        return check_pipeline(qb_pipeline, file_indexes, files);
+    }
+    let inputs = source_tables(&pipeline.sources, files, named, true)?;
+    check_pipeline(qb_pipeline, &(0..inputs.len()).collect::<Vec<_>>(), &inputs)
+}
+
+fn unknown_source<'a>(source: &Ident, candidates: impl IntoIterator<Item = &'a str>) -> CsvqbError {
+    CsvqbError::new(
+        CsvqbErrorKind::UnknownColumn,
+        format!("nothing is called '{}', FROM reads a loaded file or a result named with '=> {}' once its pipeline ran", source.name, source.name),
+        source.span,
+    ).with_token(source.name.clone())
+        .with_suggestion(closest_match(&source.name, candidates))
+}
+
+/// The table a pipeline ending in `=> name` stores: its last grid, or its last
+/// number as a single cell.
+pub fn result_table(results: &[CIR]) -> Option<CsvGrid> {
+    results.iter().rev().find_map(|cir| match cir {
+        CIR::QueryResult(grid) | CIR::Pivot(grid) | CIR::Histogram(grid) | CIR::Relation { stats: grid, .. } => Some(grid.clone()),
+        CIR::Number(value) => Some(vec![vec!["value".to_string()], vec![value.to_string()]]),
+        _ => None,
+    })
+}
+
+/// The name a stored pipeline gives its result and the names it reads with
+/// `FROM`. Pipelines that don't parse name and read nothing.
+pub fn pipeline_links(qb_pipeline: &[String]) -> (Option<Ident>, Vec<Ident>) {
+    match parse_pipeline(&qb_pipeline.join(" ")) {
+        Ok(pipeline) => (pipeline.name, pipeline.sources),
+        Err(_) => (None, Vec::new()),
+    }
+}
+
+/// Order to run pipelines in so each one runs after the pipelines whose results
+/// it reads, otherwise keeping the order they are given in. Pipelines that read
+/// each other in a circle, read from such a circle, or take a name another
+/// pipeline already has come back as errors instead.
+pub fn run_order<K: Copy>(pipelines: &[(K, Vec<String>)]) -> (Vec<K>, Vec<(K, CsvqbError)>) {
+    let links: Vec<(Option<Ident>, Vec<Ident>)> = pipelines.iter().map(|(_, pipeline)| pipeline_links(pipeline)).collect();
+    let mut errors = Vec::new();
+
+    let mut owners: HashMap<&str, usize> = HashMap::new();
+    let mut runnable = vec![true; pipelines.len()];
+    for (idx, (name, _)) in links.iter().enumerate() {
+        let Some(name) = name else { continue };
+        if owners.contains_key(name.name.as_str()) {
+            errors.push((pipelines[idx].0, CsvqbError::new(
+                CsvqbErrorKind::Dependency,
+                format!("'{}' already names the result of another pipeline", name.name),
+                name.span,
+            ).with_token(name.name.clone())));
+            runnable[idx] = false;
+        } else {
+            owners.insert(&name.name, idx);
+        }
+    }
+
+    // Pipelines each one reads from, names nobody defines are files.
+    let depends: Vec<Vec<usize>> = links.iter()
+        .map(|(_, sources)| sources.iter().filter_map(|source| owners.get(source.name.as_str()).copied()).collect())
+        .collect();
+
+    let mut done = vec![false; pipelines.len()];
+    let mut order = Vec::new();
+    while let Some(next) = (0..pipelines.len()).find(|&idx| {
+        runnable[idx] && !done[idx] && depends[idx].iter().all(|&dep| done[dep])
+    }) {
+        done[next] = true;
+        order.push(pipelines[next].0);
+    }
+
+    for idx in (0..pipelines.len()).filter(|&idx| runnable[idx] && !done[idx]) {
+        // Following unfinished dependencies has to come back around somewhere.
+        let mut path = vec![idx];
+        let mut current = idx;
+        let start = loop {
+            current = depends[current].iter().copied().find(|&dep| !done[dep]).unwrap_or(current);
+            if let Some(pos) = path.iter().position(|&seen| seen == current) {
+                break pos;
+            }
+            path.push(current);
+        };
+        let circle: Vec<&str> = path[start..].iter().chain(&path[start..start + 1])
+            .filter_map(|&idx| links[idx].0.as_ref().map(|name| name.name.as_str()))
+            .collect();
+
+        let source = links[idx].1.iter()
+            .find(|source| owners.get(source.name.as_str()).is_some_and(|&owner| !done[owner]))
+            .unwrap_or(&links[idx].1[0]);
+        let message = if start == 0 {
+            format!("'{}' reads its own result through {}", source.name, circle.join(" -> "))
+        } else {
+            format!("'{}' depends on pipelines that read each other: {}", source.name, circle.join(" -> "))
+        };
+        errors.push((pipelines[idx].0, CsvqbError::new(CsvqbErrorKind::Dependency, message, source.span)
+            .with_token(source.name.clone())));
+    }
+
+    (order, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csvqb::lexer::pipeline_tokens;
+
+    fn pipelines(sources: &[&str]) -> Vec<(usize, Vec<String>)> {
+        sources.iter().enumerate().map(|(idx, src)| (idx, pipeline_tokens(src))).collect()
+    }
+
+    fn messages(errors: &[(usize, CsvqbError)]) -> Vec<(usize, String)> {
+        errors.iter().map(|(idx, error)| (*idx, error.message.clone())).collect()
+    }
+
+    #[test]
+    fn readers_run_after_the_pipelines_they_read() {
+        let (order, errors) = run_order(&pipelines(&[
+            "FROM monthly CSUM amount",
+            "FROM sales GRP month CSUM amount => monthly",
+            "CAVG qty",
+        ]));
+        assert_eq!(order, vec![1, 0, 2]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn circles_are_errors() {
+        let (order, errors) = run_order(&pipelines(&[
+            "FROM b CSUM amount => a",
+            "FROM a CSUM amount => b",
+            "FROM a CSUM qty",
+            "FROM c CSUM amount => c",
+            "CAVG qty",
+        ]));
+        assert_eq!(order, vec![4]);
+        assert_eq!(messages(&errors), vec![
+            (0, "'b' reads its own result through a -> b -> a".to_string()),
+            (1, "'a' reads its own result through b -> a -> b".to_string()),
+            (2, "'a' depends on pipelines that read each other: a -> b -> a".to_string()),
+            (3, "'c' reads its own result through c -> c".to_string()),
+        ]);
+        assert!(errors.iter().all(|(_, error)| error.kind == CsvqbErrorKind::Dependency));
+    }
+
+    #[test]
+    fn duplicate_names_are_errors() {
+        let (order, errors) = run_order(&pipelines(&[
+            "CSUM amount => total",
+            "CSUM qty => total",
+            "FROM total CAVG value",
+        ]));
+        assert_eq!(order, vec![0, 2]);
+        assert_eq!(messages(&errors), vec![
+            (1, "'total' already names the result of another pipeline".to_string()),
+        ]);
+        assert_eq!(errors[0].1.token.as_deref(), Some("total"));
+    }
+}
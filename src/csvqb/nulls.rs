@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use serde_json::{json, Value};
+use crate::charter_utilities::CsvGrid;
+
+/// Cells that stand for a missing value in a file that wasn't given tokens of
+/// its own. `sqlite_cir_adapter` writes SQL nulls as `NULL`.
+pub const DEFAULT_NULL_TOKENS: &[&str] = &["NULL", "N/A"];
+
+/// The cells each file uses for a missing value, by file path. An empty cell
+/// is always null, tokens are compared with the trimmed cell ignoring case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NullTokens {
+    default: Vec<String>,
+    files: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for NullTokens {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_NULL_TOKENS.iter().map(|token| token.to_string()).collect(),
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl NullTokens {
+    /// Tokens of the file at `path`, the default ones unless it was given its own.
+    pub fn get(&self, path: &str) -> &[String] {
+        self.files.get(path).unwrap_or(&self.default)
+    }
+
+    /// Gives the file at `path` its own tokens, or the default ones back when
+    /// `tokens` are the same.
+    pub fn set(&mut self, path: &str, tokens: Vec<String>) {
+        if tokens == self.default {
+            self.files.remove(path);
+        } else {
+            self.files.insert(path.to_string(), tokens);
+        }
+    }
+
+    /// The tokens of `paths`, written out so a cached result can tell which ones it was read with.
+    pub fn describe<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> String {
+        paths.into_iter()
+            .map(|path| format!("{}:{}", path, self.get(path).join("|")))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self.files)
+    }
+
+    /// Tokens saved by `to_json`, files whose entry doesn't read keep the default ones.
+    pub fn from_json(value: &Value) -> NullTokens {
+        let mut tokens = NullTokens::default();
+        if let Some(files) = value.as_object() {
+            for (path, file_tokens) in files {
+                if let Ok(file_tokens) = serde_json::from_value(file_tokens.clone()) {
+                    tokens.set(path, file_tokens);
+                }
+            }
+        }
+        tokens
+    }
+}
+
+/// Whether `cell` is missing, given the null tokens of its file.
+pub fn is_null(cell: &str, tokens: &[String]) -> bool {
+    let cell = cell.trim();
+    cell.is_empty() || tokens.iter().any(|token| token.eq_ignore_ascii_case(cell))
+}
+
+/// Tokens as written in the explorer, separated by commas.
+pub fn parse_tokens(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Replaces the null cells of `column` with `value` in every selected file. A
+/// file without the column gets it, filled with `value` on every row.
+pub fn fill_column(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    null_tokens: &NullTokens,
+    column: &str,
+    value: &str
+) -> Vec<(String, Arc<CsvGrid>)> {
+    let mut result = Vec::new();
+
+    for &file_idx in file_indexes {
+        let Some((path, grid)) = csv_files.get(file_idx) else { continue };
+        let Some(headers) = grid.first() else { continue };
+        let tokens = null_tokens.get(path);
+        let mut grid = grid.as_ref().clone();
+
+        match headers.iter().position(|h| h == column) {
+            Some(col_idx) => {
+                for row in grid.iter_mut().skip(1) {
+                    if row.len() <= col_idx {
+                        row.resize(col_idx + 1, String::new());
+                    }
+                    if is_null(&row[col_idx], tokens) {
+                        row[col_idx] = value.to_string();
+                    }
+                }
+            }
+            None => {
+                grid[0].push(column.to_string());
+                let width = grid[0].len();
+                for row in grid.iter_mut().skip(1) {
+                    row.resize(width - 1, String::new());
+                    row.push(value.to_string());
+                }
+            }
+        }
+        result.push((path.clone(), Arc::new(grid)));
+    }
+
+    result
+}
@@ -0,0 +1,55 @@
+use std::cmp::Ordering;
+use crate::csvqb::ast::Ident;
+use crate::csvqb::error::{closest_match, CsvqbError, CsvqbErrorKind};
+
+/// Orders two cells as numbers when both are numeric, otherwise numbers come
+/// before text and text is compared as is.
+pub fn compare_cells(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Compares two rows cell by cell over the first `len` columns.
+pub fn compare_keys(a: &[String], b: &[String], len: usize) -> Ordering {
+    a.iter().zip(b).take(len)
+        .map(|(a, b)| compare_cells(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Sorts the rows under the header by `column`. The sort is stable, so ties keep
+/// their previous order, and empty cells go last either way so they never end up
+/// in a `TOP n`.
+pub fn sort_grid(grid: &mut [Vec<String>], column: &Ident, descending: bool) -> Result<(), CsvqbError> {
+    let Some((headers, rows)) = grid.split_first_mut() else { return Ok(()) };
+    let Some(col_idx) = headers.iter().position(|h| *h == column.name) else {
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::UnknownColumn,
+            format!("can't sort by '{}', the rows have the columns {}", column.name, headers.join(", ")),
+            column.span,
+        ).with_token(column.name.clone())
+            .with_suggestion(closest_match(&column.name, headers.iter().map(|h| h.as_str()))));
+    };
+
+    rows.sort_by(|a, b| {
+        let a = a.get(col_idx).map(|cell| cell.as_str()).unwrap_or_default();
+        let b = b.get(col_idx).map(|cell| cell.as_str()).unwrap_or_default();
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ if descending => compare_cells(b, a),
+            _ => compare_cells(a, b),
+        }
+    });
+    Ok(())
+}
+
+/// Keeps the header and the first `count` rows.
+pub fn limit_grid(grid: &mut Vec<Vec<String>>, count: usize) {
+    grid.truncate(count + 1);
+}
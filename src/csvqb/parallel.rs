@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use rayon::prelude::*;
+use crate::charter_utilities::CsvGrid;
+
+/// Rows per chunk of parallel work. Chunks have a fixed size and their results
+/// are combined in order, so the output is the same however many threads ran.
+pub const CHUNK_ROWS: usize = 64 * 1024;
+
+/// A run of rows under the header of one of the selected files.
+pub struct Chunk<'a> {
+    pub file_idx: usize,
+    pub headers: &'a [String],
+    /// Row number of the first row in `rows`, 0 is the first row under the header.
+    pub offset: usize,
+    pub rows: &'a [Vec<String>],
+}
+
+/// How far a running pipeline got, shared with the thread that shows it. Also
+/// carries the request to stop, which is checked between stages and chunks.
+#[derive(Debug, Default)]
+pub struct Progress {
+    cancelled: AtomicBool,
+    stages: AtomicUsize,
+    stages_done: AtomicUsize,
+    chunks: AtomicUsize,
+    chunks_done: AtomicUsize,
+}
+
+impl Progress {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Whether evaluation has started.
+    pub fn is_started(&self) -> bool {
+        self.stages.load(Ordering::Relaxed) > 0
+    }
+
+    /// Share of the work done, between 0 and 1. The stage that is running counts
+    /// for the share of its chunks that are done.
+    pub fn fraction(&self) -> f32 {
+        let stages = self.stages.load(Ordering::Relaxed);
+        if stages == 0 {
+            return 0.0;
+        }
+        let chunks = self.chunks.load(Ordering::Relaxed);
+        let in_stage = match chunks {
+            0 => 0.0,
+            chunks => self.chunks_done.load(Ordering::Relaxed) as f32 / chunks as f32,
+        };
+        ((self.stages_done.load(Ordering::Relaxed) as f32 + in_stage) / stages as f32).min(1.0)
+    }
+
+    pub fn start(&self, stages: usize) {
+        self.stages.store(stages.max(1), Ordering::Relaxed);
+        self.stages_done.store(0, Ordering::Relaxed);
+    }
+
+    pub fn finish_stage(&self) {
+        self.stages_done.fetch_add(1, Ordering::Relaxed);
+        self.chunks.store(0, Ordering::Relaxed);
+        self.chunks_done.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Calls `map` on every chunk of every selected file in parallel. The results
+/// come back in file and row order, ready to be merged one after the other.
+/// Once `progress` is cancelled the remaining chunks are skipped, so the
+/// results are incomplete and the caller has to check for it.
+pub fn map_chunks<'a, T, F>(
+    file_indexes: &[usize],
+    csv_files: &'a [(String, Arc<CsvGrid>)],
+    progress: &Progress,
+    map: F
+) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Chunk<'a>) -> T + Sync + Send,
+{
+    let mut chunks: Vec<Chunk<'a>> = Vec::new();
+    for &file_idx in file_indexes {
+        let Some((headers, rows)) = csv_files.get(file_idx).and_then(|(_, grid)| grid.split_first()) else { continue };
+        for (chunk_idx, rows) in rows.chunks(CHUNK_ROWS).enumerate() {
+            chunks.push(Chunk { file_idx, headers, offset: chunk_idx * CHUNK_ROWS, rows });
+        }
+    }
+
+    progress.chunks.fetch_add(chunks.len(), Ordering::Relaxed);
+    chunks.into_par_iter()
+        .filter(|_| !progress.is_cancelled())
+        .map(|chunk| {
+            let result = map(chunk);
+            progress.chunks_done.fetch_add(1, Ordering::Relaxed);
+            result
+        })
+        .collect()
+}
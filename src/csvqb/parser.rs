@@ -0,0 +1,788 @@
+use crate::csvqb::ast::{AggFunc, BinaryOp, Condition, Expr, Ident, JoinKind, Literal, Pipeline, RelationFunc, Stage, WindowFunc};
+use crate::csvqb::bins::{BinMethod, MAX_BINS};
+use crate::csvqb::dates::{DateFormat, TimeUnit};
+use crate::csvqb::error::CsvqbError;
+use crate::csvqb::lexer::{tokenize, Span, Token, TokenKind};
+use crate::csvqb::text::{Pattern, TextFunc, TEXT_OPERATORS};
+use crate::csvqb::Operator;
+
+pub fn parse_pipeline(src: &str) -> Result<Pipeline, CsvqbError> {
+    let tokens = tokenize(src)?;
+    Parser { tokens: &tokens, pos: 0, src }.pipeline()
+}
+
+/// Recursive descent parser over the token stream.
+///
+/// csvqb mixes infix and reverse polish notation: `CSUM a MUL CSUM b` and
+/// `CSUM a CSUM b MUL` build the same tree. An operator is read as infix when an
+/// operand follows it, otherwise it is postfix and consumes the two expressions
+/// right before it.
+///
+/// A comparison between a column and a value at the top level of a pipeline is
+/// a row filter, same as writing it after `WHERE`.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    src: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn pipeline(mut self) -> Result<Pipeline, CsvqbError> {
+        let chart = match self.peek() {
+            Some(Token { kind: TokenKind::Chart(name), span, .. }) => {
+                let chart = Ident { name: name.clone(), span: *span };
+                self.pos += 1;
+                Some(chart)
+            }
+            _ => None,
+        };
+
+        let mut sources = Vec::new();
+        while let Some(token @ Token { kind: TokenKind::Operator(Operator::From), .. }) = self.peek() {
+            self.pos += 1;
+            sources.push(self.column(token, "FROM expects the name of a result or a file, e.g. 'FROM monthly_rev'")?);
+        }
+
+        let stages = self.sequence(false)?;
+
+        let name = match self.next() {
+            Some(token) => {
+                let name = self.column(token, "=> expects a name for the result, e.g. '=> monthly_rev'")?;
+                if let Some(extra) = self.peek() {
+                    return Err(self.error("=> names the result and must end the pipeline", extra));
+                }
+                Some(name)
+            }
+            None => None,
+        };
+        Ok(Pipeline { chart, sources, stages, name })
+    }
+
+    /// Parses stages until the end of input, or until the closing `)` when `nested`.
+    fn sequence(&mut self, nested: bool) -> Result<Vec<Stage>, CsvqbError> {
+        let mut stages: Vec<Stage> = Vec::new();
+
+        while let Some(token) = self.peek() {
+            match &token.kind {
+                TokenKind::RParen if nested => break,
+                TokenKind::RParen => return Err(self.error("unmatched ')'", token)),
+                TokenKind::Operator(Operator::Name) if !nested => break,
+                TokenKind::Operator(op @ (Operator::Name | Operator::From)) => {
+                    let message = match op {
+                        Operator::Name => "=> names the result and must end the pipeline",
+                        _ => "FROM must come first in the pipeline, after the chart type",
+                    };
+                    return Err(self.error(message, token));
+                }
+                TokenKind::Chart(name) => {
+                    return Err(self.error(&format!("chart type '{}' must come first in the pipeline", name), token));
+                }
+                TokenKind::Operator(Operator::GroupBy) => {
+                    if nested {
+                        return Err(self.error("GRP can't be used inside parentheses", token));
+                    }
+                    stages.push(self.group()?);
+                }
+                TokenKind::Operator(Operator::Where) => {
+                    if nested {
+                        return Err(self.error("WHERE can't be used inside parentheses", token));
+                    }
+                    self.pos += 1;
+                    let expr = self.expr(0)?;
+                    let condition = match (&expr, self.peek()) {
+                        (Expr::Field(column), Some(Token { kind: TokenKind::Operator(Operator::Between), .. })) => Some(self.between(column.clone())?),
+                        (Expr::Field(column), Some(Token { kind: TokenKind::Operator(Operator::Is), .. })) => Some(self.is_null(column.clone())?),
+                        _ => None,
+                    };
+                    if let Some(condition) = condition {
+                        stages.push(Stage::Filter { span: token.span.to(condition.span()), condition });
+                        continue;
+                    }
+                    let span = token.span.to(expr.span());
+                    match self.condition(&expr)? {
+                        Some(condition) => stages.push(Stage::Filter { condition, span }),
+                        None => return Err(CsvqbError::syntax("WHERE expects a comparison like 'region = EU'", span)
+                            .with_token(token.text.clone())),
+                    }
+                }
+                TokenKind::Operator(op @ (Operator::Sort | Operator::Limit | Operator::Top)) => {
+                    if nested {
+                        return Err(self.error(&format!("{} can't be used inside parentheses", token.text), token));
+                    }
+                    match op {
+                        Operator::Sort => stages.push(self.sort()?),
+                        Operator::Limit => {
+                            self.pos += 1;
+                            let (count, span) = self.count(token)?;
+                            stages.push(Stage::Limit { count, span: token.span.to(span) });
+                        }
+                        _ => {
+                            self.pos += 1;
+                            let (count, count_span) = self.count(token)?;
+                            match self.next() {
+                                Some(Token { kind: TokenKind::Operator(Operator::By), .. }) => {}
+                                _ => return Err(self.error("TOP expects 'BY' and a column, e.g. 'TOP 5 BY sum'", token)),
+                            }
+                            let column = self.column(token, "TOP expects 'BY' and a column, e.g. 'TOP 5 BY sum'")?;
+                            let span = token.span.to(column.span);
+                            stages.push(Stage::Sort { column, descending: true, span });
+                            stages.push(Stage::Limit { count, span: token.span.to(count_span) });
+                        }
+                    }
+                }
+                TokenKind::Operator(Operator::As) => {
+                    let example = "e.g. 'price MUL qty AS revenue'";
+                    if nested {
+                        return Err(self.error("AS can't be used inside parentheses", token));
+                    }
+                    let expr = match stages.pop() {
+                        Some(Stage::Expr(expr)) => expr,
+                        _ => return Err(self.error(&format!("AS names the value before it, {}", example), token)),
+                    };
+                    self.pos += 1;
+                    let name = self.column(token, &format!("AS expects a name for the new column, {}", example))?;
+                    stages.push(Stage::Derive { span: expr.span().to(name.span), expr, name });
+                }
+                TokenKind::Operator(Operator::Between) => {
+                    let column = match stages.pop() {
+                        Some(Stage::Expr(Expr::Field(column))) => column,
+                        _ => return Err(self.error("BETWEEN needs a column before it, e.g. 'day BETWEEN 2024-01-01 2024-03-31'", token)),
+                    };
+                    let condition = self.between(column)?;
+                    stages.push(Stage::Filter { span: condition.span(), condition });
+                }
+                TokenKind::Operator(Operator::Is) => {
+                    let column = match stages.pop() {
+                        Some(Stage::Expr(Expr::Field(column))) => column,
+                        _ => return Err(self.error("IS needs a column before it, e.g. 'amount IS NULL'", token)),
+                    };
+                    let condition = self.is_null(column)?;
+                    stages.push(Stage::Filter { span: condition.span(), condition });
+                }
+                TokenKind::Operator(op @ (Operator::DateFormat | Operator::Bucket)) => {
+                    if nested {
+                        return Err(self.error(&format!("{} can't be used inside parentheses", token.text), token));
+                    }
+                    stages.push(match op {
+                        Operator::DateFormat => self.date_format()?,
+                        _ => self.bucket()?,
+                    });
+                }
+                TokenKind::Operator(Operator::Dedup) => {
+                    if nested {
+                        return Err(self.error("DEDUP can't be used inside parentheses", token));
+                    }
+                    self.pos += 1;
+                    let columns = self.columns();
+                    let span = columns.last().map_or(token.span, |last| token.span.to(last.span));
+                    stages.push(Stage::Dedup { columns, span });
+                }
+                TokenKind::Operator(Operator::Fill) => {
+                    if nested {
+                        return Err(self.error("FILL can't be used inside parentheses", token));
+                    }
+                    self.pos += 1;
+                    let message = "FILL expects a column and the value for its null cells, e.g. 'FILL amount 0'";
+                    let column = self.column(token, message)?;
+                    let value = self.literal(token, message)?;
+                    stages.push(Stage::Fill { span: token.span.to(value.span), column, value });
+                }
+                TokenKind::Operator(op) if JoinKind::from_operator(op).is_some() => {
+                    if nested {
+                        return Err(self.error(&format!("{} can't be used inside parentheses", token.text), token));
+                    }
+                    stages.push(self.join()?);
+                }
+                TokenKind::Operator(op) if BinaryOp::from_operator(op).is_some() => {
+                    let op = BinaryOp::from_operator(op).unwrap();
+                    self.pos += 1;
+
+                    if self.at_operand() {
+                        let lhs = match stages.pop() {
+                            Some(Stage::Expr(lhs)) => lhs,
+                            _ => return Err(self.error(&format!("'{}' is missing its left operand", token.text), token)),
+                        };
+                        let rhs = self.expr(op.rhs_precedence())?;
+                        let expr = binary(op, lhs, rhs);
+                        stages.push(Stage::Expr(self.infix_tail(expr, 0)?));
+                    } else {
+                        let (rhs, lhs) = match (stages.pop(), stages.pop()) {
+                            (Some(Stage::Expr(rhs)), Some(Stage::Expr(lhs))) => (rhs, lhs),
+                            _ => return Err(self.error(&format!("'{}' needs two operands before it", token.text), token)),
+                        };
+                        stages.push(Stage::Expr(binary(op, lhs, rhs)));
+                    }
+                }
+                _ => {
+                    let expr = self.expr(0)?;
+                    stages.push(Stage::Expr(expr));
+                }
+            }
+        }
+
+        if !nested {
+            for stage in stages.iter_mut() {
+                if let Stage::Expr(expr) = stage {
+                    if let Some(condition) = self.condition(expr)? {
+                        *stage = Stage::Filter { condition, span: expr.span() };
+                    }
+                }
+            }
+        }
+
+        Ok(stages)
+    }
+
+    /// Reads `column <op> value` as a row condition. The column may be a string
+    /// function of one, and `MATCHES` needs a pattern that compiles.
+    fn condition(&self, expr: &Expr) -> Result<Option<Condition>, CsvqbError> {
+        let both = |lhs: &Expr, rhs: &Expr| -> Result<Option<(Condition, Condition)>, CsvqbError> {
+            Ok(self.condition(lhs)?.zip(self.condition(rhs)?))
+        };
+        Ok(match expr {
+            Expr::Binary { op, lhs, rhs, span } if op.is_comparison() => {
+                let value = match rhs.as_ref() {
+                    Expr::Number { span, .. } => Literal { text: self.src[span.start..span.end].to_string(), span: *span },
+                    Expr::Field(value) => Literal { text: value.name.clone(), span: value.span },
+                    _ => return Ok(None),
+                };
+                match (op, lhs.as_ref()) {
+                    (BinaryOp::Matches, Expr::Field(_) | Expr::Text { .. }) => Some(Condition::Matches {
+                        expr: lhs.as_ref().clone(),
+                        pattern: Pattern::new(&value)?,
+                        span: *span,
+                    }),
+                    (op, Expr::Field(column)) => Some(Condition::Compare { column: column.clone(), op: *op, value }),
+                    (op, Expr::Text { .. }) => Some(Condition::Text { expr: lhs.as_ref().clone(), op: *op, value }),
+                    _ => None,
+                }
+            }
+            Expr::Binary { op: BinaryOp::And, lhs, rhs, .. } => both(lhs, rhs)?
+                .map(|(lhs, rhs)| Condition::And(Box::new(lhs), Box::new(rhs))),
+            Expr::Binary { op: BinaryOp::Or, lhs, rhs, .. } => both(lhs, rhs)?
+                .map(|(lhs, rhs)| Condition::Or(Box::new(lhs), Box::new(rhs))),
+            Expr::Not { expr, span } => self.condition(expr)?.map(|inner| Condition::Not(Box::new(inner), *span)),
+            _ => None,
+        })
+    }
+
+    fn group(&mut self) -> Result<Stage, CsvqbError> {
+        let grp = self.next().unwrap();
+        let columns = self.columns();
+        match columns.last() {
+            Some(last) => Ok(Stage::Group { span: grp.span.to(last.span), columns }),
+            None => Err(self.error("GRP expects at least one column", grp)),
+        }
+    }
+
+    /// The columns written one after the other from here on, as many as there are.
+    fn columns(&mut self) -> Vec<Ident> {
+        let mut columns = Vec::new();
+        while let Some(Token { kind: TokenKind::Word(name), span, .. }) = self.peek() {
+            columns.push(Ident { name: name.clone(), span: *span });
+            self.pos += 1;
+        }
+        columns
+    }
+
+    fn join(&mut self) -> Result<Stage, CsvqbError> {
+        let token = self.next().unwrap();
+        let kind = match &token.kind {
+            TokenKind::Operator(op) => JoinKind::from_operator(op).unwrap(),
+            _ => unreachable!(),
+        };
+
+        let mut keys = Vec::new();
+        while keys.len() < 2 {
+            match self.peek() {
+                Some(Token { kind: TokenKind::Word(name), span, .. }) => {
+                    keys.push(Ident { name: name.clone(), span: *span });
+                    self.pos += 1;
+                }
+                _ => return Err(self.error(
+                    &format!("{} expects two keys written as file.column, e.g. '{} orders.id customers.order_id'", token.text, token.text),
+                    token,
+                )),
+            }
+        }
+
+        let right = keys.pop().unwrap();
+        let left = keys.pop().unwrap();
+        Ok(Stage::Join { kind, span: token.span.to(right.span), left, right })
+    }
+
+    /// The two ends of `column BETWEEN from to`, the `BETWEEN` is next.
+    fn between(&mut self, column: Ident) -> Result<Condition, CsvqbError> {
+        let token = self.next().unwrap();
+        let message = "BETWEEN expects two values, e.g. 'day BETWEEN 2024-01-01 2024-03-31'";
+        let from = self.literal(token, message)?;
+        let to = self.literal(token, message)?;
+        Ok(Condition::Between { column, from, to })
+    }
+
+    /// The rest of `column IS NULL` or `column IS NOT NULL`, the `IS` is next.
+    fn is_null(&mut self, column: Ident) -> Result<Condition, CsvqbError> {
+        let token = self.next().unwrap();
+        let negated = matches!(self.peek(), Some(Token { kind: TokenKind::Operator(Operator::Not), .. }));
+        if negated {
+            self.pos += 1;
+        }
+        match self.next() {
+            Some(Token { kind: TokenKind::Word(word), span, .. }) if word == "NULL" => {
+                Ok(Condition::IsNull { span: column.span.to(*span), column, negated })
+            }
+            _ => Err(self.error("IS expects NULL or NOT NULL, e.g. 'amount IS NOT NULL'", token)),
+        }
+    }
+
+    fn date_format(&mut self) -> Result<Stage, CsvqbError> {
+        let token = self.next().unwrap();
+        let message = format!("DATEFMT expects a column and one of {}, e.g. 'DATEFMT day eu'", DateFormat::WORDS.join(", "));
+        let column = self.column(token, &message)?;
+        let format = self.column(token, &message)?;
+        match DateFormat::from_word(&format.name) {
+            Some(date_format) => Ok(Stage::Dates { column, format: date_format, span: token.span.to(format.span) }),
+            None => Err(CsvqbError::syntax(message, format.span).with_token(format.name)),
+        }
+    }
+
+    fn bucket(&mut self) -> Result<Stage, CsvqbError> {
+        let token = self.next().unwrap();
+        let message = format!("BUCKET expects a date column and one of {}, e.g. 'BUCKET day month'", TimeUnit::WORDS.join(", "));
+        let column = self.column(token, &message)?;
+        let unit = self.column(token, &message)?;
+        match TimeUnit::from_word(&unit.name) {
+            Some(time_unit) => Ok(Stage::Bucket { column, unit: time_unit, span: token.span.to(unit.span) }),
+            None => Err(CsvqbError::syntax(message, unit.span).with_token(unit.name)),
+        }
+    }
+
+    fn sort(&mut self) -> Result<Stage, CsvqbError> {
+        let token = self.next().unwrap();
+        let column = self.column(token, "SORT expects a column, e.g. 'SORT sum DESC'")?;
+        let mut span = token.span.to(column.span);
+        let mut descending = false;
+        if let Some(Token { kind: TokenKind::Operator(op @ (Operator::Asc | Operator::Desc)), span: order_span, .. }) = self.peek() {
+            descending = *op == Operator::Desc;
+            span = span.to(*order_span);
+            self.pos += 1;
+        }
+        Ok(Stage::Sort { column, descending, span })
+    }
+
+    /// Row count of `LIMIT n` and `TOP n`.
+    fn count(&mut self, keyword: &Token) -> Result<(usize, Span), CsvqbError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Number(n), span, .. }) if *n >= 0.0 && n.fract() == 0.0 => Ok((*n as usize, *span)),
+            _ => Err(self.error(&format!("{} expects a whole number of rows, e.g. '{} 10'", keyword.text, keyword.text), keyword)),
+        }
+    }
+
+    fn column(&mut self, keyword: &Token, message: &str) -> Result<Ident, CsvqbError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Word(name), span, .. }) => Ok(Ident { name: name.clone(), span: *span }),
+            _ => Err(self.error(message, keyword)),
+        }
+    }
+
+    /// A value compared against cells, kept as it was written.
+    fn literal(&mut self, keyword: &Token, message: &str) -> Result<Literal, CsvqbError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Number(_), span, text }) => Ok(Literal { text: text.clone(), span: *span }),
+            Some(Token { kind: TokenKind::Word(text), span, .. }) => Ok(Literal { text: text.clone(), span: *span }),
+            _ => Err(self.error(message, keyword)),
+        }
+    }
+
+    /// Precedence climbing over infix operators.
+    fn expr(&mut self, min_prec: u8) -> Result<Expr, CsvqbError> {
+        let lhs = self.operand()?;
+        self.infix_tail(lhs, min_prec)
+    }
+
+    fn infix_tail(&mut self, mut lhs: Expr, min_prec: u8) -> Result<Expr, CsvqbError> {
+        while let Some(op) = self.peek_infix() {
+            if op.precedence() < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(op.rhs_precedence())?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, CsvqbError> {
+        let token = match self.next() {
+            Some(token) => token.clone(),
+            None => {
+                let end = Span::new(self.src.len(), self.src.len());
+                return Err(CsvqbError::syntax("pipeline ends where a value was expected", end));
+            }
+        };
+
+        match &token.kind {
+            TokenKind::Number(value) => Ok(Expr::Number { value: *value, span: token.span }),
+            TokenKind::Word(name) => Ok(Expr::Field(Ident { name: name.clone(), span: token.span })),
+            TokenKind::Operator(op) if AggFunc::from_operator(op).is_some() => {
+                let mut func = AggFunc::from_operator(op).unwrap();
+                if let AggFunc::Percentile(_) = func {
+                    match self.next() {
+                        Some(Token { kind: TokenKind::Number(p), .. }) if (0.0..=100.0).contains(p) => {
+                            func = AggFunc::Percentile(*p);
+                        }
+                        _ => return Err(self.error("CPCT expects a percentile between 0 and 100, e.g. 'CPCT 90 amount'", &token)),
+                    }
+                }
+                match self.next() {
+                    Some(Token { kind: TokenKind::Word(name), span, .. }) => {
+                        let column = Ident { name: name.clone(), span: *span };
+                        Ok(Expr::Aggregate { func, span: token.span.to(column.span), column })
+                    }
+                    _ => Err(self.error(&format!("{} expects a column name", token.text), &token)),
+                }
+            }
+            TokenKind::Operator(op) if RelationFunc::from_operator(op).is_some() => {
+                let func = RelationFunc::from_operator(op).unwrap();
+                let message = format!("{} expects two numeric columns, e.g. '{} price sold'", token.text, token.text);
+                let x = self.column(&token, &message)?;
+                let y = self.column(&token, &message)?;
+                Ok(Expr::Relation { func, span: token.span.to(y.span), x, y })
+            }
+            TokenKind::Operator(op) if WindowFunc::from_operator(op).is_some() => {
+                let example = match op {
+                    Operator::MovingAvg => format!("e.g. '{} 7 amount BY day'", token.text),
+                    Operator::Lag | Operator::Lead => format!("e.g. '{} 1 amount BY day'", token.text),
+                    Operator::Rank => format!("e.g. '{} amount'", token.text),
+                    _ => format!("e.g. '{} amount BY day'", token.text),
+                };
+                let mut func = WindowFunc::from_operator(op).unwrap();
+                match (func, self.peek()) {
+                    (WindowFunc::MovingAvg(_) | WindowFunc::Lag(_) | WindowFunc::Lead(_), Some(Token { kind: TokenKind::Number(n), .. })) => {
+                        if *n < 1.0 || n.fract() != 0.0 {
+                            return Err(self.error(&format!("{} expects a whole number of rows, {}", token.text, example), &token));
+                        }
+                        let n = *n as usize;
+                        func = match func {
+                            WindowFunc::MovingAvg(_) => WindowFunc::MovingAvg(n),
+                            WindowFunc::Lag(_) => WindowFunc::Lag(n),
+                            _ => WindowFunc::Lead(n),
+                        };
+                        self.pos += 1;
+                    }
+                    (WindowFunc::MovingAvg(_), _) => {
+                        return Err(self.error(&format!("{} expects the number of rows to average, {}", token.text, example), &token));
+                    }
+                    _ => {}
+                }
+
+                let column = self.column(&token, &format!("{} expects a column, {}", token.text, example))?;
+                let mut span = token.span.to(column.span);
+                let mut order = None;
+                if let (Some(Token { kind: TokenKind::Operator(Operator::By), .. }), false) = (self.peek(), func == WindowFunc::Rank) {
+                    self.pos += 1;
+                    let order_by = self.column(&token, &format!("{} expects a column to order by after BY, {}", token.text, example))?;
+                    span = span.to(order_by.span);
+                    order = Some(order_by);
+                }
+                Ok(Expr::Window { func, column, order, span })
+            }
+            TokenKind::Operator(Operator::Pivot) => {
+                let example = "e.g. 'PIVOT product month CSUM revenue'";
+                let rows = self.column(&token, &format!("PIVOT expects a row column, a column column and an aggregate, {}", example))?;
+                let columns = self.column(&token, &format!("PIVOT expects a row column, a column column and an aggregate, {}", example))?;
+                match self.peek() {
+                    Some(Token { kind: TokenKind::Operator(op), .. }) if AggFunc::from_operator(op).is_some() => {}
+                    _ => return Err(self.error(&format!("PIVOT expects an aggregate for the cells, {}", example), &token)),
+                }
+                match self.operand()? {
+                    Expr::Aggregate { func, column, span } => Ok(Expr::Pivot {
+                        rows,
+                        columns,
+                        func,
+                        value: column,
+                        span: token.span.to(span),
+                    }),
+                    _ => unreachable!(),
+                }
+            }
+            TokenKind::Operator(op) if TEXT_OPERATORS.contains(op) => self.text_func(&token, *op),
+            TokenKind::Operator(Operator::Distinct) => {
+                let columns = self.columns();
+                match columns.last() {
+                    Some(last) => Ok(Expr::Distinct { span: token.span.to(last.span), columns }),
+                    None => Err(self.error("DISTINCT expects at least one column, e.g. 'DISTINCT region product'", &token)),
+                }
+            }
+            TokenKind::Operator(Operator::Bin) => self.bin(&token),
+            TokenKind::Operator(Operator::Not) => {
+                // NOT applies to the comparison that follows, `NOT a = 1 AND b = 2`
+                // negates only `a = 1`.
+                let inner = self.expr(BinaryOp::Equals.precedence())?;
+                let span = token.span.to(inner.span());
+                Ok(Expr::Not { expr: Box::new(inner), span })
+            }
+            TokenKind::LParen => {
+                let mut inner = self.sequence(true)?;
+                match self.next() {
+                    Some(Token { kind: TokenKind::RParen, .. }) => {}
+                    _ => return Err(self.error("unclosed '('", &token)),
+                }
+                match (inner.pop(), inner.is_empty()) {
+                    (Some(Stage::Expr(expr)), true) => Ok(expr),
+                    (None, _) => Err(self.error("empty parentheses", &token)),
+                    _ => Err(self.error("parentheses must contain a single expression", &token)),
+                }
+            }
+            _ => Err(self.error(&format!("unexpected '{}'", token.text), &token)),
+        }
+    }
+
+    /// `BIN column` and how to bin it, its keyword was just read.
+    fn bin(&mut self, token: &Token) -> Result<Expr, CsvqbError> {
+        let example = "e.g. 'BIN amount', 'BIN amount 20' or 'BIN amount width 5'";
+        let column = self.column(token, &format!("BIN expects a column, {}", example))?;
+        let mut span = token.span.to(column.span);
+
+        let method = match self.peek() {
+            Some(Token { kind: TokenKind::Number(n), span: count_span, .. }) => {
+                if *n < 1.0 || n.fract() != 0.0 || *n > MAX_BINS as f64 {
+                    return Err(self.error(&format!("BIN expects a whole number of bins up to {}, {}", MAX_BINS, example), token));
+                }
+                span = span.to(*count_span);
+                self.pos += 1;
+                BinMethod::Count(*n as usize)
+            }
+            Some(Token { kind: TokenKind::Word(word), .. }) if word.eq_ignore_ascii_case("width") => {
+                self.pos += 1;
+                match self.next() {
+                    Some(Token { kind: TokenKind::Number(width), span: width_span, .. }) if *width > 0.0 => {
+                        span = span.to(*width_span);
+                        BinMethod::Width(*width)
+                    }
+                    _ => return Err(self.error(&format!("BIN expects a width above 0 after 'width', {}", example), token)),
+                }
+            }
+            Some(Token { kind: TokenKind::Word(word), span: word_span, .. }) if BinMethod::from_word(word).is_some() => {
+                let method = BinMethod::from_word(word).unwrap();
+                span = span.to(*word_span);
+                self.pos += 1;
+                method
+            }
+            _ => BinMethod::Auto,
+        };
+        Ok(Expr::Bin { column, method, span })
+    }
+
+    /// A string function, its keyword was just read. It applies to a column or to
+    /// another string function, followed by what the function needs.
+    fn text_func(&mut self, token: &Token, op: Operator) -> Result<Expr, CsvqbError> {
+        let example = match op {
+            Operator::Substr => format!("e.g. '{} code 2 3'", token.text),
+            Operator::Split => format!("e.g. '{} email @ 2'", token.text),
+            Operator::Extract => format!("e.g. \"{} code '[0-9]+'\"", token.text),
+            Operator::Replace => format!("e.g. \"{} phone '[^0-9]' ''\"", token.text),
+            _ => format!("e.g. '{} region'", token.text),
+        };
+        let arg = match self.peek() {
+            Some(Token { kind: TokenKind::Word(_), .. }) => self.operand()?,
+            Some(Token { kind: TokenKind::Operator(op), .. }) if TEXT_OPERATORS.contains(op) => self.operand()?,
+            _ => return Err(self.error(&format!("{} expects a column, {}", token.text, example), token)),
+        };
+
+        let mut span = token.span.to(arg.span());
+        let func = match op {
+            Operator::Trim => TextFunc::Trim,
+            Operator::Lower => TextFunc::Lower,
+            Operator::Upper => TextFunc::Upper,
+            Operator::Substr => {
+                let message = format!("{} expects the position of the first character, counting from 1, {}", token.text, example);
+                let (start, start_span) = self.position(token, &message)?;
+                span = span.to(start_span);
+                let len = match self.peek() {
+                    Some(Token { kind: TokenKind::Number(_), .. }) => {
+                        let (len, len_span) = self.position(token, &format!("{} expects a whole number of characters, {}", token.text, example))?;
+                        span = span.to(len_span);
+                        Some(len)
+                    }
+                    _ => None,
+                };
+                TextFunc::Substr { start, len }
+            }
+            Operator::Split => {
+                let message = format!("{} expects a delimiter and which part to keep, counting from 1, {}", token.text, example);
+                let delimiter = self.literal(token, &message)?;
+                let (part, part_span) = self.position(token, &message)?;
+                span = span.to(part_span);
+                TextFunc::Split { delimiter: delimiter.text, part }
+            }
+            Operator::Extract => {
+                let pattern = self.literal(token, &format!("{} expects a pattern, {}", token.text, example))?;
+                span = span.to(pattern.span);
+                TextFunc::Extract(Pattern::new(&pattern)?)
+            }
+            _ => {
+                let message = format!("{} expects a pattern and what to replace it with, {}", token.text, example);
+                let pattern = self.literal(token, &message)?;
+                let replacement = self.literal(token, &message)?;
+                span = span.to(replacement.span);
+                TextFunc::Replace(Pattern::new(&pattern)?, replacement.text)
+            }
+        };
+        Ok(Expr::Text { func, arg: Box::new(arg), span })
+    }
+
+    /// A whole number from 1 on, a position or a count of characters.
+    fn position(&mut self, keyword: &Token, message: &str) -> Result<(usize, Span), CsvqbError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Number(n), span, .. }) if *n >= 1.0 && n.fract() == 0.0 => Ok((*n as usize, *span)),
+            _ => Err(self.error(message, keyword)),
+        }
+    }
+
+    /// Next token is a binary operator that is used infix, i.e. an operand follows it.
+    fn peek_infix(&self) -> Option<BinaryOp> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Operator(op), .. }) => {
+                let op = BinaryOp::from_operator(op)?;
+                starts_operand(self.tokens.get(self.pos + 1)).then_some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn at_operand(&self) -> bool {
+        starts_operand(self.peek())
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str, token: &Token) -> CsvqbError {
+        CsvqbError::syntax(message, token.span).with_token(token.text.clone())
+    }
+}
+
+fn starts_operand(token: Option<&Token>) -> bool {
+    match token.map(|token| &token.kind) {
+        Some(TokenKind::Number(_)) | Some(TokenKind::Word(_)) | Some(TokenKind::LParen) => true,
+        Some(TokenKind::Operator(Operator::Not | Operator::Pivot | Operator::Distinct | Operator::Bin)) => true,
+        Some(TokenKind::Operator(op)) => AggFunc::from_operator(op).is_some()
+            || RelationFunc::from_operator(op).is_some()
+            || WindowFunc::from_operator(op).is_some()
+            || TEXT_OPERATORS.contains(op),
+        _ => false,
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span().to(rhs.span());
+    Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs), span }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The expression with every binary operation in parentheses, aggregates
+    /// written as their column.
+    fn shape(expr: &Expr) -> String {
+        match expr {
+            Expr::Binary { op, lhs, rhs, .. } => format!("({} {} {})", shape(lhs), op.symbol(), shape(rhs)),
+            Expr::Aggregate { column, .. } => column.name.clone(),
+            Expr::Not { expr, .. } => format!("NOT {}", shape(expr)),
+            expr => expr.label(),
+        }
+    }
+
+    fn parse_expr(src: &str) -> String {
+        let pipeline = parse_pipeline(src).unwrap_or_else(|err| panic!("{}: {}", src, err));
+        match pipeline.stages.as_slice() {
+            [Stage::Expr(expr)] => shape(expr),
+            stages => panic!("{} parsed as {:?}", src, stages),
+        }
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        for (src, expected) in [
+            ("CSUM a ADD CSUM b MUL CSUM c", "(a + (b * c))"),
+            ("CSUM a MUL CSUM b ADD CSUM c", "((a * b) + c)"),
+            ("CSUM a SUB CSUM b SUB CSUM c", "((a - b) - c)"),
+            ("CSUM a DIV CSUM b MOD CSUM c", "((a / b) % c)"),
+            ("CSUM a SUB CSUM b DIV 2", "(a - (b / 2))"),
+            ("CSUM a MUL CSUM b POW 2", "(a * (b ^ 2))"),
+            ("CSUM a POW 2 POW 3", "(a ^ (2 ^ 3))"),
+            ("(CSUM a ADD CSUM b) MUL CSUM c", "((a + b) * c)"),
+        ] {
+            assert_eq!(parse_expr(src), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn postfix_operators() {
+        assert_eq!(parse_expr("CSUM a CSUM b MUL"), parse_expr("CSUM a MUL CSUM b"));
+        // ADD has an operand after it, so it is infix and MUL takes its result.
+        assert_eq!(parse_expr("CSUM a CSUM b ADD CSUM c MUL"), "(a * (b + c))");
+    }
+
+    /// The condition with every AND and OR in parentheses.
+    fn condition_shape(condition: &Condition) -> String {
+        match condition {
+            Condition::Compare { column, op, value } => format!("{} {} {}", column.name, op.symbol(), value.text),
+            Condition::And(lhs, rhs) => format!("({} AND {})", condition_shape(lhs), condition_shape(rhs)),
+            Condition::Or(lhs, rhs) => format!("({} OR {})", condition_shape(lhs), condition_shape(rhs)),
+            Condition::Not(inner, _) => format!("NOT {}", condition_shape(inner)),
+            condition => format!("{:?}", condition),
+        }
+    }
+
+    fn parse_filter(src: &str) -> String {
+        let pipeline = parse_pipeline(src).unwrap_or_else(|err| panic!("{}: {}", src, err));
+        match pipeline.stages.as_slice() {
+            [Stage::Filter { condition, .. }] => condition_shape(condition),
+            stages => panic!("{} parsed as {:?}", src, stages),
+        }
+    }
+
+    #[test]
+    fn logical_precedence() {
+        for (src, expected) in [
+            ("WHERE a = 1 OR b = 2 AND c = 3", "(a = 1 OR (b = 2 AND c = 3))"),
+            ("WHERE a = 1 AND b = 2 OR c = 3", "((a = 1 AND b = 2) OR c = 3)"),
+            ("WHERE a = 1 AND b = 2 AND c = 3", "((a = 1 AND b = 2) AND c = 3)"),
+            ("WHERE NOT a = 1 AND b = 2", "(NOT a = 1 AND b = 2)"),
+            ("WHERE NOT (a = 1 OR b = 2)", "NOT (a = 1 OR b = 2)"),
+            ("WHERE (a = 1 OR b = 2) AND c = 3", "((a = 1 OR b = 2) AND c = 3)"),
+            ("a > 1 OR b < 2", "(a > 1 OR b < 2)"),
+        ] {
+            assert_eq!(parse_filter(src), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn comparisons_between_arithmetic_and_logic() {
+        for (src, expected) in [
+            ("CSUM a ADD 1 > CSUM b", "((a + 1) > b)"),
+            ("CSUM a > CSUM b MUL 2", "(a > (b * 2))"),
+            ("CSUM a > 1 OR CSUM b > 2 AND CSUM c > 3", "((a > 1) OR ((b > 2) AND (c > 3)))"),
+            ("NOT CSUM a > 1 AND CSUM b > 2", "(NOT (a > 1) AND (b > 2))"),
+        ] {
+            assert_eq!(parse_expr(src), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for src in ["CSUM a ADD", "(CSUM a", "CSUM", "()"] {
+            assert!(parse_pipeline(src).is_err(), "{}", src);
+        }
+    }
+}
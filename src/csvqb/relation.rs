@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::RelationFunc;
+use crate::csvqb::columns::{read_numeric, typed_column, Numeric};
+use crate::csvqb::compute::format_number;
+use crate::csvqb::order::compare_keys;
+use crate::csvqb::parallel::map_chunks;
+use crate::csvqb::{EvalContext, Tally};
+
+/// The key of a group and its pairs of x and y.
+type Group = (Vec<String>, Vec<(f64, f64)>);
+
+/// The groups of one chunk, with what was read of `x` and how many values of
+/// `y` weren't numbers.
+type Partial = (Vec<Group>, Tally, usize);
+
+/// Relates `x` and `y` over every selected file, grouped by the `GRP` columns.
+/// Rows where either cell is null or not a number are left out.
+///
+/// Returns the points, with the group columns, `x` and `y` for every pair that
+/// was read, and the result, a row per group with the values of `func`. Also
+/// returns how many rows were read, how many were left out because a cell was
+/// null and how many values of `x` were skipped because they aren't numeric,
+/// then how many values of `y` were skipped.
+pub fn col_relation(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    x: &str,
+    y: &str,
+    group_by: Option<&[String]>,
+    func: RelationFunc
+) -> (Vec<Vec<String>>, Vec<Vec<String>>, Tally, usize) {
+    let group_cols = group_by.unwrap_or_default();
+
+    let partials = map_chunks(file_indexes, csv_files, context.progress, |chunk| {
+        let mut partial: Partial = (Vec::new(), Tally::default(), 0);
+        let position = |name: &str| chunk.headers.iter().position(|h| h == name);
+        let (Some(x_idx), Some(y_idx)) = (position(x), position(y)) else { return partial };
+        let (typed_x, typed_y) = (typed_column(&context.tables, chunk.file_idx, x), typed_column(&context.tables, chunk.file_idx, y));
+        let null_tokens = context.null_tokens.get(&csv_files[chunk.file_idx].0);
+        let group_idxs: Vec<Option<usize>> = group_cols.iter().map(|group_col| position(group_col)).collect();
+        let (groups, tally, y_skipped) = &mut partial;
+        let mut group_positions: HashMap<Vec<String>, usize> = HashMap::new();
+
+        for (row_idx, row) in chunk.rows.iter().enumerate() {
+            let (Some(x_cell), Some(y_cell)) = (row.get(x_idx), row.get(y_idx)) else { continue };
+            tally.rows += 1;
+            let x_value = read_numeric(typed_x, chunk.offset + row_idx, x_cell, null_tokens);
+            let y_value = read_numeric(typed_y, chunk.offset + row_idx, y_cell, null_tokens);
+            let (x_value, y_value) = match (x_value, y_value) {
+                (Numeric::Null, _) | (_, Numeric::Null) => {
+                    tally.nulls += 1;
+                    continue;
+                }
+                (Numeric::Value(x_value), Numeric::Value(y_value)) => (x_value, y_value),
+                (x_value, y_value) => {
+                    tally.skipped += matches!(x_value, Numeric::NotNumeric) as usize;
+                    *y_skipped += matches!(y_value, Numeric::NotNumeric) as usize;
+                    continue;
+                }
+            };
+
+            let key: Vec<String> = group_idxs.iter()
+                .map(|idx| idx.and_then(|idx| row.get(idx)).cloned().unwrap_or_default())
+                .collect();
+            let group = *group_positions.entry(key.clone()).or_insert_with(|| {
+                groups.push((key, Vec::new()));
+                groups.len() - 1
+            });
+            groups[group].1.push((x_value, y_value));
+        }
+        partial
+    });
+
+    // Pairs keep the order they were read in within their group.
+    let mut groups: HashMap<Vec<String>, Vec<(f64, f64)>> = HashMap::new();
+    let mut tally = Tally::default();
+    let mut y_skipped = 0;
+    for (partial_groups, partial_tally, partial_skipped) in partials {
+        for (key, pairs) in partial_groups {
+            groups.entry(key).or_default().extend(pairs);
+        }
+        tally.rows += partial_tally.rows;
+        tally.nulls += partial_tally.nulls;
+        tally.skipped += partial_tally.skipped;
+        y_skipped += partial_skipped;
+    }
+    let mut groups: Vec<Group> = groups.into_iter().collect();
+    groups.sort_by(|(a, _), (b, _)| compare_keys(a, b, a.len()));
+
+    let mut points = vec![group_cols.iter().cloned().chain([x.to_string(), y.to_string()]).collect::<Vec<_>>()];
+    let mut stats = vec![group_cols.iter().cloned().chain(func.result_names().iter().map(|name| name.to_string())).collect::<Vec<_>>()];
+    for (key, pairs) in groups {
+        for (x_value, y_value) in &pairs {
+            points.push(key.iter().cloned().chain([x_value.to_string(), y_value.to_string()]).collect());
+        }
+        let values = relate(func, &pairs);
+        stats.push(key.into_iter().chain(values.into_iter().map(format_number)).collect());
+    }
+
+    (points, stats, tally, y_skipped)
+}
+
+/// The values of `func` for `pairs`, in the order of `result_names`. A value
+/// is `None` when the pairs don't define it, e.g. the correlation of fewer
+/// than 2 pairs or of a column whose values are all the same.
+fn relate(func: RelationFunc, pairs: &[(f64, f64)]) -> Vec<Option<f64>> {
+    match func {
+        RelationFunc::Pearson => vec![moments(pairs).and_then(|moments| moments.correlation())],
+        RelationFunc::Spearman => {
+            let xs = ranks(&pairs.iter().map(|(x, _)| *x).collect::<Vec<_>>());
+            let ys = ranks(&pairs.iter().map(|(_, y)| *y).collect::<Vec<_>>());
+            let ranked: Vec<(f64, f64)> = xs.into_iter().zip(ys).collect();
+            vec![moments(&ranked).and_then(|moments| moments.correlation())]
+        }
+        RelationFunc::Covariance => vec![moments(pairs).map(|moments| moments.sxy / (moments.n - 1.0))],
+        RelationFunc::Fit => {
+            let Some(moments) = moments(pairs).filter(|moments| moments.sxx > 0.0) else { return vec![None; 3] };
+            let slope = moments.sxy / moments.sxx;
+            let intercept = moments.mean_y - slope * moments.mean_x;
+            vec![Some(slope), Some(intercept), moments.correlation().map(|r| r * r)]
+        }
+    }
+}
+
+/// Means and sums of squared deviations of a set of pairs.
+struct Moments {
+    n: f64,
+    mean_x: f64,
+    mean_y: f64,
+    sxx: f64,
+    syy: f64,
+    sxy: f64,
+}
+
+impl Moments {
+    fn correlation(&self) -> Option<f64> {
+        (self.sxx > 0.0 && self.syy > 0.0).then(|| (self.sxy / (self.sxx * self.syy).sqrt()).clamp(-1.0, 1.0))
+    }
+}
+
+/// Moments of `pairs`, `None` for fewer than 2 pairs. The means are taken
+/// first so the sums don't lose precision on large values.
+fn moments(pairs: &[(f64, f64)]) -> Option<Moments> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        sxx += (x - mean_x) * (x - mean_x);
+        syy += (y - mean_y) * (y - mean_y);
+        sxy += (x - mean_x) * (y - mean_y);
+    }
+    Some(Moments { n, mean_x, mean_y, sxx, syy, sxy })
+}
+
+/// Rank of every value from 1 for the smallest, tied values share the average of their ranks.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        // Positions start..end hold ranks start + 1 through end.
+        let rank = (start + 1 + end) as f64 / 2.0;
+        for &idx in &order[start..end] {
+            ranks[idx] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
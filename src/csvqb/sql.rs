@@ -0,0 +1,531 @@
+use std::sync::Arc;
+use itertools::Itertools;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::{AggFunc, BinaryOp, Condition, Expr, Stage};
+use crate::csvqb::check_pipeline;
+use crate::csvqb::named::input_indexes;
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::Span;
+use crate::csvqb::nulls::NullTokens;
+use crate::db_manager::DbManager;
+
+/// The tables `DbManager::import_csv` made for the selected files, with their
+/// headers, and the null tokens the files share.
+struct Source<'a> {
+    tables: Vec<(String, &'a [String])>,
+    null_tokens: &'a [String],
+}
+
+impl Source<'_> {
+    /// A column as it is called in the query, the name `import_csv` gave it in
+    /// the first table. Rows of other tables are renamed to match in `from`.
+    fn column(&self, header: &str) -> String {
+        quote_ident(&DbManager::sanitize_column_name(&self.tables[0].0, header))
+    }
+
+    /// One table is read as it is. Several are stacked with `UNION ALL`, with
+    /// their position kept in `_file` and `_row` so rows stay in file order.
+    fn from(&self) -> String {
+        let [(table, _)] = self.tables.as_slice() else {
+            let headers = self.tables[0].1;
+            let selects: Vec<String> = self.tables.iter().enumerate()
+                .map(|(file, (table, table_headers))| {
+                    let columns: Vec<String> = headers.iter()
+                        .map(|header| if table_headers.contains(header) {
+                            format!("{} AS {}", quote_ident(&DbManager::sanitize_column_name(table, header)), self.column(header))
+                        } else {
+                            format!("'' AS {}", self.column(header))
+                        })
+                        .collect();
+                    format!("SELECT {} AS \"_file\", rowid AS \"_row\", {} FROM {}", file, columns.join(", "), quote_ident(table))
+                })
+                .collect();
+            return format!("({}) AS \"rows\"", selects.join(" UNION ALL "));
+        };
+        quote_ident(table)
+    }
+
+    /// Whether `expr` is null the way `nulls::is_null` reads a cell. `upper`
+    /// only folds ASCII letters, like `eq_ignore_ascii_case`.
+    fn is_null(&self, expr: &str) -> String {
+        let tokens: Vec<String> = self.null_tokens.iter()
+            .map(|token| quote_literal(&token.to_ascii_uppercase()))
+            .collect();
+        match tokens.is_empty() {
+            true => format!("({} IS NULL OR trim({}) = '')", expr, expr),
+            false => format!("({} IS NULL OR trim({}) = '' OR upper(trim({})) IN ({}))", expr, expr, expr, tokens.join(", ")),
+        }
+    }
+
+    /// Order of the rows in the files.
+    fn row_order(&self) -> &'static str {
+        match self.tables.len() {
+            1 => "rowid",
+            _ => "\"_file\", \"_row\"",
+        }
+    }
+}
+
+/// The result of the query, when it isn't the rows that are left.
+enum Output {
+    /// An aggregate of the column, grouped by the `GRP` columns.
+    Aggregate(AggFunc, String),
+    /// Every combination of the `GRP` columns and these.
+    Distinct(Vec<String>),
+}
+
+/// Translates a pipeline to a SQLite query on the tables `DbManager::import_csv`
+/// made for the selected files, prefixed with the chart type like SQL pipelines
+/// are, so `sqlite_cir_adapter` returns the same results `csvqb_to_cir` does.
+///
+/// Covers filters, `IS NULL`, `GRP` with one of `CSUM`, `CAVG`, `CCOUNT`, `CCOUNTD`, `CMIN`,
+/// `CMAX` or `DISTINCT`, `SORT`, `LIMIT` and `TOP`. Cells are compared and summed the way csvqb does:
+/// as numbers when they are numeric and as text otherwise. Dates are compared
+/// as text, which orders ISO 8601 dates correctly.
+pub fn csvqb_to_sql(
+    qb_pipeline: &[String],
+    file_indexes: &[usize],
+    files: &[(String, Arc<CsvGrid>)],
+    null_tokens: &NullTokens
+) -> Result<String, CsvqbError> {
+    let pipeline = check_pipeline(qb_pipeline, file_indexes, files)?;
+    let file_indexes = &input_indexes(&pipeline, file_indexes, files)?;
+    let src = qb_pipeline.join(" ");
+    let text = |span: Span| src.get(span.start..span.end).unwrap_or_default().to_string();
+    let unsupported = |message: String, span: Span| {
+        CsvqbError::new(CsvqbErrorKind::Unsupported, message, span).with_token(text(span))
+    };
+
+    let mut tables = Vec::new();
+    let mut tokens: Vec<&[String]> = Vec::new();
+    for &idx in file_indexes {
+        let Some((path, grid)) = files.get(idx) else { continue };
+        let (Some(table), Some(headers)) = (DbManager::table_name(path), grid.first()) else { continue };
+        tables.push((table, headers.as_slice()));
+        tokens.push(null_tokens.get(path));
+    }
+    if tables.is_empty() {
+        return Err(CsvqbError::new(CsvqbErrorKind::NoInput, "none of the selected files are loaded", Span::default()));
+    }
+    if !tokens.iter().all_equal() {
+        return Err(CsvqbError::new(
+            CsvqbErrorKind::Unsupported,
+            "SQL needs the same null values in every selected file",
+            Span::default(),
+        ));
+    }
+    let source = Source { tables, null_tokens: tokens[0] };
+
+    let mut filters: Vec<String> = Vec::new();
+    // Columns the query reads, which every file needs.
+    let mut read: Vec<String> = Vec::new();
+    let mut groups: Vec<String> = Vec::new();
+    let mut output: Option<Output> = None;
+    // Later sorts come first, earlier ones only break their ties.
+    let mut sorts: Vec<(String, bool)> = Vec::new();
+    let mut limit: Option<usize> = None;
+
+    for stage in &pipeline.stages {
+        match stage {
+            // Filters and groups after the output don't change it.
+            Stage::Filter { .. } | Stage::Group { .. } if output.is_some() => {}
+            Stage::Filter { condition, .. } => {
+                filters.push(condition_sql(condition, &source)?);
+                let _ = condition.visit_columns(&mut |column| {
+                    read.push(column.name.clone());
+                    Ok::<(), ()>(())
+                });
+            }
+            Stage::Group { columns, .. } => groups.extend(columns.iter().map(|column| column.name.clone())),
+            Stage::Sort { .. } if limit.is_some() => {
+                return Err(unsupported("SQL can't sort again after LIMIT".to_string(), stage.span()));
+            }
+            // Sorts before an aggregate are dropped once it comes, ordering the
+            // rows doesn't change what it computes.
+            Stage::Sort { column, descending, .. } => sorts.insert(0, (column.name.clone(), *descending)),
+            Stage::Limit { .. } if output.is_none() && pipeline.stages.iter().any(|stage| matches!(stage, Stage::Expr(_))) => {
+                return Err(unsupported("SQL can only LIMIT the rows of the result".to_string(), stage.span()));
+            }
+            Stage::Limit { count, .. } => limit = Some(limit.map_or(*count, |limit| limit.min(*count))),
+            Stage::Expr(_) if output.is_some() => {
+                return Err(unsupported("a SQL query has a single result, the pipeline has more".to_string(), stage.span()));
+            }
+            Stage::Expr(Expr::Aggregate { func, column, span }) => match func {
+                AggFunc::Sum | AggFunc::Avg | AggFunc::Count | AggFunc::CountDistinct | AggFunc::Min | AggFunc::Max => {
+                    output = Some(Output::Aggregate(*func, column.name.clone()));
+                    sorts.clear();
+                }
+                _ => return Err(unsupported(format!("'{}' has no SQLite equivalent", text(*span)), *span)),
+            },
+            Stage::Expr(Expr::Distinct { columns, .. }) => {
+                output = Some(Output::Distinct(columns.iter().map(|column| column.name.clone()).collect()));
+                sorts.clear();
+            }
+            Stage::Expr(expr) => {
+                return Err(unsupported(format!("only aggregates and DISTINCT translate to SQL, '{}' doesn't", expr.label()), expr.span()));
+            }
+            Stage::Join { span, .. }
+            | Stage::Derive { span, .. }
+            | Stage::Dates { span, .. }
+            | Stage::Bucket { span, .. }
+            | Stage::Dedup { span, .. }
+            | Stage::Fill { span, .. } => {
+                return Err(unsupported(format!("'{}' can't be translated to SQL yet", text(*span)), *span));
+            }
+        }
+    }
+
+    // csvqb skips the rows of files without a column where SQL has no row to skip.
+    read.extend(groups.iter().cloned());
+    match &output {
+        Some(Output::Aggregate(_, column)) => read.push(column.clone()),
+        Some(Output::Distinct(columns)) => read.extend(columns.iter().cloned()),
+        None => {}
+    }
+    for (table, headers) in &source.tables {
+        if let Some(missing) = read.iter().find(|column| !headers.contains(column)) {
+            return Err(CsvqbError::new(
+                CsvqbErrorKind::Unsupported,
+                format!("SQL needs '{}' in every selected file, table {} doesn't have it", missing, table),
+                Span::default(),
+            ).with_token(missing.clone()));
+        }
+    }
+
+    let chart = pipeline.chart.as_ref().map(|chart| format!("{} ", chart.name)).unwrap_or_default();
+    let filter = if filters.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", filters.join(" AND "))
+    };
+    let limit = limit.map(|limit| format!(" LIMIT {}", limit)).unwrap_or_default();
+
+    let Some(output) = output else {
+        // Without an output the rows that are left are the result.
+        let columns: Vec<String> = source.tables[0].1.iter()
+            .map(|header| format!("{} AS {}", source.column(header), quote_ident(header)))
+            .collect();
+        let mut order: Vec<String> = sorts.iter()
+            .map(|(column, descending)| sort_keys(&source.column(column), *descending))
+            .collect();
+        order.push(source.row_order().to_string());
+        return Ok(format!(
+            "{}SELECT {} FROM {}{} ORDER BY {}{}",
+            chart, columns.join(", "), source.from(), filter, order.join(", "), limit
+        ));
+    };
+
+    let (keys, aggregate): (Vec<String>, Option<(AggFunc, String, String)>) = match output {
+        // Grouping by every column leaves each combination once.
+        Output::Distinct(columns) => (groups.iter().chain(&columns).unique().cloned().collect(), None),
+        Output::Aggregate(func, column) => {
+            // Without GRP, CCOUNT counts every value of its column.
+            let keys = match (groups.is_empty(), func) {
+                (false, _) => groups.clone(),
+                (true, AggFunc::Count) => vec![column.clone()],
+                (true, _) => Vec::new(),
+            };
+            let value = source.column(&column);
+            let number = format!("CASE WHEN {} THEN CAST(trim({}) AS REAL) END", is_number(&format!("trim({})", value)), value);
+            let aggregate = match func {
+                AggFunc::Count => "COUNT(*)".to_string(),
+                AggFunc::CountDistinct => format!("COUNT(DISTINCT CASE WHEN {} THEN NULL ELSE {} END)", source.is_null(&value), value),
+                AggFunc::Sum => format!("COALESCE(SUM({}), '')", number),
+                AggFunc::Avg => format!("COALESCE(AVG({}), '')", number),
+                AggFunc::Min => format!("COALESCE(MIN({}), '')", number),
+                _ => format!("COALESCE(MAX({}), '')", number),
+            };
+            (keys, Some((func, column, aggregate)))
+        }
+    };
+
+    let mut columns: Vec<String> = keys.iter()
+        .map(|key| format!("{} AS {}", source.column(key), quote_ident(key)))
+        .collect();
+    if let Some((func, column, aggregate)) = &aggregate {
+        if keys.is_empty() {
+            columns.push(format!("{} AS {}", quote_literal(&func.default_label()), quote_ident(column)));
+        }
+        columns.push(format!("{} AS {}", aggregate, quote_ident(&func.result_name())));
+    }
+
+    let grouping = if keys.is_empty() {
+        // An aggregate of no rows is no row, like in csvqb.
+        " HAVING COUNT(*) > 0".to_string()
+    } else {
+        format!(" GROUP BY {}", keys.iter().map(|key| source.column(key)).collect::<Vec<_>>().join(", "))
+    };
+
+    // Sorting in an outer query lets the order read the result columns by name.
+    let mut order: Vec<String> = sorts.iter()
+        .map(|(column, descending)| sort_keys(&quote_ident(column), *descending))
+        .collect();
+    order.extend(keys.iter().map(|key| key_order(&quote_ident(key))));
+    let order = if order.is_empty() {
+        String::new()
+    } else {
+        format!(" ORDER BY {}", order.join(", "))
+    };
+
+    Ok(format!(
+        "{}SELECT * FROM (SELECT {} FROM {}{}{}){}{}",
+        chart, columns.join(", "), source.from(), filter, grouping, order, limit
+    ))
+}
+
+fn condition_sql(condition: &Condition, source: &Source) -> Result<String, CsvqbError> {
+    Ok(match condition {
+        Condition::Compare { column, op, value } => compare_sql(&source.column(&column.name), *op, &value.text),
+        Condition::Between { column, from, to } => {
+            let column = source.column(&column.name);
+            format!(
+                "({} AND {})",
+                compare_sql(&column, BinaryOp::GreaterEq, &from.text),
+                compare_sql(&column, BinaryOp::LessEq, &to.text)
+            )
+        }
+        Condition::IsNull { column, negated, .. } => {
+            let is_null = source.is_null(&source.column(&column.name));
+            if *negated { format!("NOT {}", is_null) } else { is_null }
+        }
+        Condition::And(lhs, rhs) => format!("({} AND {})", condition_sql(lhs, source)?, condition_sql(rhs, source)?),
+        Condition::Or(lhs, rhs) => format!("({} OR {})", condition_sql(lhs, source)?, condition_sql(rhs, source)?),
+        Condition::Not(inner, _) => format!("NOT {}", condition_sql(inner, source)?),
+        // SQLite only folds the case of ASCII letters and has no regular expressions.
+        Condition::Text { .. } | Condition::Matches { .. } => return Err(CsvqbError::new(
+            CsvqbErrorKind::Unsupported,
+            "string functions and MATCHES can't be translated to SQL yet",
+            condition.span(),
+        )),
+    })
+}
+
+/// `BinaryOp::compare_cell` in SQL: numbers when the cell and the value are
+/// both numeric, text otherwise.
+fn compare_sql(column: &str, op: BinaryOp, value: &str) -> String {
+    let literal = quote_literal(value);
+    match op {
+        BinaryOp::Contains => format!("instr({}, {}) > 0", column, literal),
+        BinaryOp::StartsWith => format!("substr({}, 1, {}) = {}", column, value.chars().count(), literal),
+        BinaryOp::EndsWith => format!("substr({}, -{}) = {}", column, value.chars().count(), literal),
+        op => match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => format!(
+                "(CASE WHEN {} THEN CAST({} AS REAL) {} {} ELSE {} {} {} END)",
+                is_number(column), column, op.symbol(), number, column, op.symbol(), literal
+            ),
+            _ => format!("{} {} {}", column, op.symbol(), literal),
+        },
+    }
+}
+
+/// Whether the text of `expr` is a number. The cast has REAL affinity, so the
+/// comparison converts the text to a number only when all of it is one. SQLite
+/// allows spaces around it where `str::parse` doesn't.
+fn is_number(expr: &str) -> String {
+    format!("(CAST({} AS REAL) = {} AND trim({}) = {})", expr, expr, expr, expr)
+}
+
+/// `sort_grid` in SQL: empty cells last, then numbers before text, reversed
+/// together when descending.
+fn sort_keys(column: &str, descending: bool) -> String {
+    let direction = if descending { " DESC" } else { "" };
+    format!(
+        "({} IS NULL OR {} = ''), CASE WHEN {} THEN 0 ELSE 1 END{}, CASE WHEN {} THEN CAST({} AS REAL) END{}, {}{}",
+        column, column, is_number(column), direction, is_number(column), column, direction, column, direction
+    )
+}
+
+/// `compare_cells` in SQL, the order csvqb puts groups in.
+fn key_order(column: &str) -> String {
+    format!(
+        "CASE WHEN {} THEN 0 ELSE 1 END, CASE WHEN {} THEN CAST({} AS REAL) END, {}",
+        is_number(column), is_number(column), column, column
+    )
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+    use crate::cir_adapters::sqlite_cir_adapter;
+    use crate::csvqb::lexer::pipeline_tokens;
+    use crate::csvqb::parallel::Progress;
+    use crate::csvqb::{csvqb_to_cir, CIR};
+
+    fn grid(rows: &[&[&str]]) -> Arc<CsvGrid> {
+        Arc::new(rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect())
+    }
+
+    fn files() -> Vec<(String, Arc<CsvGrid>)> {
+        vec![
+            ("sales.csv".to_string(), grid(&[
+                &["region", "amount", "qty", "day", "order", "unit price"],
+                &["EU", "10", "2", "2024-01-03", "a", "1.5"],
+                &["US", "5", "3", "2024-02-10", "b", "2"],
+                &["EU", "1", "1", "2024-02-11", "c", "n/a"],
+                &["APAC", "x", "4", "2024-03-01", "d", ""],
+                &["US", " 7 ", "", "2024-03-15", "e", "3.25"],
+                &["", "2.5", "5", "2024-04-01", "f", "1"],
+            ])),
+            ("more sales.csv".to_string(), grid(&[
+                &["region", "amount", "qty", "day", "order", "unit price"],
+                &["EU", "4", "2", "2024-05-03", "g", "2"],
+                &["JP", "12", "1", "2024-05-10", "h", "4"],
+            ])),
+        ]
+    }
+
+    fn same_cell(left: &str, right: &str) -> bool {
+        left == right || matches!(
+            (left.trim().parse::<f64>(), right.trim().parse::<f64>()),
+            (Ok(l), Ok(r)) if (l - r).abs() <= 1e-9 * l.abs().max(1.0)
+        )
+    }
+
+    fn grids(results: &[CIR]) -> Vec<CsvGrid> {
+        results.iter()
+            .filter_map(|cir| match cir {
+                CIR::QueryResult(grid) => Some(grid.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Runs `pipeline` natively and through SQLite and checks both give the same grid.
+    fn check(pipeline: &str, file_indexes: &[usize]) {
+        let files = files();
+        let mut conn = Connection::open_in_memory().unwrap();
+        for (path, grid) in &files {
+            DbManager::import_csv(&mut conn, &DbManager::table_name(path).unwrap(), grid).unwrap();
+        }
+        let tokens = pipeline_tokens(pipeline);
+        let native = csvqb_to_cir(&tokens, file_indexes, &files, &[], &NullTokens::default(), &Progress::default()).unwrap();
+        let query = csvqb_to_sql(&tokens, file_indexes, &files, &NullTokens::default()).unwrap();
+        let translated = sqlite_cir_adapter(&conn, &query, &[]).unwrap();
+
+        let (native, translated) = (grids(&native.results), grids(&translated));
+        assert_eq!(native.len(), 1, "{}", pipeline);
+        assert_eq!(translated.len(), 1, "{}", pipeline);
+        let (native, translated) = (&native[0], &translated[0]);
+        assert_eq!(native.len(), translated.len(), "{}\n{}\n{:?}\n{:?}", pipeline, query, native, translated);
+        for (native_row, translated_row) in native.iter().zip(translated) {
+            assert!(
+                native_row.len() == translated_row.len()
+                    && native_row.iter().zip(translated_row).all(|(l, r)| same_cell(l, r)),
+                "{}\n{}\n{:?}\n{:?}", pipeline, query, native, translated
+            );
+        }
+    }
+
+    #[test]
+    fn grouped_aggregates() {
+        for func in ["CSUM", "CAVG", "CCOUNT", "CCOUNTD", "CMIN", "CMAX"] {
+            check(&format!("GRP region {} amount", func), &[0]);
+            check(&format!("GRP region day {} qty", func), &[0]);
+        }
+    }
+
+    #[test]
+    fn ungrouped_aggregates() {
+        for func in ["CSUM", "CAVG", "CCOUNT", "CCOUNTD", "CMIN", "CMAX"] {
+            check(&format!("{} amount", func), &[0]);
+        }
+        check("region = 'none' CSUM amount", &[0]);
+    }
+
+    #[test]
+    fn filters() {
+        for filter in [
+            "amount > 4",
+            "amount <= '5'",
+            "region = EU",
+            "region != EU",
+            "region CONTAINS U",
+            "region STARTSWITH E",
+            "region ENDSWITH S",
+            "NOT region = EU",
+            "region = EU OR qty >= 3",
+            "day BETWEEN 2024-02-01 2024-03-31",
+            "day < 2024-03-01",
+            "'unit price' IS NULL",
+            "WHERE qty IS NOT NULL",
+        ] {
+            check(&format!("{} GRP region CSUM amount", filter), &[0]);
+            check(filter, &[0]);
+        }
+    }
+
+    #[test]
+    fn sorts_and_limits() {
+        check("GRP region CSUM amount SORT sum DESC LIMIT 2", &[0]);
+        check("GRP region CCOUNT amount SORT count", &[0]);
+        check("SORT amount", &[0]);
+        check("SORT amount DESC LIMIT 3", &[0]);
+        check("SORT qty SORT region DESC", &[0]);
+        check("SORT amount TOP 2 BY amount", &[0]);
+        check("GRP region CSUM amount TOP 2 BY sum", &[0]);
+    }
+
+    #[test]
+    fn distinct() {
+        check("DISTINCT region", &[0]);
+        check("DISTINCT region qty", &[0]);
+        check("GRP region DISTINCT qty", &[0]);
+        check("amount > 3 DISTINCT region SORT region DESC LIMIT 2", &[0]);
+        check("DISTINCT region", &[0, 1]);
+        check("CCOUNTD region", &[0, 1]);
+        check("GRP day CCOUNTD 'unit price'", &[0]);
+    }
+
+    #[test]
+    fn several_files() {
+        check("GRP region CSUM amount", &[0, 1]);
+        check("amount > 3 SORT day DESC", &[0, 1]);
+        check("CCOUNT region", &[0, 1]);
+    }
+
+    #[test]
+    fn awkward_columns() {
+        check("GRP order CAVG 'unit price'", &[0]);
+        check("'unit price' > 1.5 SORT order DESC", &[0]);
+    }
+
+    #[test]
+    fn unsupported() {
+        let files = files();
+        for pipeline in [
+            "GRP region CMEDIAN amount",
+            "CSUM amount CSUM qty",
+            "LIMIT 2 CSUM amount",
+            "DEDUP region CSUM amount",
+            "LOWER region = eu CSUM amount",
+            "order MATCHES '[a-c]'",
+            "TRIM region AS region GRP region CSUM amount",
+            "FILL amount 0 CSUM amount",
+            "BIN amount 5",
+            "GRP region CFIT amount qty",
+        ] {
+            let err = csvqb_to_sql(&pipeline_tokens(pipeline), &[0], &files, &NullTokens::default()).unwrap_err();
+            assert_eq!(err.kind, CsvqbErrorKind::Unsupported, "{}", pipeline);
+        }
+    }
+
+    #[test]
+    fn null_tokens() {
+        let files = files();
+        let mut tokens = NullTokens::default();
+        tokens.set("sales.csv", vec!["x".to_string()]);
+        let query = csvqb_to_sql(&pipeline_tokens("amount IS NULL"), &[0], &files, &tokens).unwrap();
+        assert!(query.contains("IN ('X')"), "{}", query);
+        let err = csvqb_to_sql(&pipeline_tokens("CSUM amount"), &[0, 1], &files, &tokens).unwrap_err();
+        assert_eq!(err.kind, CsvqbErrorKind::Unsupported);
+    }
+}
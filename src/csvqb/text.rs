@@ -0,0 +1,116 @@
+use regex::Regex;
+use crate::csvqb::ast::{Expr, Literal};
+use crate::csvqb::error::CsvqbError;
+use crate::csvqb::Operator;
+
+/// Keywords of the string functions.
+pub const TEXT_OPERATORS: &[Operator] = &[
+    Operator::Trim,
+    Operator::Lower,
+    Operator::Upper,
+    Operator::Substr,
+    Operator::Split,
+    Operator::Extract,
+    Operator::Replace,
+];
+
+/// A regular expression written in the pipeline, compiled once when it is parsed.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub source: String,
+    pub regex: Regex,
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Pattern {
+    pub fn new(literal: &Literal) -> Result<Pattern, CsvqbError> {
+        match Regex::new(&literal.text) {
+            Ok(regex) => Ok(Pattern { source: literal.text.clone(), regex }),
+            Err(err) => {
+                // The last line of a regex error says what is wrong, the ones
+                // before it point at the pattern in a way the error span already does.
+                let err = err.to_string();
+                let reason = err.lines().last().unwrap_or_default().trim_start_matches("error: ");
+                Err(CsvqbError::syntax(format!("'{}' isn't a valid pattern, {}", literal.text, reason), literal.span)
+                    .with_token(literal.text.clone()))
+            }
+        }
+    }
+}
+
+/// String functions, applied to the cells of a column or to what another
+/// string function made of them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextFunc {
+    /// `TRIM region`, without the whitespace around it.
+    Trim,
+    /// `LOWER region`
+    Lower,
+    /// `UPPER region`
+    Upper,
+    /// `SUBSTR code 2 3`, 3 characters from the 2nd one on. Without a length
+    /// the rest of the cell.
+    Substr { start: usize, len: Option<usize> },
+    /// `SPLIT email @ 2`, the second part of the cell split on `@`, empty when
+    /// there are fewer parts.
+    Split { delimiter: String, part: usize },
+    /// `EXTRACT code '[0-9]+'`, the first match of the pattern, or what its
+    /// first group matched when it has one. Empty when nothing matches.
+    Extract(Pattern),
+    /// `REPLACE phone '[^0-9]' ''`, every match of the pattern replaced. `$1` in
+    /// the replacement stands for what the first group matched.
+    Replace(Pattern, String),
+}
+
+impl TextFunc {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            TextFunc::Trim => "TRIM",
+            TextFunc::Lower => "LOWER",
+            TextFunc::Upper => "UPPER",
+            TextFunc::Substr { .. } => "SUBSTR",
+            TextFunc::Split { .. } => "SPLIT",
+            TextFunc::Extract(_) => "EXTRACT",
+            TextFunc::Replace(..) => "REPLACE",
+        }
+    }
+
+    pub fn apply(&self, cell: &str) -> String {
+        match self {
+            TextFunc::Trim => cell.trim().to_string(),
+            TextFunc::Lower => cell.to_lowercase(),
+            TextFunc::Upper => cell.to_uppercase(),
+            TextFunc::Substr { start, len } => cell.chars()
+                .skip(start.saturating_sub(1))
+                .take(len.unwrap_or(usize::MAX))
+                .collect(),
+            TextFunc::Split { delimiter, part } => cell.split(delimiter.as_str())
+                .nth(part.saturating_sub(1))
+                .unwrap_or_default()
+                .to_string(),
+            TextFunc::Extract(pattern) => pattern.regex.captures(cell)
+                .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+                .map(|found| found.as_str().to_string())
+                .unwrap_or_default(),
+            TextFunc::Replace(pattern, replacement) => pattern.regex.replace_all(cell, replacement.as_str()).into_owned(),
+        }
+    }
+}
+
+/// Text of a string function, or of a column, for one row. `None` when the
+/// row has no such column.
+pub fn eval_text(expr: &Expr, headers: &[String], row: &[String]) -> Option<String> {
+    match expr {
+        Expr::Field(ident) => headers.iter()
+            .position(|h| *h == ident.name)
+            .and_then(|idx| row.get(idx))
+            .cloned(),
+        Expr::Text { func, arg, .. } => eval_text(arg, headers, row).map(|text| func.apply(&text)),
+        _ => None,
+    }
+}
@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::ast::WindowFunc;
+use crate::csvqb::columns::{read_numeric, typed_column, Numeric};
+use crate::csvqb::compute::format_number;
+use crate::csvqb::order::{compare_cells, compare_keys};
+use crate::csvqb::{EvalContext, Tally};
+
+struct WindowRow {
+    partition: Vec<String>,
+    order: String,
+    value: Option<f64>,
+}
+
+/// Computes a window function over every selected file. Rows are split into
+/// partitions by the `GRP` columns and ordered by `order_by` within each one,
+/// or kept in file order without it. The result has the partition columns, the
+/// order column and the window value, ready to be drawn as a line chart. Also
+/// returns how many rows were read, how many of them were null and how many
+/// values were skipped because they aren't numeric.
+pub fn col_window(
+    file_indexes: &[usize],
+    csv_files: &[(String, Arc<CsvGrid>)],
+    context: &EvalContext,
+    column: &str,
+    order_by: Option<&str>,
+    partition_by: Option<&[String]>,
+    func: WindowFunc
+) -> (Vec<Vec<String>>, Tally) {
+    let partition_cols = partition_by.unwrap_or_default();
+    let mut rows: Vec<WindowRow> = Vec::new();
+    let mut tally = Tally::default();
+
+    for &file_idx in file_indexes {
+        if let Some((_, grid)) = csv_files.get(file_idx) {
+            let Some(headers) = grid.first() else { continue };
+            let Some(col_idx) = headers.iter().position(|h| h == column) else { continue };
+            let order_idx = order_by.and_then(|order| headers.iter().position(|h| h == order));
+            let partition_idxs: Vec<Option<usize>> = partition_cols.iter()
+                .map(|partition| headers.iter().position(|h| h == partition))
+                .collect();
+            let typed = typed_column(&context.tables, file_idx, column);
+            let null_tokens = context.null_tokens.get(&csv_files[file_idx].0);
+
+            for (row_idx, row) in grid.iter().skip(1).enumerate() {
+                let cell = |idx: Option<usize>| idx.and_then(|idx| row.get(idx)).cloned().unwrap_or_default();
+                tally.rows += 1;
+                let value = match read_numeric(typed, row_idx, &cell(Some(col_idx)), null_tokens) {
+                    Numeric::Value(value) => Some(value),
+                    Numeric::NotNumeric => {
+                        tally.skipped += 1;
+                        None
+                    }
+                    Numeric::Null => {
+                        tally.nulls += 1;
+                        None
+                    }
+                };
+                rows.push(WindowRow {
+                    partition: partition_idxs.iter().map(|&idx| cell(idx)).collect(),
+                    order: match order_by {
+                        Some(_) => cell(order_idx),
+                        None => (rows.len() + 1).to_string(),
+                    },
+                    value,
+                });
+            }
+        }
+    }
+
+    let order_rows = |a: &WindowRow, b: &WindowRow| {
+        compare_keys(&a.partition, &b.partition, partition_cols.len()).then_with(|| match func {
+            WindowFunc::Rank => b.value.unwrap_or(f64::NEG_INFINITY).total_cmp(&a.value.unwrap_or(f64::NEG_INFINITY)),
+            _ if order_by.is_some() => compare_cells(&a.order, &b.order),
+            _ => Ordering::Equal,
+        })
+    };
+    rows.sort_by(order_rows);
+
+    let mut header_row = partition_cols.to_vec();
+    header_row.push(match func {
+        WindowFunc::Rank => column.to_string(),
+        _ => order_by.unwrap_or("row").to_string(),
+    });
+    header_row.push(func.result_name());
+    let mut query_grid = vec![header_row];
+
+    for partition in rows.chunk_by(|a, b| a.partition == b.partition) {
+        let values: Vec<Option<f64>> = partition.iter().map(|row| row.value).collect();
+        let results = window_values(&values, func);
+
+        for (row, result) in partition.iter().zip(results) {
+            let mut out = row.partition.clone();
+            out.push(match func {
+                WindowFunc::Rank => format_number(row.value),
+                _ => row.order.clone(),
+            });
+            out.push(format_number(result));
+            query_grid.push(out);
+        }
+    }
+
+    (query_grid, tally)
+}
+
+/// Window values of one partition whose rows are already in order.
+fn window_values(values: &[Option<f64>], func: WindowFunc) -> Vec<Option<f64>> {
+    match func {
+        WindowFunc::RunningSum => {
+            // Rows without a number carry the total so far.
+            let mut total = None;
+            values.iter().map(|value| {
+                if let Some(value) = value {
+                    total = Some(total.unwrap_or(0.0) + value);
+                }
+                total
+            }).collect()
+        }
+        WindowFunc::MovingAvg(n) => (0..values.len()).map(|idx| {
+            let window: Vec<f64> = values[(idx + 1).saturating_sub(n)..=idx].iter().flatten().copied().collect();
+            (!window.is_empty()).then(|| window.iter().sum::<f64>() / window.len() as f64)
+        }).collect(),
+        WindowFunc::Lag(n) => (0..values.len()).map(|idx| {
+            let earlier = idx.checked_sub(n).and_then(|earlier| values[earlier])?;
+            Some(values[idx]? - earlier)
+        }).collect(),
+        WindowFunc::Lead(n) => (0..values.len()).map(|idx| {
+            let later = values.get(idx + n).copied().flatten()?;
+            Some(later - values[idx]?)
+        }).collect(),
+        // Rows are ordered by value, largest first, so equal values are next to
+        // each other and share the rank of the first one.
+        WindowFunc::Rank => {
+            let mut rank = 0;
+            values.iter().enumerate().map(|(idx, value)| {
+                let value = (*value)?;
+                if idx == 0 || values[idx - 1] != Some(value) {
+                    rank = idx + 1;
+                }
+                Some(rank as f64)
+            }).collect()
+        }
+    }
+}
@@ -3,9 +3,11 @@ use std::error::Error;
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use itertools::Itertools;
 use rusqlite::Connection;
 use crate::charter_utilities::{get_default_db_path, CsvGrid};
+use crate::result_cache::forget_table_hashes;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DatabaseType {
@@ -67,6 +69,8 @@ pub struct DatabaseConfig {
     pub db_type: DatabaseType,
     pub connection_string: String,
     pub database_path: DatabaseSource,
+    /// Keep results of pipelines in the database so they are reused after a restart.
+    pub cache_results: bool,
 }
 
 impl Default for DatabaseConfig {
@@ -76,10 +80,30 @@ impl Default for DatabaseConfig {
             db_type: DatabaseType::SQLite,
             connection_string: String::new(),
             database_path: DatabaseSource::Default,
+            cache_results: false,
         }
     }
 }
 
+const RESERVED_WORDS: &[&str] = &[
+    "index", "Index", "group", "order", "table", "select", "where", "from", "having", "update",
+    "delete", "references", "ABORT", "ACTION", "ADD", "AFTER", "ALL", "ALTER", "ALWAYS", "ANALYZE",
+    "AND", "AS", "ASC", "ATTACH", "AUTOINCREMENT", "BEFORE", "BEGIN", "BETWEEN", "BY", "CASCADE",
+    "CASE", "CAST", "CHECK", "COLLATE", "COLUMN", "COMMIT", "CONFLICT", "CONSTRAINT", "CREATE",
+    "CROSS", "CURRENT", "CURRENT_DATE", "CURRENT_TIME", "CURRENT_TIMESTAMP", "DATABASE", "DEFAULT",
+    "DEFERRABLE", "DEFERRED", "DESC", "DETACH", "DISTINCT", "DO", "DROP", "EACH", "ELSE", "END",
+    "ESCAPE", "EXCEPT", "EXCLUSIVE", "EXISTS", "EXPLAIN", "FAIL", "FILTER", "FIRST", "FOLLOWING",
+    "FOR", "FOREIGN", "FULL", "GENERATED", "GLOB", "GROUPS", "IF", "IGNORE", "IMMEDIATE", "IN",
+    "INDEXED", "INITIALLY", "INNER", "INSERT", "INSTEAD", "INTERSECT", "INTO", "IS", "ISNULL",
+    "JOIN", "KEY", "LAST", "LEFT", "LIKE", "LIMIT", "MATCH", "NATURAL", "NO", "NOT", "NOTHING",
+    "NOTNULL", "NULL", "NULLS", "OF", "OFFSET", "ON", "OR", "OTHERS", "OUTER", "OVER", "PARTITION",
+    "PLAN", "PRAGMA", "PRECEDING", "PRIMARY", "QUERY", "RAISE", "RANGE", "RECURSIVE", "REGEXP",
+    "REINDEX", "RELEASE", "RENAME", "REPLACE", "RESTRICT", "RETURNING", "RIGHT", "ROLLBACK", "ROW",
+    "ROWS", "SAVEPOINT", "SET", "STORED", "TEMP", "TEMPORARY", "THEN", "TIES", "TO", "TRANSACTION",
+    "TRIGGER", "UNBOUNDED", "UNION", "UNIQUE", "USING", "VACUUM", "VALUES", "VIEW", "VIRTUAL",
+    "WHEN", "WINDOW", "WITH", "WITHOUT"
+];
+
 pub struct DbManager {
     config: DatabaseConfig,
     pub(crate) connection: Option<Connection>,
@@ -112,30 +136,56 @@ impl DbManager {
 
 
 
-    pub fn import_all_csvs(conn: &mut Connection, csv_files: &Vec<(String, CsvGrid)>) -> Result<(), Box<dyn Error>> {
+    pub fn import_all_csvs(conn: &mut Connection, csv_files: &[(String, Arc<CsvGrid>)]) -> Result<(), Box<dyn Error>> {
         for (file_path, csv_grid) in csv_files {
-            let table_name = file_path
-                .split(['/', '\\'])
-                .last()
-                .and_then(|s| s.split('.').next())
-                .ok_or("Invalid file path")?;
+            let table_name = DbManager::table_name(file_path).ok_or("Invalid file path")?;
 
-            DbManager::import_csv(conn, table_name, csv_grid)?;
+            DbManager::import_csv(conn, &table_name, csv_grid)?;
         }
         Ok(())
     }
-    pub fn import_csv(conn: &mut Connection, table_name: &str, csv_data: &CsvGrid) -> Result<(), Box<dyn Error>> {
 
-        if csv_data.is_empty() {
-            return Err("Empty CSV data".into());
-        }
+    /// The table `import_all_csvs` puts a csv file in.
+    pub fn table_name(file_path: &str) -> Option<String> {
+        file_path
+            .split(['/', '\\'])
+            .next_back()
+            .and_then(|s| s.split('.').next())
+            .map(DbManager::sanitize_table_name)
+    }
 
-        let table_name = table_name.split(['/', '\\', '-', ' '])
+    pub fn sanitize_table_name(table_name: &str) -> String {
+        table_name.split(['/', '\\', '-', ' '])
             .map(|name| {
                 name.chars()
                     .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
                     .collect::<String>()
-            }).join("_");
+            }).join("_")
+    }
+
+    /// The column `import_csv` creates for `header`. Reserved words get the
+    /// table name in front so the statement still parses.
+    pub fn sanitize_column_name(table_name: &str, header: &str) -> String {
+        let sanitized = header.chars()
+            .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+            .collect::<String>();
+
+        let lower_sanitized = sanitized.to_lowercase();
+
+        if RESERVED_WORDS.contains(&lower_sanitized.as_str()) {
+            format!("{}_{}", table_name, sanitized)
+        } else {
+            sanitized
+        }
+    }
+
+    pub fn import_csv(conn: &mut Connection, table_name: &str, csv_data: &CsvGrid) -> Result<(), Box<dyn Error>> {
+
+        if csv_data.is_empty() {
+            return Err("Empty CSV data".into());
+        }
+
+        let table_name = DbManager::sanitize_table_name(table_name);
 
         let check_table_sql = "SELECT name FROM sqlite_master WHERE type='table' AND name=?";
 
@@ -144,43 +194,10 @@ impl DbManager {
 
         if !exists {
             let headers = &csv_data[0].iter()
-                .map(|h| {
-                    let sanitized = h.chars()
-                        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
-                        .collect::<String>();
-
-                    let lower_sanitized = sanitized.to_lowercase();
-
-                    let column_name = if RESERVED_WORDS.contains(&lower_sanitized.as_str()) {
-                        format!("{}_{}", table_name, sanitized)
-                    } else {
-                        sanitized
-                    };
-
-                    format!("{}", column_name)
-                })
+                .map(|h| DbManager::sanitize_column_name(&table_name, h))
                 .collect::<Vec<_>>()
                 .join(", ");
 
-            const RESERVED_WORDS: &[&str] = &[
-                "index", "Index", "group", "order", "table", "select", "where", "from", "having", "update",
-                "delete", "references", "ABORT", "ACTION", "ADD", "AFTER", "ALL", "ALTER", "ALWAYS", "ANALYZE",
-                "AND", "AS", "ASC", "ATTACH", "AUTOINCREMENT", "BEFORE", "BEGIN", "BETWEEN", "BY", "CASCADE",
-                "CASE", "CAST", "CHECK", "COLLATE", "COLUMN", "COMMIT", "CONFLICT", "CONSTRAINT", "CREATE",
-                "CROSS", "CURRENT", "CURRENT_DATE", "CURRENT_TIME", "CURRENT_TIMESTAMP", "DATABASE", "DEFAULT",
-                "DEFERRABLE", "DEFERRED", "DESC", "DETACH", "DISTINCT", "DO", "DROP", "EACH", "ELSE", "END",
-                "ESCAPE", "EXCEPT", "EXCLUSIVE", "EXISTS", "EXPLAIN", "FAIL", "FILTER", "FIRST", "FOLLOWING",
-                "FOR", "FOREIGN", "FULL", "GENERATED", "GLOB", "GROUPS", "IF", "IGNORE", "IMMEDIATE", "IN",
-                "INDEXED", "INITIALLY", "INNER", "INSERT", "INSTEAD", "INTERSECT", "INTO", "IS", "ISNULL",
-                "JOIN", "KEY", "LAST", "LEFT", "LIKE", "LIMIT", "MATCH", "NATURAL", "NO", "NOT", "NOTHING",
-                "NOTNULL", "NULL", "NULLS", "OF", "OFFSET", "ON", "OR", "OTHERS", "OUTER", "OVER", "PARTITION",
-                "PLAN", "PRAGMA", "PRECEDING", "PRIMARY", "QUERY", "RAISE", "RANGE", "RECURSIVE", "REGEXP",
-                "REINDEX", "RELEASE", "RENAME", "REPLACE", "RESTRICT", "RETURNING", "RIGHT", "ROLLBACK", "ROW",
-                "ROWS", "SAVEPOINT", "SET", "STORED", "TEMP", "TEMPORARY", "THEN", "TIES", "TO", "TRANSACTION",
-                "TRIGGER", "UNBOUNDED", "UNION", "UNIQUE", "USING", "VACUUM", "VALUES", "VIEW", "VIRTUAL",
-                "WHEN", "WINDOW", "WITH", "WITHOUT"
-            ];
-
             let create_table_sql = format!(
                 "CREATE TABLE {:?} ({})",
                 table_name,
@@ -215,6 +232,8 @@ impl DbManager {
             }
 
             tx.commit()?;
+            // A table dropped and made again under the same name may still have a hash.
+            forget_table_hashes(conn, &[table_name])?;
         }
 
         Ok(())
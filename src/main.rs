@@ -8,6 +8,9 @@ mod charter_csv;
 pub mod session;
 mod db_manager;
 mod cir_adapters;
+mod query_executor;
+mod result_cache;
+mod pipeline_params;
 pub mod components;
 
 use charter_csv::CharterCsvApp;
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use rusqlite::types::Value as SqlValue;
+use serde_json::{json, Value};
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::{quote_word, Span};
+use crate::csvqb::macros::{expand_macros, Macro};
+
+/// How the Data Explorer asks for the value of a placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamKind {
+    Text,
+    Number,
+    /// Picked from the distinct values of a column of the selected files.
+    Values(String),
+}
+
+/// A `$name` placeholder of the pipelines and the value it stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub kind: ParamKind,
+    pub value: String,
+}
+
+/// Values of the placeholders by name, without the `$`.
+pub type Params = BTreeMap<String, Param>;
+
+impl Param {
+    /// A new placeholder is picked from a column when one has its name.
+    pub fn guess(name: &str, headers: &[String]) -> Param {
+        let kind = match headers.iter().find(|header| header.eq_ignore_ascii_case(name)) {
+            Some(header) => ParamKind::Values(header.clone()),
+            None => ParamKind::Text,
+        };
+        Param { kind, value: String::new() }
+    }
+
+    fn number(&self) -> Option<f64> {
+        self.value.trim().parse::<f64>().ok().filter(|number| number.is_finite())
+    }
+}
+
+/// Names of the `$name` placeholders in a pipeline or query, in the order they
+/// first appear. Anything inside quotes is text and has none.
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '$') => {
+                let mut end = start + 1;
+                while let Some(&(idx, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = idx + c.len_utf8();
+                    chars.next();
+                }
+                let name = &text[start + 1..end];
+                if !name.is_empty() && !names.iter().any(|known| known == name) {
+                    names.push(name.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// The csvqb pipeline with every `$name` token replaced by its value. Numbers
+/// are written as they are and text is quoted where it needs to be, so a value
+/// always stays a single token.
+pub fn bind_csvqb(pipeline: &[String], params: &Params) -> Result<Vec<String>, CsvqbError> {
+    let mut start = 0;
+    let mut bound = Vec::with_capacity(pipeline.len());
+    for token in pipeline {
+        let span = Span::new(start, start + token.len());
+        start = span.end + 1;
+        let Some(name) = token.strip_prefix('$').filter(|name| !name.is_empty()) else {
+            bound.push(token.clone());
+            continue;
+        };
+
+        let param = params.get(name).filter(|param| !param.value.is_empty()).ok_or_else(|| {
+            CsvqbError::new(CsvqbErrorKind::NoInput, format!("no value for {}, set it under Parameters", token), span)
+                .with_token(token.clone())
+        })?;
+        bound.push(match param.kind {
+            ParamKind::Number => match param.number() {
+                Some(number) => number.to_string(),
+                None => return Err(CsvqbError::new(
+                    CsvqbErrorKind::TypeMismatch,
+                    format!("{} needs a number but '{}' isn't one", token, param.value),
+                    span,
+                ).with_token(token.clone())),
+            },
+            _ => quote_word(&param.value),
+        });
+    }
+    Ok(bound)
+}
+
+/// The csvqb pipeline as it runs: macro calls expanded, then placeholders bound.
+pub fn prepare_pipeline(pipeline: &[String], macros: &[Macro], params: &Params) -> Result<Vec<String>, CsvqbError> {
+    bind_csvqb(&expand_macros(pipeline, macros)?, params)
+}
+
+/// Values to bind to the `$name` parameters of a SQL query. Numbers are bound
+/// as numbers, whole ones as integers so `LIMIT $n` works. Placeholders without
+/// a usable value are left out, running the query then reports them.
+pub fn sql_params(query: &str, params: &Params) -> Vec<(String, SqlValue)> {
+    placeholders(query).into_iter()
+        .filter_map(|name| {
+            let param = params.get(&name).filter(|param| !param.value.is_empty())?;
+            let value = match param.kind {
+                ParamKind::Number => match param.number()? {
+                    number if number.fract() == 0.0 && number.abs() < i64::MAX as f64 => SqlValue::Integer(number as i64),
+                    number => SqlValue::Real(number),
+                },
+                _ => SqlValue::Text(param.value.clone()),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+pub fn params_to_json(params: &Params) -> Value {
+    let params: serde_json::Map<String, Value> = params.iter()
+        .map(|(name, param)| {
+            let kind = match &param.kind {
+                ParamKind::Text => json!("text"),
+                ParamKind::Number => json!("number"),
+                ParamKind::Values(column) => json!({ "values": column }),
+            };
+            (name.clone(), json!({ "kind": kind, "value": param.value }))
+        })
+        .collect();
+    Value::Object(params)
+}
+
+/// Params saved by `params_to_json`, entries that don't read are dropped.
+pub fn params_from_json(value: &Value) -> Params {
+    let Some(params) = value.as_object() else { return Params::new() };
+    params.iter()
+        .filter_map(|(name, param)| {
+            let kind = match param.get("kind")? {
+                Value::String(kind) if kind == "number" => ParamKind::Number,
+                Value::String(_) => ParamKind::Text,
+                kind => ParamKind::Values(kind.get("values")?.as_str()?.to_string()),
+            };
+            let value = param.get("value")?.as_str()?.to_string();
+            Some((name.clone(), Param { kind, value }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csvqb::lexer::pipeline_tokens;
+
+    fn params(values: &[(&str, ParamKind, &str)]) -> Params {
+        values.iter()
+            .map(|(name, kind, value)| (name.to_string(), Param { kind: kind.clone(), value: value.to_string() }))
+            .collect()
+    }
+
+    fn macros() -> Vec<Macro> {
+        vec![
+            Macro::new("big", "col", "WHERE $col > $min").unwrap(),
+            Macro::new("in_region", "", "WHERE region = $region").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn macros_expand_before_placeholders_are_bound() {
+        let params = params(&[
+            ("min", ParamKind::Number, " 5.50 "),
+            ("region", ParamKind::Values("region".to_string()), "New York"),
+            ("limit", ParamKind::Number, "3"),
+        ]);
+        let cases = [
+            ("@big amount CSUM amount", "WHERE amount > 5.5 CSUM amount"),
+            ("@in_region LIMIT $limit", "WHERE region = \"New York\" LIMIT 3"),
+            // A macro argument can be a placeholder too.
+            ("@big $region", "WHERE \"New York\" > 5.5"),
+            ("CSUM amount", "CSUM amount"),
+            // Compared values and quoted words are text, not calls.
+            ("WHERE email CONTAINS @home", "WHERE email CONTAINS @home"),
+            ("WHERE handle BETWEEN @a @m @big amount", "WHERE handle BETWEEN @a @m WHERE amount > 5.5"),
+            ("FILL handle \"@none\"", "FILL handle \"@none\""),
+        ];
+        for (pipeline, expected) in cases {
+            let prepared = prepare_pipeline(&pipeline_tokens(pipeline), &macros(), &params).unwrap();
+            assert_eq!(prepared.join(" "), expected, "{}", pipeline);
+        }
+    }
+
+    #[test]
+    fn missing_and_bad_values_are_errors() {
+        let params = params(&[
+            ("min", ParamKind::Number, "five"),
+            ("region", ParamKind::Text, ""),
+        ]);
+        let cases = [
+            ("LIMIT $limit", CsvqbErrorKind::NoInput, "$limit", (6, 12)),
+            // Set but empty is the same as missing.
+            ("@in_region CSUM amount", CsvqbErrorKind::NoInput, "$region", (15, 22)),
+            ("@big amount", CsvqbErrorKind::TypeMismatch, "$min", (15, 19)),
+            ("WHERE region = x @bigg amount", CsvqbErrorKind::UnknownMacro, "@bigg", (17, 22)),
+        ];
+        for (pipeline, kind, token, (start, end)) in cases {
+            let err = prepare_pipeline(&pipeline_tokens(pipeline), &macros(), &params).unwrap_err();
+            assert_eq!(err.kind, kind, "{}", pipeline);
+            assert_eq!(err.token.as_deref(), Some(token), "{}", pipeline);
+            assert_eq!((err.span.start, err.span.end), (start, end), "{}", pipeline);
+        }
+    }
+}
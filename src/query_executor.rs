@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use rayon::prelude::*;
+use rusqlite::types::Value;
+use rusqlite::{Connection, InterruptHandle};
+use crate::charter_utilities::CsvGrid;
+use crate::cir_adapters::{split_graph_type, sqlite_cir_adapter};
+use crate::csvqb::columns::TypedTable;
+use crate::csvqb::error::{CsvqbError, CsvqbErrorKind};
+use crate::csvqb::lexer::{canonical_pipeline, Span};
+use crate::csvqb::named::{result_table, source_tables, NamedResults};
+use crate::csvqb::nulls::NullTokens;
+use crate::csvqb::parallel::Progress;
+use crate::csvqb::parser::parse_pipeline;
+use crate::csvqb::{csvqb_to_cir, QueryOutput, CIR};
+use crate::db_manager::DbManager;
+use crate::result_cache::{cache_key, forget_table_hashes, grid_hash, query_tables, stored_table_hash, ResultCache};
+
+/// A pipeline of the explorer, the file it belongs to and its position under it.
+pub type PipelineKey = (usize, usize);
+
+pub enum QueryJob {
+    Csvqb { pipeline: Vec<String>, file_indexes: Vec<usize> },
+    /// `params` are bound to the `$name` parameters of the query, the files of
+    /// `import` are copied into their tables before it runs.
+    Sql { query: String, conn_path: PathBuf, params: Vec<(String, Value)>, import: Vec<(String, Arc<CsvGrid>)> },
+}
+
+pub enum QueryOutcome {
+    Csvqb(Result<QueryOutput, CsvqbError>),
+    Sql(Result<Vec<CIR>, String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryState {
+    /// Waiting for the pipelines before it, or running.
+    Pending,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+struct Job {
+    state: QueryState,
+    progress: Arc<Progress>,
+    /// Set while a SQL query runs, so cancelling can interrupt it.
+    interrupt: Arc<Mutex<Option<InterruptHandle>>>,
+}
+
+/// The files as they were when a run started. The grids are shared with the
+/// app, so a snapshot only copies their handles; it is kept between runs for
+/// as long as the files don't change so their hashes are worked out once.
+struct Snapshot {
+    version: u64,
+    files: Arc<Vec<(String, Arc<CsvGrid>)>>,
+    tables: Arc<Vec<Option<Arc<TypedTable>>>>,
+    /// Content hashes of `files`, worked out the first time a run needs them.
+    hashes: Arc<OnceLock<Vec<u64>>>,
+}
+
+/// Runs pipelines on a worker thread so the UI stays responsive. A run goes
+/// through its pipelines in order and sends each outcome back over a channel,
+/// the same way loaded files come back through `file_receiver`. Pipelines that
+/// read named results have to be given after the pipelines naming them.
+pub struct QueryExecutor {
+    sender: Sender<(u64, PipelineKey, QueryOutcome)>,
+    receiver: Receiver<(u64, PipelineKey, QueryOutcome)>,
+    /// Outcomes of earlier runs are dropped when they arrive.
+    run: u64,
+    jobs: BTreeMap<PipelineKey, Job>,
+    snapshot: Option<Snapshot>,
+    cache: Arc<Mutex<ResultCache>>,
+    /// Results of pipelines ending in `=> name`, kept across runs so a
+    /// pipeline can run again without the ones it reads from.
+    named: Arc<Mutex<NamedResults>>,
+    /// Goes up whenever `named` may have changed, so checks of pipelines that
+    /// read named results know to run again.
+    named_version: u64,
+}
+
+impl Default for QueryExecutor {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            run: 0,
+            jobs: BTreeMap::new(),
+            snapshot: None,
+            cache: Arc::new(Mutex::new(ResultCache::default())),
+            named: Arc::new(Mutex::new(NamedResults::new())),
+            named_version: 0,
+        }
+    }
+}
+
+impl QueryExecutor {
+    /// Cancels whatever is still running and starts `jobs`. `version` tells
+    /// whether `files` and `tables` changed since the last run, `null_tokens`
+    /// are the cells csvqb pipelines read as missing values.
+    pub fn run(
+        &mut self,
+        jobs: Vec<(PipelineKey, QueryJob)>,
+        files: &[(String, Arc<CsvGrid>)],
+        tables: Vec<Option<Arc<TypedTable>>>,
+        version: u64,
+        null_tokens: &NullTokens
+    ) {
+        self.cancel_all();
+        self.jobs.clear();
+        self.run += 1;
+
+        if self.snapshot.as_ref().is_none_or(|snapshot| snapshot.version != version) {
+            self.snapshot = Some(Snapshot {
+                version,
+                files: Arc::new(files.to_vec()),
+                tables: Arc::new(tables),
+                hashes: Arc::new(OnceLock::new()),
+            });
+        }
+        let Some(snapshot) = &self.snapshot else { return };
+        let (files, tables, hashes) = (snapshot.files.clone(), snapshot.tables.clone(), snapshot.hashes.clone());
+        let (cache, named) = (self.cache.clone(), self.named.clone());
+        let null_tokens = null_tokens.clone();
+
+        let mut work = Vec::new();
+        for (key, job) in jobs {
+            let state = Job {
+                state: QueryState::Pending,
+                progress: Arc::new(Progress::default()),
+                interrupt: Arc::new(Mutex::new(None)),
+            };
+            work.push((key, job, state.progress.clone(), state.interrupt.clone()));
+            self.jobs.insert(key, state);
+        }
+
+        let (run, sender) = (self.run, self.sender.clone());
+        thread::spawn(move || {
+            let tables: Vec<Option<&TypedTable>> = tables.iter().map(|table| table.as_deref()).collect();
+            for (key, job, progress, interrupt) in work {
+                if progress.is_cancelled() {
+                    continue;
+                }
+                let outcome = match job {
+                    QueryJob::Csvqb { pipeline, file_indexes } => {
+                        let parsed = parse_pipeline(&pipeline.join(" ")).ok();
+                        // A pipeline with FROM reads its own tables, which are hashed as they are.
+                        let inputs = match parsed.as_ref().filter(|parsed| !parsed.sources.is_empty()) {
+                            Some(parsed) => match source_tables(&parsed.sources, &files, &named.lock().unwrap(), false) {
+                                Ok(inputs) => Some(inputs),
+                                Err(err) => {
+                                    if sender.send((run, key, QueryOutcome::Csvqb(Err(err)))).is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            },
+                            None => None,
+                        };
+                        let (file_indexes, sources): (Vec<usize>, Vec<(String, u64)>) = match &inputs {
+                            Some(inputs) => (
+                                (0..inputs.len()).collect(),
+                                inputs.iter().map(|(name, grid)| (name.clone(), grid_hash(grid))).collect(),
+                            ),
+                            None => {
+                                let hashes = hashes.get_or_init(|| files.par_iter().map(|(_, grid)| grid_hash(grid)).collect());
+                                let sources = file_indexes.iter()
+                                    .filter_map(|&idx| Some((files.get(idx)?.0.clone(), hashes[idx])))
+                                    .collect();
+                                (file_indexes, sources)
+                            }
+                        };
+                        let (files, tables): (&Vec<_>, &[_]) = match &inputs {
+                            Some(inputs) => (inputs, &[]),
+                            None => (&files, &tables),
+                        };
+                        // The same pipeline reads other values as null once the tokens change.
+                        let nulls = null_tokens.describe(sources.iter().map(|(name, _)| name.as_str()));
+                        let cache_key = cache_key("csvqb", &format!("{}\n{}", canonical_pipeline(&pipeline), nulls), &sources);
+
+                        let cached = cache.lock().unwrap().get(&cache_key);
+                        let result = match cached {
+                            Some(output) => Ok(output),
+                            None => csvqb_to_cir(&pipeline, &file_indexes, files, tables, &null_tokens, &progress).inspect(|output| {
+                                let names = sources.into_iter().map(|(name, _)| name).collect();
+                                cache.lock().unwrap().insert(cache_key, names, output.clone());
+                            }),
+                        };
+                        if let (Ok(output), Some(name)) = (&result, parsed.and_then(|parsed| parsed.name)) {
+                            match result_table(&output.results) {
+                                Some(table) => named.lock().unwrap().insert(name.name, Arc::new(table)),
+                                None => named.lock().unwrap().remove(&name.name),
+                            };
+                        }
+                        QueryOutcome::Csvqb(result)
+                    }
+                    QueryJob::Sql { query, conn_path, params, import } => match Connection::open(conn_path) {
+                        Ok(mut conn) => match DbManager::import_all_csvs(&mut conn, &import) {
+                            Ok(()) => QueryOutcome::Sql(sql_results(&conn, &query, &params, &cache, &progress, &interrupt)),
+                            // The pipeline has no input without its tables, reported like its other errors.
+                            Err(err) => QueryOutcome::Csvqb(Err(CsvqbError::new(
+                                CsvqbErrorKind::NoInput,
+                                format!("the selected files couldn't be copied into SQLite: {}", err),
+                                Span::default(),
+                            ))),
+                        },
+                        Err(err) => QueryOutcome::Sql(Err(err.to_string())),
+                    },
+                };
+                if sender.send((run, key, outcome)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Results of the pipelines that named theirs, as of the last one that finished.
+    pub fn named_results(&self) -> MutexGuard<'_, NamedResults> {
+        self.named.lock().unwrap()
+    }
+
+    /// Forgets every named result, for when another session is opened.
+    pub fn clear_named_results(&mut self) {
+        self.named.lock().unwrap().clear();
+        self.named_version += 1;
+    }
+
+    pub fn named_version(&self) -> u64 {
+        self.named_version
+    }
+
+    /// Keeps cached results in the database at `path` too, or only in memory for `None`.
+    pub fn persist_results(&self, path: Option<PathBuf>) {
+        self.cache.lock().unwrap().persist_to(path);
+    }
+
+    /// Forgets cached results of the file or table called `source`.
+    pub fn invalidate(&self, source: &str) {
+        self.cache.lock().unwrap().invalidate(source);
+    }
+
+    pub fn cancel(&mut self, key: PipelineKey) {
+        if let Some(job) = self.jobs.get_mut(&key) {
+            if job.state == QueryState::Pending {
+                job.progress.cancel();
+                if let Some(interrupt) = job.interrupt.lock().unwrap().as_ref() {
+                    interrupt.interrupt();
+                }
+                job.state = QueryState::Cancelled;
+            }
+        }
+    }
+
+    pub fn cancel_all(&mut self) {
+        let keys: Vec<PipelineKey> = self.jobs.keys().copied().collect();
+        for key in keys {
+            self.cancel(key);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.jobs.values().any(|job| job.state == QueryState::Pending)
+    }
+
+    /// State of a pipeline of the current run and how far it got.
+    pub fn state(&self, key: PipelineKey) -> Option<(&QueryState, &Progress)> {
+        self.jobs.get(&key).map(|job| (&job.state, job.progress.as_ref()))
+    }
+
+    /// Outcomes that arrived since the last call, in the order the pipelines
+    /// were given to `run`. Cancelled pipelines are left out.
+    pub fn poll(&mut self) -> Vec<(PipelineKey, QueryOutcome)> {
+        let mut outcomes = Vec::new();
+        while let Ok((run, key, outcome)) = self.receiver.try_recv() {
+            let Some(job) = self.jobs.get_mut(&key).filter(|job| run == self.run && job.state == QueryState::Pending) else {
+                continue;
+            };
+            if let QueryOutcome::Csvqb(Ok(_)) = &outcome {
+                self.named_version += 1;
+            }
+            job.state = match &outcome {
+                QueryOutcome::Csvqb(Err(err)) => QueryState::Failed(err.to_string()),
+                QueryOutcome::Sql(Err(err)) => QueryState::Failed(err.clone()),
+                _ => QueryState::Done,
+            };
+            outcomes.push((key, outcome));
+        }
+        outcomes
+    }
+}
+
+/// Runs a SQL query on `conn`, or answers it from `cache` while the tables it
+/// reads are unchanged.
+fn sql_results(
+    conn: &Connection,
+    query: &str,
+    params: &[(String, Value)],
+    cache: &Mutex<ResultCache>,
+    progress: &Progress,
+    interrupt: &Mutex<Option<InterruptHandle>>
+) -> Result<Vec<CIR>, String> {
+    // A query that writes is never answered from the cache, and the
+    // tables it mentions are hashed again afterwards.
+    let writes = conn.prepare(split_graph_type(query).1).is_ok_and(|stmt| !stmt.readonly());
+    // Tables that can't be read are left out of the key, the query then reports the error.
+    let sources: Vec<(String, u64)> = query_tables(conn, query).unwrap_or_default().into_iter()
+        .filter_map(|table| stored_table_hash(conn, &table).ok().map(|hash| (table, hash)))
+        .collect();
+    let bound = params.iter().map(|(name, value)| format!("${}={:?}", name, value)).collect::<Vec<_>>();
+    let query_text = query.split_whitespace().chain(bound.iter().map(String::as_str)).collect::<Vec<_>>().join(" ");
+    let key = cache_key("sqlite", &query_text, &sources);
+
+    let cached = if writes { None } else { cache.lock().unwrap().get(&key) };
+    match cached {
+        Some(output) => Ok(output.results),
+        None => {
+            *interrupt.lock().unwrap() = Some(conn.get_interrupt_handle());
+            // Cancelled between taking the handle and getting here.
+            let result = if progress.is_cancelled() {
+                Err("the query was cancelled".to_string())
+            } else {
+                sqlite_cir_adapter(conn, query, params)
+            };
+            *interrupt.lock().unwrap() = None;
+            let names: Vec<String> = sources.into_iter().map(|(name, _)| name).collect();
+            if writes {
+                // A hash left from before the write would answer later reads with old results.
+                result.and_then(|results| forget_table_hashes(conn, &names)
+                    .map(|()| results)
+                    .map_err(|err| format!("the query ran, but the cache couldn't tell its tables changed: {}", err)))
+            } else {
+                result.inspect(|results| {
+                    let output = QueryOutput { results: results.clone(), skipped: Vec::new(), nulls: Vec::new() };
+                    cache.lock().unwrap().insert(key, names, output);
+                })
+            }
+        }
+    }
+}
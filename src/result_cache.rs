@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use itertools::Itertools;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use crate::charter_utilities::CsvGrid;
+use crate::csvqb::{QueryOutput, CIR};
+
+/// Most entries kept in memory, the least recently used ones go first.
+const MAX_ENTRIES: usize = 64;
+
+/// Most entries kept in the `query_cache` table of the database.
+const MAX_STORED: usize = 256;
+
+/// Results of pipelines that ran before, so running one again on the same data
+/// doesn't evaluate it again. An entry is found by `cache_key`, which changes
+/// whenever the pipeline or the content of one of its inputs does.
+#[derive(Default)]
+pub struct ResultCache {
+    entries: HashMap<String, CacheEntry>,
+    /// Goes up on every hit and insert, so entries know when they were last used.
+    clock: u64,
+    /// Database the entries are also written to, so they outlive the app.
+    persist_path: Option<PathBuf>,
+}
+
+struct CacheEntry {
+    /// Files or tables the results were computed from.
+    sources: Vec<String>,
+    output: QueryOutput,
+    used: u64,
+}
+
+/// Files or tables an entry was computed from and its results.
+type StoredEntry = (Vec<String>, QueryOutput);
+
+impl ResultCache {
+    /// Keeps entries in the SQLite database at `path` as well, or only in memory for `None`.
+    pub fn persist_to(&mut self, path: Option<PathBuf>) {
+        self.persist_path = path;
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<QueryOutput> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.used = self.clock;
+            return Some(entry.output.clone());
+        }
+        let path = self.persist_path.as_ref()?;
+        let (sources, output) = load_entry(path, key).ok().flatten()?;
+        self.keep(key.to_string(), sources, output.clone());
+        Some(output)
+    }
+
+    pub fn insert(&mut self, key: String, sources: Vec<String>, output: QueryOutput) {
+        self.clock += 1;
+        if let Some(path) = &self.persist_path {
+            if let Err(err) = store_entry(path, &key, &sources, &output) {
+                eprintln!("failed to store cached results: {}", err);
+            }
+        }
+        self.keep(key, sources, output);
+    }
+
+    /// Adds an entry to the ones in memory, dropping the least recently used
+    /// one when there are too many.
+    fn keep(&mut self, key: String, sources: Vec<String>, output: QueryOutput) {
+        self.entries.insert(key, CacheEntry { sources, output, used: self.clock });
+        if self.entries.len() > MAX_ENTRIES {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.used).map(|(key, _)| key.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drops every entry computed from `source`, a file name or a table name.
+    pub fn invalidate(&mut self, source: &str) {
+        self.entries.retain(|_, entry| !entry.sources.iter().any(|s| s == source));
+        if let Some(path) = &self.persist_path {
+            if let Err(err) = remove_entries(path, source) {
+                eprintln!("failed to remove cached results: {}", err);
+            }
+        }
+    }
+}
+
+/// FNV-1a, unlike `DefaultHasher` it hashes the same way in every build, so
+/// persisted keys stay valid after an update.
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for ContentHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+pub fn grid_hash(grid: &CsvGrid) -> u64 {
+    let mut hasher = ContentHasher::default();
+    grid.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash of every row of a SQLite table, in the order SQLite returns them.
+pub fn table_hash(conn: &Connection, table: &str) -> rusqlite::Result<u64> {
+    let mut hasher = ContentHasher::default();
+    let mut stmt = conn.prepare(&format!("SELECT * FROM \"{}\"", table.replace('"', "\"\"")))?;
+    let columns = stmt.column_count();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        for i in 0..columns {
+            match row.get_ref(i)? {
+                ValueRef::Null => 0u8.hash(&mut hasher),
+                ValueRef::Integer(int) => (1u8, int).hash(&mut hasher),
+                ValueRef::Real(real) => (2u8, real.to_bits()).hash(&mut hasher),
+                ValueRef::Text(text) => (3u8, text).hash(&mut hasher),
+                ValueRef::Blob(blob) => (4u8, blob).hash(&mut hasher),
+            }
+        }
+    }
+    Ok(hasher.finish())
+}
+
+fn create_hashes_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_hashes (
+            name TEXT PRIMARY KEY,
+            hash TEXT
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// `table_hash` of a table as it was stored the first time it was asked for,
+/// so keying a query doesn't read its tables every time. The app forgets the
+/// stored hash whenever it writes to the table; a table changed by another
+/// program keeps its old hash until then.
+pub fn stored_table_hash(conn: &Connection, table: &str) -> rusqlite::Result<u64> {
+    create_hashes_table(conn)?;
+    let stored: Option<String> = conn.query_row("SELECT hash FROM table_hashes WHERE name = ?1", [table], |row| row.get(0)).optional()?;
+    if let Some(hash) = stored.and_then(|hash| u64::from_str_radix(&hash, 16).ok()) {
+        return Ok(hash);
+    }
+    let hash = table_hash(conn, table)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO table_hashes (name, hash) VALUES (?1, ?2)",
+        params![table, format!("{:016x}", hash)],
+    )?;
+    Ok(hash)
+}
+
+/// Forgets the stored hashes of tables that were just written to, they are
+/// worked out again the next time a query reads them.
+pub fn forget_table_hashes(conn: &Connection, tables: &[String]) -> rusqlite::Result<()> {
+    create_hashes_table(conn)?;
+    for table in tables {
+        conn.execute("DELETE FROM table_hashes WHERE name = ?1", [table])?;
+    }
+    Ok(())
+}
+
+/// Tables of the database that `query` mentions by name, the ones its results depend on.
+pub fn query_tables(conn: &Connection, query: &str) -> rusqlite::Result<Vec<String>> {
+    let words: Vec<String> = query
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")?;
+    let tables = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .filter_map(Result::ok)
+        .filter(|table| words.contains(&table.to_lowercase()))
+        .collect();
+    Ok(tables)
+}
+
+/// Key of a pipeline run on `sources`, given as names with the hash of their content.
+pub fn cache_key(mode: &str, pipeline: &str, sources: &[(String, u64)]) -> String {
+    let sources = sources.iter()
+        .map(|(name, hash)| format!("{}:{:016x}", name, hash))
+        .join(",");
+    format!("{}\n{}\n{}", mode, pipeline, sources)
+}
+
+fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS query_cache (
+            key TEXT PRIMARY KEY,
+            sources TEXT,
+            results TEXT,
+            used INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn load_entry(path: &Path, key: &str) -> Result<Option<StoredEntry>, Box<dyn Error>> {
+    let conn = Connection::open(path)?;
+    create_table(&conn)?;
+    let row: Option<(String, String)> = conn.query_row(
+        "SELECT sources, results FROM query_cache WHERE key = ?1",
+        [key],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+
+    let Some((sources, results)) = row else { return Ok(None) };
+    conn.execute(
+        "UPDATE query_cache SET used = (SELECT COALESCE(MAX(used), 0) + 1 FROM query_cache) WHERE key = ?1",
+        [key],
+    )?;
+    let sources: Vec<String> = serde_json::from_str(&sources)?;
+    Ok(output_from_json(&serde_json::from_str(&results)?).map(|output| (sources, output)))
+}
+
+/// Stores an entry as the most recently used one, then drops the least
+/// recently used entries beyond `MAX_STORED`.
+fn store_entry(path: &Path, key: &str, sources: &[String], output: &QueryOutput) -> Result<(), Box<dyn Error>> {
+    let conn = Connection::open(path)?;
+    create_table(&conn)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO query_cache (key, sources, results, used)
+         VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(used), 0) + 1 FROM query_cache))",
+        params![key, serde_json::to_string(sources)?, output_to_json(output).to_string()],
+    )?;
+    conn.execute(
+        "DELETE FROM query_cache WHERE key NOT IN (SELECT key FROM query_cache ORDER BY used DESC LIMIT ?1)",
+        [MAX_STORED as i64],
+    )?;
+    Ok(())
+}
+
+fn remove_entries(path: &Path, source: &str) -> Result<(), Box<dyn Error>> {
+    let conn = Connection::open(path)?;
+    create_table(&conn)?;
+    conn.execute(
+        "DELETE FROM query_cache WHERE EXISTS (SELECT 1 FROM json_each(sources) WHERE value = ?1)",
+        [source],
+    )?;
+    Ok(())
+}
+
+/// JSON has no NaN or infinity, numbers that aren't finite are written as text.
+fn output_to_json(output: &QueryOutput) -> Value {
+    let results: Vec<Value> = output.results.iter()
+        .map(|cir| match cir {
+            CIR::Bool(bool) => json!({ "bool": bool }),
+            CIR::Number(num) if !num.is_finite() => json!({ "number": num.to_string() }),
+            CIR::Number(num) => json!({ "number": num }),
+            CIR::Text(text) => json!({ "text": text }),
+            CIR::Field(field) => json!({ "field": field }),
+            CIR::QueryResult(grid) => json!({ "query_result": grid }),
+            CIR::Pivot(grid) => json!({ "pivot": grid }),
+            CIR::Histogram(grid) => json!({ "histogram": grid }),
+            CIR::Relation { points, stats } => json!({ "relation": { "points": points, "stats": stats } }),
+        })
+        .collect();
+    json!({ "results": results, "skipped": output.skipped, "nulls": output.nulls })
+}
+
+fn output_from_json(value: &Value) -> Option<QueryOutput> {
+    let grid = |value: &Value| serde_json::from_value::<Vec<Vec<String>>>(value.clone()).ok();
+    let results = value.get("results")?.as_array()?.iter()
+        .map(|cir| {
+            let (variant, value) = cir.as_object()?.iter().next()?;
+            match variant.as_str() {
+                "bool" => value.as_bool().map(CIR::Bool),
+                "number" => value.as_f64().or_else(|| value.as_str()?.parse().ok()).map(CIR::Number),
+                "text" => value.as_str().map(|text| CIR::Text(text.to_string())),
+                "field" => value.as_str().map(|field| CIR::Field(field.to_string())),
+                "query_result" => grid(value).map(CIR::QueryResult),
+                "pivot" => grid(value).map(CIR::Pivot),
+                "histogram" => grid(value).map(CIR::Histogram),
+                "relation" => Some(CIR::Relation { points: grid(value.get("points")?)?, stats: grid(value.get("stats")?)? }),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<CIR>>>()?;
+    let skipped = serde_json::from_value(value.get("skipped")?.clone()).ok()?;
+    let nulls = serde_json::from_value(value.get("nulls")?.clone()).ok()?;
+    Some(QueryOutput { results, skipped, nulls })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_that_are_not_finite_survive_storing() {
+        let output = QueryOutput {
+            results: [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.5].into_iter().map(CIR::Number).collect(),
+            skipped: vec![("amount".to_string(), 2)],
+            nulls: vec![("amount".to_string(), 1, 10)],
+        };
+        let stored = output_from_json(&serde_json::from_str(&output_to_json(&output).to_string()).unwrap()).unwrap();
+
+        let numbers: Vec<String> = stored.results.iter()
+            .map(|cir| match cir {
+                CIR::Number(num) => num.to_string(),
+                other => format!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(numbers, vec!["NaN", "inf", "-inf", "-0.5"]);
+        assert_eq!(stored.skipped, output.skipped);
+        assert_eq!(stored.nulls, output.nulls);
+    }
+}
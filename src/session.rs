@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs::{self, File};
 use std::io::{self, Write, BufRead, BufReader, ErrorKind};
@@ -6,10 +5,12 @@ use std::path::Path;
 use std::str::FromStr;
 use std::sync::mpsc;
 use std::thread;
-use itertools::Itertools;
 use rusqlite::{params, Connection};
 use serde_json::Value;
 use crate::charter_utilities::{csv_parser, CsvGrid};
+use crate::csvqb::macros::Macro;
+use crate::csvqb::nulls::NullTokens;
+use crate::pipeline_params::{params_from_json, params_to_json, Params};
 use crate::db_manager::DatabaseType;
 
 #[derive(Debug, Clone)]
@@ -19,6 +20,12 @@ pub struct Session {
     pub(crate) pipelines: Vec<Vec<String>>,
     pub(crate) selected_files: Vec<usize>,
     pub(crate) query_mode: DatabaseType,
+    /// Names the pipelines give their results with `=>`, in pipeline order.
+    pub(crate) names: Vec<String>,
+    /// Values of the `$name` placeholders of the pipelines.
+    pub(crate) params: Params,
+    /// Cells the session's files use for a missing value.
+    pub(crate) null_tokens: NullTokens,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +34,7 @@ pub struct SessionSummary {
     pub(crate) file_count: usize,
     pub(crate) pipeline_count: usize,
     pub(crate) query_mode: String,
+    pub(crate) names: Vec<String>,
 }
 
 impl Session {
@@ -37,6 +45,9 @@ impl Session {
             pipelines,
             selected_files: vec![],
             query_mode: DatabaseType::CsvQB,
+            names: vec![],
+            params: Params::new(),
+            null_tokens: NullTokens::default(),
         }
     }
 
@@ -69,7 +80,10 @@ pub fn save_session_to_database(mut conn: Connection, sessions: Vec<Session>) ->
             files TEXT,
             pipelines TEXT,
             selected_files TEXT,
-            query_mode TEXT
+            query_mode TEXT,
+            names TEXT,
+            params TEXT,
+            null_tokens TEXT
         )",
         [],
     )?;
@@ -81,16 +95,22 @@ pub fn save_session_to_database(mut conn: Connection, sessions: Vec<Session>) ->
         let pipelines_json = serde_json::to_string(&session.pipelines)?;
         let selected_files_json = serde_json::to_string(&session.selected_files)?;
         let query_mode_str = format!("{:?}", session.query_mode);
+        let names_json = serde_json::to_string(&session.names)?;
+        let params_json = params_to_json(&session.params).to_string();
+        let null_tokens_json = session.null_tokens.to_json().to_string();
         println!("{:?}", pipelines_json);
         transaction.execute(
-            "INSERT OR REPLACE INTO sessions (name, files, pipelines, selected_files, query_mode)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT OR REPLACE INTO sessions (name, files, pipelines, selected_files, query_mode, names, params, null_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![
                 session.name,
                 files_json,
                 pipelines_json,
                 selected_files_json,
                 query_mode_str,
+                names_json,
+                params_json,
+                null_tokens_json,
             ],
         )?;
     }
@@ -100,28 +120,61 @@ pub fn save_session_to_database(mut conn: Connection, sessions: Vec<Session>) ->
     Ok(())
 }
 
+/// Creates the sessions table of a database the app just opened, or adds the
+/// columns it is missing when it was made before pipelines could name their
+/// results, take parameters or files had null tokens. Runs once per database,
+/// the session functions below expect every column to be there.
+pub fn migrate_sessions_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            name TEXT PRIMARY KEY,
+            files TEXT,
+            pipelines TEXT,
+            selected_files TEXT,
+            query_mode TEXT,
+            names TEXT,
+            params TEXT,
+            null_tokens TEXT
+        )",
+        [],
+    )?;
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('sessions')")?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    for column in ["names", "params", "null_tokens"] {
+        if !columns.iter().any(|existing| existing == column) {
+            conn.execute(&format!("ALTER TABLE sessions ADD COLUMN {} TEXT", column), [])?;
+        }
+    }
+    Ok(())
+}
+
+/// Names a session stores, sessions saved before there were any have none.
+fn names_from_json(names_json: Option<String>) -> Vec<String> {
+    names_json.and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default()
+}
+
 pub fn retrieve_session_list(conn: &Connection) -> Result<Vec<SessionSummary>, Box<dyn Error>> {
-    let mut stmt = conn.prepare(
-        "SELECT name, files, pipelines, query_mode FROM sessions"
-    ).or_else(|_| {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                    name TEXT PRIMARY KEY,
-                    files TEXT,
-                    pipelines TEXT,
-                    selected_files TEXT,
-                    query_mode TEXT
-                )",
-            [],
-        )?;
-        conn.prepare("SELECT name, files, pipelines, query_mode FROM sessions")
-    })?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+                name TEXT PRIMARY KEY,
+                files TEXT,
+                pipelines TEXT,
+                selected_files TEXT,
+                query_mode TEXT,
+                names TEXT,
+                params TEXT,
+                null_tokens TEXT
+            )",
+        [],
+    )?;
+    let mut stmt = conn.prepare("SELECT name, files, pipelines, query_mode, names FROM sessions")?;
 
     let session_iter = stmt.query_map([], |row| {
         let name: String = row.get(0)?;
         let files_json: String = row.get(1)?;
         let pipelines_json: String = row.get(2)?;
         let query_mode: String = row.get(3)?;
+        let names_json: Option<String> = row.get(4)?;
 
         let files: Value = serde_json::from_str(&files_json)
             .unwrap_or(Value::Array(vec![]));
@@ -145,6 +198,7 @@ pub fn retrieve_session_list(conn: &Connection) -> Result<Vec<SessionSummary>, B
             file_count,
             pipeline_count,
             query_mode,
+            names: names_from_json(names_json),
         })
     })?;
 
@@ -158,7 +212,7 @@ pub fn retrieve_session_list(conn: &Connection) -> Result<Vec<SessionSummary>, B
 
 pub fn load_sessions_from_db(conn: &Connection) -> Result<Vec<Session>, Box<dyn Error>> {
     let mut stmt = conn.prepare(
-        "SELECT name, files, pipelines, selected_files, query_mode FROM sessions"
+        "SELECT name, files, pipelines, selected_files, query_mode, names, params, null_tokens FROM sessions"
     )?;
 
     let mut sessions = Vec::new();
@@ -169,6 +223,9 @@ pub fn load_sessions_from_db(conn: &Connection) -> Result<Vec<Session>, Box<dyn
         let pipelines_json: String = row.get(2)?;
         let selected_files_json: String = row.get(3)?;
         let query_mode_str: String = row.get(4)?;
+        let names_json: Option<String> = row.get(5)?;
+        let params_json: Option<String> = row.get(6)?;
+        let null_tokens_json: Option<String> = row.get(7)?;
 
         let files: Vec<String> = serde_json::from_str(&files_json)
             .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
@@ -231,6 +288,15 @@ pub fn load_sessions_from_db(conn: &Connection) -> Result<Vec<Session>, Box<dyn
             pipelines,
             selected_files,
             query_mode,
+            names: names_from_json(names_json),
+            params: params_json
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .map(|json| params_from_json(&json))
+                .unwrap_or_default(),
+            null_tokens: null_tokens_json
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .map(|json| NullTokens::from_json(&json))
+                .unwrap_or_default(),
         })
     })?;
 
@@ -241,46 +307,48 @@ pub fn load_sessions_from_db(conn: &Connection) -> Result<Vec<Session>, Box<dyn
     Ok(sessions)
 }
 
-pub fn update_current_session(
-    csv_files: &Vec<(String, CsvGrid)>,
-    csvqb_pipelines: &mut Vec<Vec<(usize, Vec<String>)>>,
-    sessions: &mut Vec<Session>,
-    multi_pipeline_tracker: &mut HashMap<usize, Vec<usize>>,
-    current_session: usize,
-    query_mode: &DatabaseType,
-    conn: Connection
-) {
-    let mut file_paths: Vec<String> = vec![];
-    let mut pipelines: Vec<Vec<String>> = vec![];
-
-    for (path, _) in csv_files.iter() {
-        file_paths.push(path.to_string());
-    }
-
-    for (_index, pipeline) in csvqb_pipelines.iter_mut().enumerate() {
-        for (pipeline_number, query_string) in pipeline.iter_mut() {
-            let mut pipeline_collection = vec![];
-            let pipeline_str = format!("{} {}", pipeline_number.to_string(), query_string.join(" "));
-            pipeline_collection.push(pipeline_str);
-            pipelines.push(pipeline_collection);
-        }
-    }
+fn create_macros_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS macros (
+            name TEXT PRIMARY KEY,
+            params TEXT,
+            body TEXT
+        )",
+        [],
+    )?;
+    Ok(())
+}
 
-    let ssi = current_session;
-    let selected_files = multi_pipeline_tracker.keys().copied().sorted().collect();
+/// Saves a csvqb macro, replacing the one with its name if there is one.
+pub fn save_macro_to_db(conn: &Connection, csvqb_macro: &Macro) -> Result<(), Box<dyn Error>> {
+    create_macros_table(conn)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO macros (name, params, body) VALUES (?1, ?2, ?3)",
+        params![csvqb_macro.name, serde_json::to_string(&csvqb_macro.params)?, csvqb_macro.body],
+    )?;
+    Ok(())
+}
 
-    let session = Session {
-        name: sessions[ssi].name.to_string(),
-        files: file_paths,
-        pipelines,
-        selected_files,
-        query_mode: query_mode.clone(),
-    };
+pub fn delete_macro_from_db(conn: &Connection, name: &str) -> Result<(), Box<dyn Error>> {
+    create_macros_table(conn)?;
+    conn.execute("DELETE FROM macros WHERE name = ?1", [name])?;
+    Ok(())
+}
 
-    if let Err(err) = save_session_to_database(conn, vec![session]) {
-        println!("{}", format!("Error saving session to sql lite db: {}", err));
-    }
+pub fn load_macros_from_db(conn: &Connection) -> Result<Vec<Macro>, Box<dyn Error>> {
+    create_macros_table(conn)?;
+    let mut stmt = conn.prepare("SELECT name, params, body FROM macros ORDER BY name")?;
+    let rows = stmt.query_map([], |row| {
+        let params_json: String = row.get(1)?;
+        Ok(Macro {
+            name: row.get(0)?,
+            params: serde_json::from_str(&params_json).unwrap_or_default(),
+            body: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
 }
+
 /// Deprecated: May be used in future redundancy
 pub fn save_session(
     session_name: String,
@@ -372,7 +440,10 @@ pub fn load_sessions_from_directory() -> io::Result<Vec<Session>> {
                     files: csv_files,
                     pipelines: csvqb_pipeline,
                     selected_files,
-                    query_mode 
+                    query_mode,
+                    names: vec![],
+                    params: Params::new(),
+                    null_tokens: NullTokens::default(),
                 });
             }
         }